@@ -0,0 +1,61 @@
+#![feature(test)]
+extern crate chrono;
+extern crate hypercube;
+extern crate xpz_program_interface;
+extern crate test;
+
+use chrono::prelude::Utc;
+use hypercube::fin_plan_program::FinPlanState;
+use hypercube::fin_plan_transaction::FinPlanTransaction;
+use hypercube::hash::Hash;
+use hypercube::signature::{Keypair, KeypairUtil};
+use hypercube::transaction::Transaction;
+use xpz_program_interface::account::Account;
+use test::Bencher;
+
+// Contracts between unrelated parties, so every transaction can run concurrently.
+fn disjoint_contracts(count: usize) -> (Vec<Transaction>, Vec<Account>) {
+    let dt = Utc::now();
+    let mut txs = vec![];
+    let mut accounts = vec![];
+    for _ in 0..count {
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        txs.push(Transaction::fin_plan_new_on_date(
+            &from,
+            to,
+            contract,
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        ));
+        accounts.push(Account::new(1, 0, FinPlanState::id()));
+        accounts.push(Account::new(0, 512, FinPlanState::id()));
+        accounts.push(Account::new(0, 0, FinPlanState::id()));
+    }
+    (txs, accounts)
+}
+
+#[bench]
+fn bench_process_transactions_serial(bencher: &mut Bencher) {
+    let (txs, accounts) = disjoint_contracts(1024);
+    bencher.iter(|| {
+        let mut accounts = accounts.clone();
+        for (tx, tx_accounts) in txs.iter().zip(accounts.chunks_mut(3)) {
+            assert!(FinPlanState::process_transaction(tx, tx_accounts).is_ok());
+        }
+    })
+}
+
+#[bench]
+fn bench_process_transactions_parallel(bencher: &mut Bencher) {
+    let (txs, accounts) = disjoint_contracts(1024);
+    bencher.iter(|| {
+        let mut accounts = accounts.clone();
+        let results = FinPlanState::process_transactions_parallel(&txs, &mut accounts);
+        assert!(results.iter().all(Result::is_ok));
+    })
+}
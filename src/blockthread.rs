@@ -13,7 +13,7 @@
 //!
 //! TransactionProcessor needs to provide an interface for us to query the stake weight
 use bincode::{deserialize, serialize};
-use fin_plan_instruction::Vote;
+use vote_program::Vote;
 use choose_gossip_peer_strategy::{ChooseGossipPeerStrategy, ChooseWeightedPeerStrategy};
 use counter::Counter;
 use hash::Hash;
@@ -231,6 +231,62 @@ pub struct BlockThread {
     pub leader_rotation_interval: u64,
 }
 
+/// A copy of the leader schedule for a window of heights, taken at one moment, so a
+/// decision that reads it several times can't see it change partway through.
+#[derive(Debug, Clone)]
+pub struct LeaderSchedule {
+    my_id: Pubkey,
+    default_leader_id: Pubkey,
+    /// The window covers `len` heights from `start_height` on
+    start_height: u64,
+    len: u64,
+    scheduled_leaders: HashMap<u64, Pubkey>,
+}
+
+impl LeaderSchedule {
+    pub fn my_id(&self) -> Pubkey {
+        self.my_id
+    }
+
+    fn contains(&self, entry_height: u64) -> bool {
+        entry_height >= self.start_height && entry_height - self.start_height < self.len
+    }
+
+    /// The first height past the window, or `None` if the window runs to the last height.
+    pub fn next_height(&self) -> Option<u64> {
+        self.start_height.checked_add(self.len)
+    }
+
+    /// Add the heights read into `later`, which has to start at `next_height()`. Heights
+    /// already in the window keep what they were read as.
+    pub fn extend(&mut self, later: LeaderSchedule) {
+        debug_assert_eq!(self.next_height(), Some(later.start_height));
+        self.len = self.len.saturating_add(later.len);
+        self.scheduled_leaders.extend(later.scheduled_leaders);
+    }
+
+    /// Heights outside the window have no leader.
+    pub fn get_scheduled_leader(&self, entry_height: u64) -> Option<Pubkey> {
+        if !self.contains(entry_height) {
+            return None;
+        }
+        match self.scheduled_leaders.get(&entry_height) {
+            Some(x) => Some(*x),
+            None => Some(self.default_leader_id),
+        }
+    }
+
+    /// Heights past `u64::max_value()` have no leader.
+    pub fn get_scheduled_leaders(&self, start_height: u64, count: u64) -> Vec<Option<Pubkey>> {
+        (0..count)
+            .map(|i| {
+                start_height
+                    .checked_add(i)
+                    .and_then(|entry_height| self.get_scheduled_leader(entry_height))
+            }).collect()
+    }
+}
+
 // TODO These messages should be signed, and go through the gpu pipeline for spam filtering
 #[derive(Serialize, Deserialize, Debug)]
 enum Protocol {
@@ -332,6 +388,39 @@ impl BlockThread {
         }
     }
 
+    /// Snapshot the leader schedule for `count` heights from `start_height` on.
+    pub fn leader_schedule(&self, start_height: u64, count: u64) -> LeaderSchedule {
+        // Walk whichever of the window and the schedule is smaller
+        let scheduled_leaders = if count < self.scheduled_leaders.len() as u64 {
+            (0..count)
+                .filter_map(|i| start_height.checked_add(i))
+                .filter_map(|height| {
+                    self.scheduled_leaders
+                        .get(&height)
+                        .map(|leader_id| (height, *leader_id))
+                }).collect()
+        } else {
+            self.scheduled_leaders
+                .iter()
+                .filter(|(height, _)| **height >= start_height && **height - start_height < count)
+                .map(|(height, leader_id)| (*height, *leader_id))
+                .collect()
+        };
+        LeaderSchedule {
+            my_id: self.my_data().id,
+            default_leader_id: self.my_data().leader_id,
+            start_height,
+            len: count,
+            scheduled_leaders,
+        }
+    }
+
+    /// Forget the leaders scheduled for heights below `entry_height`, once nothing can
+    /// still ask about them.
+    pub fn prune_scheduled_leaders(&mut self, entry_height: u64) {
+        self.scheduled_leaders.retain(|height, _| *height >= entry_height);
+    }
+
     pub fn set_leader_rotation_interval(&mut self, leader_rotation_interval: u64) {
         self.leader_rotation_interval = leader_rotation_interval;
     }
@@ -1375,7 +1464,7 @@ fn report_time_spent(label: &str, time: &Duration, extra: &str) {
 
 #[cfg(test)]
 mod tests {
-    use fin_plan_instruction::Vote;
+    use vote_program::Vote;
     use blockthread::{
         BlockThread, BlockThreadError, Node, NodeInfo, Protocol, FULLNODE_PORT_RANGE, GOSSIP_PURGE_MILLIS,
         GOSSIP_SLEEP_MILLIS, MIN_TABLE_SIZE,
@@ -1850,6 +1939,76 @@ mod tests {
         assert_eq!(blockthread.my_data().leader_id, leader1.id);
     }
 
+    #[test]
+    fn test_get_scheduled_leaders() {
+        let me = NodeInfo::new_with_socketaddr(&socketaddr!("127.0.0.1:1234"));
+        let mut blockthread = BlockThread::new(me).expect("BlockThread::new");
+        let leader = Keypair::new().pubkey();
+        blockthread.set_scheduled_leader(3, leader);
+        blockthread.set_scheduled_leader(5, leader);
+        let leader_schedule = blockthread.leader_schedule(2, 5);
+        let leaders = leader_schedule.get_scheduled_leaders(2, 5);
+        assert_eq!(leaders.len(), 5);
+        assert_eq!(leaders[1], Some(leader));
+        for (entry_height, scheduled_leader) in (2..7).zip(leaders) {
+            assert_eq!(
+                scheduled_leader,
+                blockthread.get_scheduled_leader(entry_height)
+            );
+        }
+        assert!(leader_schedule.get_scheduled_leaders(2, 0).is_empty());
+
+        // Heights past the last one have no leader rather than wrapping around
+        let last_height = u64::max_value();
+        let leaders = blockthread
+            .leader_schedule(last_height - 1, 2)
+            .get_scheduled_leaders(last_height - 1, 3);
+        assert_eq!(leaders.len(), 3);
+        assert!(leaders[1].is_some());
+        assert_eq!(leaders[2], None);
+    }
+
+    #[test]
+    fn test_leader_schedule_window() {
+        let me = NodeInfo::new_with_socketaddr(&socketaddr!("127.0.0.1:1234"));
+        let mut blockthread = BlockThread::new(me).expect("BlockThread::new");
+        let default_leader_id = blockthread.my_data().leader_id;
+        let leader = Keypair::new().pubkey();
+        for height in &[0, 10, 20, 30] {
+            blockthread.set_scheduled_leader(*height, leader);
+        }
+
+        // Only the window is copied, whichever way it is read
+        for (count, num_scheduled) in vec![(3, 1), (100, 3)] {
+            let leader_schedule = blockthread.leader_schedule(8, count);
+            assert_eq!(leader_schedule.scheduled_leaders.len(), num_scheduled);
+            assert_eq!(leader_schedule.get_scheduled_leader(7), None);
+            assert_eq!(leader_schedule.get_scheduled_leader(8), Some(default_leader_id));
+            assert_eq!(leader_schedule.get_scheduled_leader(10), Some(leader));
+            assert_eq!(leader_schedule.get_scheduled_leader(8 + count), None);
+        }
+
+        // Growing the window reads the heights after it, not those already in it
+        let mut leader_schedule = blockthread.leader_schedule(8, 5);
+        blockthread.set_scheduled_leader(9, leader);
+        let later = blockthread.leader_schedule(13, 10);
+        leader_schedule.extend(later);
+        assert_eq!(leader_schedule.next_height(), Some(23));
+        assert_eq!(leader_schedule.get_scheduled_leader(9), Some(default_leader_id));
+        assert_eq!(leader_schedule.get_scheduled_leader(20), Some(leader));
+
+        // A window running to the last height doesn't wrap around
+        let leader_schedule = blockthread.leader_schedule(u64::max_value(), 2);
+        assert_eq!(leader_schedule.next_height(), None);
+        assert_eq!(leader_schedule.get_scheduled_leader(0), None);
+
+        // Pruning keeps the heights from the given one on
+        blockthread.prune_scheduled_leaders(20);
+        let mut heights: Vec<_> = blockthread.scheduled_leaders.keys().cloned().collect();
+        heights.sort();
+        assert_eq!(heights, vec![20, 30]);
+    }
+
     #[test]
     fn test_valid_last_ids() {
         logger::setup();
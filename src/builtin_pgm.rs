@@ -348,7 +348,7 @@ mod test {
     #[test]
     fn test_sdk_serialize() {
         let keypair = Keypair::new();
-        use fin_plan_program::BUDGET_PROGRAM_ID;
+        use fin_plan_program::FinPlanState;
 
         // CreateAccount
         let tx = Transaction::system_create(
@@ -357,7 +357,7 @@ mod test {
             Hash::default(),
             111,
             222,
-            Pubkey::new(&BUDGET_PROGRAM_ID),
+            FinPlanState::id(),
             0,
         );
 
@@ -392,7 +392,7 @@ mod test {
         let tx = Transaction::system_assign(
             &keypair,
             Hash::default(),
-            Pubkey::new(&BUDGET_PROGRAM_ID),
+            FinPlanState::id(),
             0,
         );
         assert_eq!(
@@ -6,6 +6,7 @@
 use chrono::prelude::*;
 use trx_out::{Payment, Witness};
 use xpz_program_interface::pubkey::Pubkey;
+use std::cmp;
 use std::mem;
 
 /// A data type representing a `Witness` that the payment plan is waiting on.
@@ -16,6 +17,15 @@ pub enum Condition {
 
     /// Wait for a `Signature` `Witness` from `Pubkey`.
     Signature(Pubkey),
+
+    /// Wait for a `BlockHeight` `Witness` at or after the given entry height.
+    BlockHeight(u64),
+
+    /// Wait until every one of the conditions is satisfied, by any number of `Witness`es.
+    All(Vec<Condition>),
+
+    /// Wait for a `Signature` `Witness` from any one of the `Pubkey`s.
+    SignatureAnyOf(Vec<Pubkey>),
 }
 
 impl Condition {
@@ -26,9 +36,58 @@ impl Condition {
             (Condition::Timestamp(dt, pubkey), Witness::Timestamp(last_time)) => {
                 pubkey == from && dt <= last_time
             }
+            (Condition::BlockHeight(height), Witness::BlockHeight(last_height)) => {
+                height <= last_height
+            }
+            (Condition::All(conditions), _) => {
+                conditions.iter().all(|cond| cond.is_satisfied(witness, from))
+            }
+            (Condition::SignatureAnyOf(pubkeys), Witness::Signature) => pubkeys.contains(from),
             _ => false,
         }
     }
+
+    /// Drop the conditions of an `All` that the given Witness satisfies, so the ones left
+    /// are those still waited on. Other conditions are left untouched.
+    pub fn apply_witness(&mut self, witness: &Witness, from: &Pubkey) {
+        if let Condition::All(conditions) = self {
+            for cond in conditions.iter_mut() {
+                cond.apply_witness(witness, from);
+            }
+            conditions.retain(|cond| !cond.is_satisfied(witness, from));
+        }
+    }
+
+    /// Return false if this condition, or one it's built from, is an `All` of nothing. An
+    /// empty `All` is satisfied by any witness from anyone, so no plan may start out waiting
+    /// on one.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Condition::All(conditions) => {
+                !conditions.is_empty() && conditions.iter().all(Condition::is_valid)
+            }
+            _ => true,
+        }
+    }
+
+    /// Return every key whose witness this condition waits on.
+    pub fn keys(&self) -> Vec<Pubkey> {
+        match self {
+            Condition::Timestamp(_, pubkey) | Condition::Signature(pubkey) => vec![*pubkey],
+            Condition::BlockHeight(_) => vec![],
+            Condition::All(conditions) => conditions.iter().flat_map(Condition::keys).collect(),
+            Condition::SignatureAnyOf(pubkeys) => pubkeys.clone(),
+        }
+    }
+
+    /// The simple conditions this one waits on: itself, or those of every condition in an
+    /// `All`.
+    pub fn leaves(&self) -> Vec<&Condition> {
+        match self {
+            Condition::All(conditions) => conditions.iter().flat_map(Condition::leaves).collect(),
+            _ => vec![self],
+        }
+    }
 }
 
 /// A data type representing a payment plan.
@@ -47,21 +106,57 @@ pub enum FinPlan {
 
     /// Make a payment after both of two conditions are satisfied
     And(Condition, Condition, Payment),
+
+    /// Make a payment once `threshold` distinct keys from `signers` have signed. `signed`
+    /// records the keys that have already been witnessed.
+    Multisig {
+        signers: Vec<Pubkey>,
+        threshold: u64,
+        signed: Vec<Pubkey>,
+        payment: Payment,
+    },
+
+    /// Make several payments at once.
+    Split(Vec<Payment>),
+
+    /// Make several payments after some condition.
+    AfterSplit(Condition, Vec<Payment>),
+
+    /// Release `payment.tokens` of the `remaining` tokens each time the next of `conditions`
+    /// is satisfied. The plan stays pending until `remaining` is drained.
+    Installments {
+        conditions: Vec<Condition>,
+        payment: Payment,
+        remaining: u64,
+    },
+
+    /// Make the `pay` payment once its condition is satisfied, or the `refund` payment
+    /// once its condition is, whichever comes first. The refund condition only counts
+    /// after `deadline` has been satisfied, at which point the plan reduces to an `Or`.
+    CancelableAfter {
+        pay: (Condition, Payment),
+        deadline: Condition,
+        refund: (Condition, Payment),
+    },
+
+    /// Make each payment once its own condition is satisfied. A witness releases every
+    /// tranche it satisfies at once, and the plan stays pending until all are released.
+    Vesting(Vec<(Condition, Payment)>),
 }
 
 impl FinPlan {
     /// Create the simplest fin_plan - one that pays `tokens` to Pubkey.
-    pub fn new_payment(tokens: i64, to: Pubkey) -> Self {
+    pub fn new_payment(tokens: u64, to: Pubkey) -> Self {
         FinPlan::Pay(Payment { tokens, to })
     }
 
     /// Create a fin_plan that pays `tokens` to `to` after being witnessed by `from`.
-    pub fn new_authorized_payment(from: Pubkey, tokens: i64, to: Pubkey) -> Self {
+    pub fn new_authorized_payment(from: Pubkey, tokens: u64, to: Pubkey) -> Self {
         FinPlan::After(Condition::Signature(from), Payment { tokens, to })
     }
 
     /// Create a fin_plan that pays tokens` to `to` after being witnessed by 2x `from`s
-    pub fn new_2_2_multisig_payment(from0: Pubkey, from1: Pubkey, tokens: i64, to: Pubkey) -> Self {
+    pub fn new_2_2_multisig_payment(from0: Pubkey, from1: Pubkey, tokens: u64, to: Pubkey) -> Self {
         FinPlan::And(
             Condition::Signature(from0),
             Condition::Signature(from1),
@@ -69,8 +164,45 @@ impl FinPlan {
         )
     }
 
+    /// Create a fin_plan that pays `tokens` to `to` after being witnessed by `threshold`
+    /// distinct keys from `signers`.
+    pub fn new_multisig_payment(
+        signers: Vec<Pubkey>,
+        threshold: u64,
+        tokens: u64,
+        to: Pubkey,
+    ) -> Self {
+        FinPlan::Multisig {
+            signers,
+            threshold,
+            signed: vec![],
+            payment: Payment { tokens, to },
+        }
+    }
+
+    /// Create a fin_plan that makes each of `payments` after being witnessed by `from`.
+    pub fn new_authorized_split_payment(from: Pubkey, payments: Vec<Payment>) -> Self {
+        FinPlan::AfterSplit(Condition::Signature(from), payments)
+    }
+
+    /// Create a fin_plan that pays `tokens` to `to` once the ledger reaches `height`.
+    pub fn new_block_height_payment(height: u64, tokens: u64, to: Pubkey) -> Self {
+        FinPlan::After(Condition::BlockHeight(height), Payment { tokens, to })
+    }
+
+    /// Create a fin_plan that pays `tokens` to `to` once every one of `conditions` is
+    /// satisfied, in any order.
+    pub fn new_all_conditions_payment(conditions: Vec<Condition>, tokens: u64, to: Pubkey) -> Self {
+        FinPlan::After(Condition::All(conditions), Payment { tokens, to })
+    }
+
+    /// Create a fin_plan that pays `tokens` to `to` once any one of `signers` witnesses it.
+    pub fn new_any_signer_payment(signers: Vec<Pubkey>, tokens: u64, to: Pubkey) -> Self {
+        FinPlan::After(Condition::SignatureAnyOf(signers), Payment { tokens, to })
+    }
+
     /// Create a fin_plan that pays `tokens` to `to` after the given DateTime.
-    pub fn new_future_payment(dt: DateTime<Utc>, from: Pubkey, tokens: i64, to: Pubkey) -> Self {
+    pub fn new_future_payment(dt: DateTime<Utc>, from: Pubkey, tokens: u64, to: Pubkey) -> Self {
         FinPlan::After(Condition::Timestamp(dt, from), Payment { tokens, to })
     }
 
@@ -79,7 +211,7 @@ impl FinPlan {
     pub fn new_cancelable_future_payment(
         dt: DateTime<Utc>,
         from: Pubkey,
-        tokens: i64,
+        tokens: u64,
         to: Pubkey,
     ) -> Self {
         FinPlan::Or(
@@ -88,27 +220,304 @@ impl FinPlan {
         )
     }
 
-    /// Return Payment if the fin_plan requires no additional Witnesses.
-    pub fn final_payment(&self) -> Option<Payment> {
+    /// Create a fin_plan that pays `tokens` to `to` after the given DateTime unless
+    /// cancelled by `arbiter`, in which case `tokens` are refunded to `source`.
+    pub fn new_arbitrated_future_payment(
+        dt: DateTime<Utc>,
+        dt_from: Pubkey,
+        arbiter: Pubkey,
+        source: Pubkey,
+        tokens: u64,
+        to: Pubkey,
+    ) -> Self {
+        FinPlan::Or(
+            (Condition::Timestamp(dt, dt_from), Payment { tokens, to }),
+            (Condition::Signature(arbiter), Payment { tokens, to: source }),
+        )
+    }
+
+    /// Create a fin_plan that pays `tokens` to `to` as soon as `to` signs, and that `from`
+    /// can cancel for a refund once `dt_from` witnesses the given DateTime.
+    pub fn new_cancelable_after_payment(
+        dt: DateTime<Utc>,
+        dt_from: Pubkey,
+        from: Pubkey,
+        tokens: u64,
+        to: Pubkey,
+    ) -> Self {
+        FinPlan::CancelableAfter {
+            pay: (Condition::Signature(to), Payment { tokens, to }),
+            deadline: Condition::Timestamp(dt, dt_from),
+            refund: (Condition::Signature(from), Payment { tokens, to: from }),
+        }
+    }
+
+    /// Create a fin_plan that pays `tranche` tokens to `to` on each of `dates`, as witnessed
+    /// by `from`, until `tokens` have been paid out.
+    pub fn new_installment_payment(
+        dates: Vec<DateTime<Utc>>,
+        from: Pubkey,
+        tranche: u64,
+        tokens: u64,
+        to: Pubkey,
+    ) -> Self {
+        FinPlan::Installments {
+            conditions: dates
+                .into_iter()
+                .map(|dt| Condition::Timestamp(dt, from))
+                .collect(),
+            payment: Payment {
+                tokens: tranche,
+                to,
+            },
+            remaining: tokens,
+        }
+    }
+
+    /// Create a fin_plan that makes each payment of `tranches` once `from` witnesses its
+    /// DateTime.
+    pub fn new_vesting_payment(tranches: Vec<(DateTime<Utc>, Payment)>, from: Pubkey) -> Self {
+        FinPlan::Vesting(
+            tranches
+                .into_iter()
+                .map(|(dt, payment)| (Condition::Timestamp(dt, from), payment))
+                .collect(),
+        )
+    }
+
+    /// Return the Payments to make if the fin_plan requires no additional Witnesses.
+    pub fn final_payments(&self) -> Option<Vec<Payment>> {
         match self {
-            FinPlan::Pay(payment) => Some(payment.clone()),
+            FinPlan::Pay(payment) => Some(vec![payment.clone()]),
+            FinPlan::Split(payments) => Some(payments.clone()),
             _ => None,
         }
     }
 
-    /// Return true if the fin_plan spends exactly `spendable_tokens`.
-    pub fn verify(&self, spendable_tokens: i64) -> bool {
+    /// Return the number of tokens the fin_plan pays out once it resolves.
+    pub fn tokens(&self) -> u64 {
+        match self {
+            FinPlan::Split(payments) | FinPlan::AfterSplit(_, payments) => payments
+                .iter()
+                .fold(0u64, |total, payment| total.saturating_add(payment.tokens)),
+            FinPlan::Installments { remaining, .. } => *remaining,
+            FinPlan::Vesting(tranches) => tranches
+                .iter()
+                .fold(0u64, |total, (_, payment)| total.saturating_add(payment.tokens)),
+            _ => self.payments()[0].tokens,
+        }
+    }
+
+    /// Grow the amount the fin_plan pays out by `tokens`, whichever way it resolves. Returns
+    /// false and leaves the fin_plan untouched if it pays out several amounts, or if the
+    /// amount would overflow.
+    pub fn add_tokens(&mut self, tokens: u64) -> bool {
+        let payments: Vec<&mut Payment> = match self {
+            FinPlan::Pay(payment)
+            | FinPlan::After(_, payment)
+            | FinPlan::And(_, _, payment)
+            | FinPlan::Multisig { payment, .. } => vec![payment],
+            FinPlan::Or(a, b) => vec![&mut a.1, &mut b.1],
+            FinPlan::CancelableAfter { pay, refund, .. } => vec![&mut pay.1, &mut refund.1],
+            FinPlan::Split(_)
+            | FinPlan::AfterSplit(_, _)
+            | FinPlan::Installments { .. }
+            | FinPlan::Vesting(_) => return false,
+        };
+        if payments
+            .iter()
+            .any(|payment| payment.tokens.checked_add(tokens).is_none())
+        {
+            return false;
+        }
+        for payment in payments {
+            payment.tokens += tokens;
+        }
+        true
+    }
+
+    /// Return every payment the fin_plan could make.
+    pub fn payments(&self) -> Vec<&Payment> {
+        match self {
+            FinPlan::Pay(payment)
+            | FinPlan::After(_, payment)
+            | FinPlan::And(_, _, payment)
+            | FinPlan::Multisig { payment, .. }
+            | FinPlan::Installments { payment, .. } => vec![payment],
+            FinPlan::Or(a, b) => vec![&a.1, &b.1],
+            FinPlan::CancelableAfter { pay, refund, .. } => vec![&pay.1, &refund.1],
+            FinPlan::Split(payments) | FinPlan::AfterSplit(_, payments) => {
+                payments.iter().collect()
+            }
+            FinPlan::Vesting(tranches) => tranches.iter().map(|(_, payment)| payment).collect(),
+        }
+    }
+
+    /// Return every key the fin_plan may pay or wait on a witness from.
+    pub fn keys(&self) -> Vec<Pubkey> {
+        let mut keys: Vec<Pubkey> = self.payments().iter().map(|payment| payment.to).collect();
+        keys.extend(self.conditions().iter().flat_map(|cond| cond.keys()));
+        if let FinPlan::Multisig { signers, .. } = self {
+            keys.extend(signers.iter().cloned());
+        }
+        keys
+    }
+
+    /// Return every condition the fin_plan is waiting on.
+    pub fn conditions(&self) -> Vec<&Condition> {
         match self {
-            FinPlan::Pay(payment) | FinPlan::After(_, payment) | FinPlan::And(_, _, payment) => {
-                payment.tokens == spendable_tokens
+            FinPlan::Pay(_) | FinPlan::Multisig { .. } | FinPlan::Split(_) => vec![],
+            FinPlan::After(cond, _) | FinPlan::AfterSplit(cond, _) => vec![cond],
+            FinPlan::Or((cond0, _), (cond1, _)) | FinPlan::And(cond0, cond1, _) => {
+                vec![cond0, cond1]
             }
+            FinPlan::Installments { conditions, .. } => conditions.iter().collect(),
+            FinPlan::CancelableAfter {
+                pay,
+                deadline,
+                refund,
+            } => vec![&pay.0, deadline, &refund.0],
+            FinPlan::Vesting(tranches) => tranches.iter().map(|(cond, _)| cond).collect(),
+        }
+    }
+
+    /// Return true if the fin_plan spends exactly `spendable_tokens` and each of its
+    /// conditions is valid.
+    pub fn verify(&self, spendable_tokens: u64) -> bool {
+        if !self.conditions().iter().all(|cond| cond.is_valid()) {
+            return false;
+        }
+        match self {
+            FinPlan::Pay(payment)
+            | FinPlan::After(_, payment)
+            | FinPlan::And(_, _, payment)
+            | FinPlan::Multisig { payment, .. } => payment.tokens == spendable_tokens,
             FinPlan::Or(a, b) => a.1.tokens == spendable_tokens && b.1.tokens == spendable_tokens,
+            FinPlan::CancelableAfter { pay, refund, .. } => {
+                pay.1.tokens == spendable_tokens && refund.1.tokens == spendable_tokens
+            }
+            FinPlan::Split(payments) | FinPlan::AfterSplit(_, payments) => {
+                payments
+                    .iter()
+                    .try_fold(0u64, |total, payment| total.checked_add(payment.tokens))
+                    == Some(spendable_tokens)
+            }
+            FinPlan::Installments {
+                conditions,
+                payment,
+                remaining,
+            } => {
+                // Every tranche must be paid out by the time the last condition is met
+                let schedulable = payment
+                    .tokens
+                    .checked_mul(conditions.len() as u64)
+                    .unwrap_or(u64::max_value());
+                *remaining == spendable_tokens && payment.tokens > 0 && schedulable >= *remaining
+            }
+            FinPlan::Vesting(tranches) => {
+                !tranches.is_empty()
+                    && tranches
+                        .iter()
+                        .try_fold(0u64, |total, (_, payment)| total.checked_add(payment.tokens))
+                        == Some(spendable_tokens)
+            }
+        }
+    }
+
+    /// Release the next tranche of an `Installments` plan if the witness satisfies its next
+    /// condition, or every tranche of a `Vesting` plan that the witness satisfies, returning
+    /// the payments to make. Other plans are left untouched.
+    pub fn apply_drawdown(&mut self, witness: &Witness, from: &Pubkey) -> Vec<Payment> {
+        match self {
+            FinPlan::Installments {
+                conditions,
+                payment,
+                remaining,
+            } => {
+                if let Some(cond) = conditions.first_mut() {
+                    cond.apply_witness(witness, from);
+                }
+                if !conditions.is_empty() && conditions[0].is_satisfied(witness, from) {
+                    conditions.remove(0);
+                    let tokens = cmp::min(payment.tokens, *remaining);
+                    *remaining -= tokens;
+                    return vec![Payment {
+                        tokens,
+                        to: payment.to,
+                    }];
+                }
+                vec![]
+            }
+            FinPlan::Vesting(tranches) => {
+                for (cond, _) in tranches.iter_mut() {
+                    cond.apply_witness(witness, from);
+                }
+                let (due, pending): (Vec<_>, Vec<_>) = tranches
+                    .drain(..)
+                    .partition(|(cond, _)| cond.is_satisfied(witness, from));
+                *tranches = pending;
+                due.into_iter().map(|(_, payment)| payment).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Return true if the fin_plan has nothing left to pay out.
+    pub fn is_drained(&self) -> bool {
+        match self {
+            FinPlan::Installments {
+                conditions,
+                remaining,
+                ..
+            } => *remaining == 0 || conditions.is_empty(),
+            FinPlan::Vesting(tranches) => tranches.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Return true if the witness would satisfy the refund condition of a `CancelableAfter`
+    /// plan before its deadline has passed.
+    pub fn is_premature_refund(&self, witness: &Witness, from: &Pubkey) -> bool {
+        match self {
+            FinPlan::CancelableAfter { pay, refund, .. } => {
+                refund.0.is_satisfied(witness, from) && !pay.0.is_satisfied(witness, from)
+            }
+            _ => false,
+        }
+    }
+
+    /// Narrow every `All` condition the fin_plan is waiting on by the witness.
+    /// `Installments` and `Vesting` tranches are narrowed by `apply_drawdown` instead.
+    fn narrow_conditions(&mut self, witness: &Witness, from: &Pubkey) {
+        match self {
+            FinPlan::After(cond, _) | FinPlan::AfterSplit(cond, _) => {
+                cond.apply_witness(witness, from)
+            }
+            FinPlan::Or((cond0, _), (cond1, _)) | FinPlan::And(cond0, cond1, _) => {
+                cond0.apply_witness(witness, from);
+                cond1.apply_witness(witness, from);
+            }
+            FinPlan::CancelableAfter {
+                pay,
+                deadline,
+                refund,
+            } => {
+                pay.0.apply_witness(witness, from);
+                deadline.apply_witness(witness, from);
+                refund.0.apply_witness(witness, from);
+            }
+            FinPlan::Pay(_)
+            | FinPlan::Multisig { .. }
+            | FinPlan::Split(_)
+            | FinPlan::Installments { .. }
+            | FinPlan::Vesting(_) => (),
         }
     }
 
     /// Apply a witness to the fin_plan to see if the fin_plan can be reduced.
     /// If so, modify the fin_plan in-place.
     pub fn apply_witness(&mut self, witness: &Witness, from: &Pubkey) {
+        self.narrow_conditions(witness, from);
         let new_fin_plan = match self {
             FinPlan::After(cond, payment) if cond.is_satisfied(witness, from) => {
                 Some(FinPlan::Pay(payment.clone()))
@@ -119,6 +528,20 @@ impl FinPlan {
             FinPlan::Or(_, (cond, payment)) if cond.is_satisfied(witness, from) => {
                 Some(FinPlan::Pay(payment.clone()))
             }
+            FinPlan::AfterSplit(cond, payments) if cond.is_satisfied(witness, from) => {
+                Some(FinPlan::Split(payments.clone()))
+            }
+            FinPlan::CancelableAfter { pay, .. } if pay.0.is_satisfied(witness, from) => {
+                Some(FinPlan::Pay(pay.1.clone()))
+            }
+            FinPlan::CancelableAfter {
+                pay,
+                deadline,
+                refund,
+            } if deadline.is_satisfied(witness, from) =>
+            {
+                Some(FinPlan::Or(pay.clone(), refund.clone()))
+            }
             FinPlan::And(cond0, cond1, payment) => {
                 if cond0.is_satisfied(witness, from) {
                     Some(FinPlan::After(cond1.clone(), payment.clone()))
@@ -128,6 +551,24 @@ impl FinPlan {
                     None
                 }
             }
+            FinPlan::Multisig {
+                signers,
+                threshold,
+                signed,
+                payment,
+            } => {
+                if *witness == Witness::Signature
+                    && signers.contains(from)
+                    && !signed.contains(from)
+                {
+                    signed.push(*from);
+                }
+                if signed.len() as u64 >= *threshold {
+                    Some(FinPlan::Pay(payment.clone()))
+                } else {
+                    None
+                }
+            }
             _ => None,
         };
         if let Some(fin_plan) = new_fin_plan {
@@ -136,6 +577,48 @@ impl FinPlan {
     }
 }
 
+/// Ready-made plans for the payments most transactions make, so callers don't have to
+/// assemble conditions by hand.
+pub mod templates {
+    use super::{Condition, FinPlan};
+    use chrono::prelude::*;
+    use trx_out::Payment;
+    use xpz_program_interface::pubkey::Pubkey;
+
+    impl FinPlan {
+        /// Pay `tokens` to `to` right away.
+        pub fn transfer(to: Pubkey, tokens: u64) -> Self {
+            FinPlan::Pay(Payment { tokens, to })
+        }
+
+        /// Pay `tokens` to `to` once `dt_pubkey` witnesses a time at or after `dt`.
+        pub fn after(dt: DateTime<Utc>, dt_pubkey: Pubkey, to: Pubkey, tokens: u64) -> Self {
+            FinPlan::After(Condition::Timestamp(dt, dt_pubkey), Payment { tokens, to })
+        }
+
+        /// Pay `tokens` to `to` once `dt_pubkey` witnesses a time at or after `dt`,
+        /// unless `cancel_by` signs first and takes the tokens back.
+        pub fn escrow(
+            dt: DateTime<Utc>,
+            dt_pubkey: Pubkey,
+            to: Pubkey,
+            cancel_by: Pubkey,
+            tokens: u64,
+        ) -> Self {
+            FinPlan::Or(
+                (Condition::Timestamp(dt, dt_pubkey), Payment { tokens, to }),
+                (
+                    Condition::Signature(cancel_by),
+                    Payment {
+                        tokens,
+                        to: cancel_by,
+                    },
+                ),
+            )
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -157,6 +640,104 @@ mod tests {
         assert!(!Condition::Timestamp(dt2, from).is_satisfied(&Witness::Timestamp(dt1), &from));
     }
 
+    #[test]
+    fn test_block_height_satisfied() {
+        let from = Pubkey::default();
+        assert!(Condition::BlockHeight(10).is_satisfied(&Witness::BlockHeight(10), &from));
+        assert!(Condition::BlockHeight(10).is_satisfied(&Witness::BlockHeight(11), &from));
+        assert!(!Condition::BlockHeight(10).is_satisfied(&Witness::BlockHeight(9), &from));
+    }
+
+    #[test]
+    fn test_signature_any_of_satisfied() {
+        let buyer = Keypair::new().pubkey();
+        let arbiter = Keypair::new().pubkey();
+        let stranger = Keypair::new().pubkey();
+        let cond = Condition::SignatureAnyOf(vec![buyer, arbiter]);
+        assert!(cond.is_satisfied(&Witness::Signature, &buyer));
+        assert!(cond.is_satisfied(&Witness::Signature, &arbiter));
+        assert!(!cond.is_satisfied(&Witness::Signature, &stranger));
+        assert!(!cond.is_satisfied(&Witness::BlockHeight(u64::max_value()), &buyer));
+    }
+
+    #[test]
+    fn test_all_satisfied() {
+        let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let from = Pubkey::default();
+        let mut cond = Condition::All(vec![
+            Condition::Timestamp(dt, from),
+            Condition::All(vec![Condition::Signature(from), Condition::BlockHeight(10)]),
+        ]);
+        assert!(!cond.is_satisfied(&Witness::Signature, &from));
+
+        cond.apply_witness(&Witness::Signature, &from);
+        cond.apply_witness(&Witness::BlockHeight(10), &from);
+        assert_eq!(cond, Condition::All(vec![Condition::Timestamp(dt, from)]));
+        assert_eq!(cond.leaves(), vec![&Condition::Timestamp(dt, from)]);
+        assert!(cond.is_satisfied(&Witness::Timestamp(dt), &from));
+    }
+
+    #[test]
+    fn test_installment_payment() {
+        let dt0 = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let dt1 = Utc.ymd(2014, 12, 14).and_hms(8, 9, 10);
+        let from = Pubkey::default();
+        let to = Keypair::new().pubkey();
+
+        let mut fin_plan = FinPlan::new_installment_payment(vec![dt0, dt1], from, 60, 100, to);
+        assert!(fin_plan.verify(100));
+        assert!(!fin_plan.verify(99));
+        assert!(!FinPlan::new_installment_payment(vec![dt0], from, 60, 100, to).verify(100));
+        assert!(!FinPlan::new_installment_payment(vec![dt0], from, 0, 0, to).verify(0));
+
+        // Too early for the second tranche
+        assert_eq!(
+            fin_plan.apply_drawdown(&Witness::Timestamp(dt0), &from),
+            vec![Payment { tokens: 60, to }]
+        );
+        assert_eq!(fin_plan.apply_drawdown(&Witness::Timestamp(dt0), &from), vec![]);
+        assert_eq!(fin_plan.tokens(), 40);
+        assert!(!fin_plan.is_drained());
+
+        assert_eq!(
+            fin_plan.apply_drawdown(&Witness::Timestamp(dt1), &from),
+            vec![Payment { tokens: 40, to }]
+        );
+        assert!(fin_plan.is_drained());
+    }
+
+    #[test]
+    fn test_vesting_payment() {
+        let dt0 = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let dt1 = Utc.ymd(2014, 12, 14).and_hms(8, 9, 10);
+        let dt2 = Utc.ymd(2015, 1, 14).and_hms(8, 9, 10);
+        let from = Pubkey::default();
+        let to = Keypair::new().pubkey();
+        let tranche = |tokens| Payment { tokens, to };
+
+        let mut fin_plan = FinPlan::new_vesting_payment(
+            vec![(dt0, tranche(50)), (dt1, tranche(30)), (dt2, tranche(20))],
+            from,
+        );
+        assert!(fin_plan.verify(100));
+        assert!(!fin_plan.verify(99));
+        assert!(!FinPlan::new_vesting_payment(vec![], from).verify(0));
+
+        // A witness past two dates releases both of their tranches
+        assert_eq!(
+            fin_plan.apply_drawdown(&Witness::Timestamp(dt1), &from),
+            vec![tranche(50), tranche(30)]
+        );
+        assert_eq!(fin_plan.tokens(), 20);
+        assert!(!fin_plan.is_drained());
+
+        assert_eq!(
+            fin_plan.apply_drawdown(&Witness::Timestamp(dt2), &from),
+            vec![tranche(20)]
+        );
+        assert!(fin_plan.is_drained());
+    }
+
     #[test]
     fn test_verify() {
         let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
@@ -166,6 +747,42 @@ mod tests {
         assert!(FinPlan::new_authorized_payment(from, 42, to).verify(42));
         assert!(FinPlan::new_future_payment(dt, from, 42, to).verify(42));
         assert!(FinPlan::new_cancelable_future_payment(dt, from, 42, to).verify(42));
+        assert!(FinPlan::new_multisig_payment(vec![from], 1, 42, to).verify(42));
+    }
+
+    #[test]
+    fn test_verify_empty_all() {
+        let from = Pubkey::default();
+        let to = Pubkey::default();
+        let payment = Payment { tokens: 42, to };
+
+        // Any stranger's signature satisfies an `All` of nothing
+        let stranger = Keypair::new().pubkey();
+        assert!(Condition::All(vec![]).is_satisfied(&Witness::Signature, &stranger));
+        assert!(!FinPlan::After(Condition::All(vec![]), payment.clone()).verify(42));
+        let nested = Condition::All(vec![Condition::Signature(from), Condition::All(vec![])]);
+        assert!(!nested.is_valid());
+        assert!(!FinPlan::After(nested, payment.clone()).verify(42));
+        let cond = Condition::All(vec![Condition::Signature(from), Condition::BlockHeight(10)]);
+        assert!(FinPlan::After(cond, payment).verify(42));
+    }
+
+    #[test]
+    fn test_payments() {
+        let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let from = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        let fin_plan = FinPlan::new_cancelable_future_payment(dt, from, 42, to);
+        assert_eq!(
+            fin_plan.payments(),
+            vec![
+                &Payment { tokens: 42, to },
+                &Payment {
+                    tokens: 42,
+                    to: from
+                }
+            ]
+        );
     }
 
     #[test]
@@ -178,6 +795,65 @@ mod tests {
         assert_eq!(fin_plan, FinPlan::new_payment(42, to));
     }
 
+    #[test]
+    fn test_any_signer_payment() {
+        let buyer = Keypair::new().pubkey();
+        let arbiter = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+
+        for signer in &[buyer, arbiter] {
+            let mut fin_plan = FinPlan::new_any_signer_payment(vec![buyer, arbiter], 42, to);
+            fin_plan.apply_witness(&Witness::Signature, signer);
+            assert_eq!(fin_plan, FinPlan::new_payment(42, to));
+        }
+
+        let mut fin_plan = FinPlan::new_any_signer_payment(vec![buyer, arbiter], 42, to);
+        let pending = fin_plan.clone();
+        fin_plan.apply_witness(&Witness::Signature, &Keypair::new().pubkey());
+        assert_eq!(fin_plan, pending);
+    }
+
+    #[test]
+    fn test_add_tokens() {
+        let from = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+
+        // Whichever way an `Or` resolves, it pays the new amount
+        let mut fin_plan = FinPlan::new_cancelable_future_payment(dt, from, 42, to);
+        assert!(fin_plan.add_tokens(8));
+        assert_eq!(fin_plan, FinPlan::new_cancelable_future_payment(dt, from, 50, to));
+        assert!(fin_plan.verify(50));
+
+        assert!(!fin_plan.add_tokens(u64::max_value()));
+        assert_eq!(fin_plan.tokens(), 50);
+
+        let payments = vec![Payment { tokens: 1, to }, Payment { tokens: 1, to: from }];
+        let mut fin_plan = FinPlan::Split(payments.clone());
+        assert!(!fin_plan.add_tokens(1));
+        assert_eq!(fin_plan, FinPlan::Split(payments));
+    }
+
+    #[test]
+    fn test_split_payment() {
+        let from = Keypair::new().pubkey();
+        let to0 = Keypair::new().pubkey();
+        let to1 = Keypair::new().pubkey();
+        let payments = vec![
+            Payment { tokens: 70, to: to0 },
+            Payment { tokens: 30, to: to1 },
+        ];
+
+        let mut fin_plan = FinPlan::new_authorized_split_payment(from, payments.clone());
+        assert!(fin_plan.verify(100));
+        assert!(!fin_plan.verify(70));
+        assert_eq!(fin_plan.tokens(), 100);
+        assert_eq!(fin_plan.final_payments(), None);
+
+        fin_plan.apply_witness(&Witness::Signature, &from);
+        assert_eq!(fin_plan.final_payments(), Some(payments));
+    }
+
     #[test]
     fn test_future_payment() {
         let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
@@ -217,6 +893,43 @@ mod tests {
         fin_plan.apply_witness(&Witness::Signature, &from);
         assert_eq!(fin_plan, FinPlan::new_payment(42, from));
     }
+    #[test]
+    fn test_arbitrated_future_payment() {
+        let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let source = Keypair::new().pubkey();
+        let arbiter = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+
+        let mut fin_plan =
+            FinPlan::new_arbitrated_future_payment(dt, source, arbiter, source, 42, to);
+        fin_plan.apply_witness(&Witness::Signature, &source);
+        assert_eq!(fin_plan.final_payments(), None);
+        fin_plan.apply_witness(&Witness::Signature, &arbiter);
+        assert_eq!(fin_plan, FinPlan::new_payment(42, source));
+    }
+
+    #[test]
+    fn test_cancelable_after_payment() {
+        let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let from = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+
+        let mut fin_plan = FinPlan::new_cancelable_after_payment(dt, from, from, 42, to);
+        assert!(fin_plan.verify(42));
+        assert!(fin_plan.is_premature_refund(&Witness::Signature, &from));
+        fin_plan.apply_witness(&Witness::Signature, &from);
+        assert_eq!(fin_plan.final_payments(), None);
+
+        fin_plan.apply_witness(&Witness::Timestamp(dt), &from);
+        assert!(!fin_plan.is_premature_refund(&Witness::Signature, &from));
+        fin_plan.apply_witness(&Witness::Signature, &from);
+        assert_eq!(fin_plan, FinPlan::new_payment(42, from));
+
+        let mut fin_plan = FinPlan::new_cancelable_after_payment(dt, from, from, 42, to);
+        fin_plan.apply_witness(&Witness::Signature, &to);
+        assert_eq!(fin_plan, FinPlan::new_payment(42, to));
+    }
+
     #[test]
     fn test_2_2_multisig_payment() {
         let from0 = Keypair::new().pubkey();
@@ -227,4 +940,50 @@ mod tests {
         fin_plan.apply_witness(&Witness::Signature, &from0);
         assert_eq!(fin_plan, FinPlan::new_authorized_payment(from1, 42, to));
     }
+
+    #[test]
+    fn test_all_conditions_payment() {
+        let dt = Utc.ymd(2014, 11, 14).and_hms(8, 9, 10);
+        let from = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        let conditions = vec![Condition::Timestamp(dt, from), Condition::Signature(from)];
+        let fin_plan = FinPlan::new_all_conditions_payment(conditions, 42, to);
+
+        // Neither witness alone pays out
+        let mut dated = fin_plan.clone();
+        dated.apply_witness(&Witness::Timestamp(dt), &from);
+        assert_eq!(dated.final_payments(), None);
+        let mut signed = fin_plan.clone();
+        signed.apply_witness(&Witness::Signature, &from);
+        assert_eq!(signed.final_payments(), None);
+
+        // Both do, in either order
+        dated.apply_witness(&Witness::Signature, &from);
+        assert_eq!(dated, FinPlan::new_payment(42, to));
+        signed.apply_witness(&Witness::Timestamp(dt), &from);
+        assert_eq!(signed, FinPlan::new_payment(42, to));
+    }
+
+    #[test]
+    fn test_multisig_payment() {
+        let from0 = Keypair::new().pubkey();
+        let from1 = Keypair::new().pubkey();
+        let from2 = Keypair::new().pubkey();
+        let to = Pubkey::default();
+
+        let mut fin_plan = FinPlan::new_multisig_payment(vec![from0, from1, from2], 2, 42, to);
+        fin_plan.apply_witness(&Witness::Signature, &from0);
+        assert_eq!(fin_plan.final_payments(), None);
+
+        // A replayed signature from the same key must not count twice
+        fin_plan.apply_witness(&Witness::Signature, &from0);
+        assert_eq!(fin_plan.final_payments(), None);
+
+        // Neither does a signature from a key outside the signer set
+        fin_plan.apply_witness(&Witness::Signature, &to);
+        assert_eq!(fin_plan.final_payments(), None);
+
+        fin_plan.apply_witness(&Witness::Signature, &from2);
+        assert_eq!(fin_plan, FinPlan::new_payment(42, to));
+    }
 }
@@ -1,19 +1,64 @@
 use fin_plan::FinPlan;
 use chrono::prelude::{DateTime, Utc};
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use xpz_program_interface::pubkey::Pubkey;
 
+/// Longest memo a contract may carry.
+pub const MAX_MEMO_SIZE: usize = 64;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Contract {
 
-    pub tokens: i64,
+    pub tokens: u64,
     pub fin_plan: FinPlan,
+    /// An opaque reference, such as an invoice id, kept with the contract and reported when
+    /// it pays out. Up to `MAX_MEMO_SIZE` bytes; it plays no part in the plan.
+    #[serde(default, deserialize_with = "deserialize_trailing")]
+    pub memo: Option<Vec<u8>>,
+    /// Once a trusted timestamp at or after `expiry` arrives, refund the source instead.
+    #[serde(default, deserialize_with = "deserialize_trailing")]
+    pub expiry: Option<DateTime<Utc>>,
 }
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-pub struct Vote {
-    pub version: u64,
-    pub contact_info_version: u64,
+
+/// Contracts encoded before a trailing field existed end where it would start, so read
+/// running out of input as `None`.
+fn deserialize_trailing<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Option::deserialize(deserializer).unwrap_or(None))
+}
+
+/// Trailing fields are left off while they and every field after them are `None`, so a
+/// contract that uses none of them encodes exactly as it did before they existed.
+impl Serialize for Contract {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let trailing = if self.expiry.is_some() {
+            2
+        } else if self.memo.is_some() {
+            1
+        } else {
+            0
+        };
+        let mut state = serializer.serialize_struct("Contract", 2 + trailing)?;
+        state.serialize_field("tokens", &self.tokens)?;
+        state.serialize_field("fin_plan", &self.fin_plan)?;
+        if trailing > 0 {
+            state.serialize_field("memo", &self.memo)?;
+        }
+        if trailing > 1 {
+            state.serialize_field("expiry", &self.expiry)?;
+        }
+        state.end()
+    }
 }
 
+/// Number of `Instruction` variants. Userdata tagged with a variant at or past this was
+/// written for a newer program.
+pub const INSTRUCTION_VARIANTS: u32 = 9;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum Instruction {
@@ -26,6 +71,43 @@ pub enum Instruction {
     
     ApplySignature,
 
-    
-    NewVote(Vote),
+    /// Escrow `tokens` for `to` until `threshold` distinct keys in `signers` have signed.
+    NewMultisigContract {
+        signers: Vec<Pubkey>,
+        threshold: u64,
+        tokens: u64,
+        to: Pubkey,
+    },
+
+    /// Witness that the ledger has reached the given entry height.
+    ApplyBlockHeight(u64),
+
+    /// Write the spendable balance of the contract in `keys[1]` into the userdata of the
+    /// output account in `keys[2]`.
+    GetBalance,
+
+    /// Escrow `count` payments of `tokens` for `to`, the first due at `start` and each
+    /// following one `interval` seconds later. A payment is released by a timestamp from
+    /// `dt_pubkey` at or after its due date, and the source may cancel the rest with a
+    /// signature.
+    NewRecurringContract {
+        start: DateTime<Utc>,
+        interval: u64,
+        count: u64,
+        tokens: u64,
+        dt_pubkey: Pubkey,
+        to: Pubkey,
+    },
+
+    /// Pay `tokens` out at once, split between `recipients` by their percentage share.
+    /// The shares must sum to 100; tokens that don't divide evenly go to the recipients
+    /// with the largest remainders.
+    NewSplitContract {
+        recipients: Vec<(Pubkey, u8)>,
+        tokens: u64,
+    },
+
+    /// Move `tokens` from the source into the pending contract in `keys[1]` and grow its
+    /// payout by as much. Only the key that funded the contract may add to it.
+    AddFunds(u64),
 }
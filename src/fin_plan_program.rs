@@ -1,107 +1,1179 @@
 //! fin_plan program
 use bincode::{self, deserialize, serialize_into, serialized_size};
-use fin_plan::FinPlan;
-use fin_plan_instruction::Instruction;
-use chrono::prelude::{DateTime, Utc};
-use trx_out::Witness;
+use counter::Counter;
+use fin_plan::{Condition, FinPlan};
+use fin_plan_instruction::{Contract, Instruction, INSTRUCTION_VARIANTS, MAX_MEMO_SIZE};
+use chrono::prelude::{DateTime, NaiveDate, Utc};
+use chrono::Duration;
+use log::Level;
+use serde::de::DeserializeOwned;
+use trx_out::{Payment, Witness};
 use xpz_program_interface::account::Account;
 use xpz_program_interface::pubkey::Pubkey;
+use rayon::prelude::*;
+use ring::constant_time;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use transaction::Transaction;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum FinPlanError {
-    InsufficientFunds(Pubkey),
+    /// `who` holds `available` tokens but the transaction needs `needed`, counting any fee.
+    InsufficientFunds {
+        who: Pubkey,
+        needed: i64,
+        available: i64,
+    },
     ContractAlreadyExists(Pubkey),
     ContractNotPending(Pubkey),
     SourceIsPendingContract(Pubkey),
     UninitializedContract(Pubkey),
-    NegativeTokens,
+    TokensOutOfRange,
     DestinationMissing(Pubkey),
     FailedWitness,
     UserdataTooSmall,
     UserdataDeserializeFailure,
+    InvalidThreshold,
+    TokenOverflow(Pubkey),
+    MissingKeys,
+    FutureBlockHeight(u64),
+    WrongProgramOwner(Pubkey),
+    DuplicateWitness,
+    InvalidRecurrence,
+    UnsupportedInstruction(u32),
+    NonMonotonicTimestamp,
+    ZeroTokens,
+    DuplicateKey(Pubkey),
+    InvalidSplit,
+    UnreferencedKey(Pubkey),
+    /// Only a completed contract holding no tokens can be closed.
+    ContractNotClosable,
+    /// Only the key that funded a contract may add to it.
+    NotContractSource(Pubkey),
+    /// The pending plan doesn't pay a single amount that a top-up could grow.
+    FixedPlanAmount,
+    /// A contract's memo is longer than `MAX_MEMO_SIZE`.
+    MemoTooLarge,
+    /// In strict mode, a new contract's plan pays out at once instead of holding tokens.
+    NotAnEscrow,
+    /// The witness neither satisfies nor advances any condition of the pending plan, such as a
+    /// timestamp before the due date or a signature the plan doesn't name. These used to
+    /// succeed without doing anything; they now fail so they can't be recorded.
+    UnusedWitness,
+    /// A new contract's plan waits on an `All` of no conditions, which any witness would
+    /// satisfy.
+    EmptyCondition,
+}
+
+impl fmt::Display for FinPlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FinPlanError::InsufficientFunds {
+                who,
+                needed,
+                available,
+            } => write!(
+                f,
+                "insufficient funds in {}: needed {}, available {}",
+                who, needed, available
+            ),
+            FinPlanError::ContractAlreadyExists(key) => {
+                write!(f, "contract {} already exists", key)
+            }
+            FinPlanError::ContractNotPending(key) => write!(f, "contract {} is not pending", key),
+            FinPlanError::SourceIsPendingContract(key) => {
+                write!(f, "source {} is a pending contract", key)
+            }
+            FinPlanError::UninitializedContract(key) => {
+                write!(f, "contract {} is uninitialized", key)
+            }
+            FinPlanError::TokensOutOfRange => write!(f, "token amount out of range"),
+            FinPlanError::DestinationMissing(key) => write!(f, "destination {} is missing", key),
+            FinPlanError::FailedWitness => write!(f, "witness failed"),
+            FinPlanError::UserdataTooSmall => write!(f, "userdata too small"),
+            FinPlanError::UserdataDeserializeFailure => write!(f, "userdata failed to deserialize"),
+            FinPlanError::InvalidThreshold => write!(f, "invalid multisig threshold"),
+            FinPlanError::TokenOverflow(key) => write!(f, "token overflow in {}", key),
+            FinPlanError::MissingKeys => write!(f, "transaction is missing keys"),
+            FinPlanError::FutureBlockHeight(height) => {
+                write!(f, "block height {} has not been reached", height)
+            }
+            FinPlanError::WrongProgramOwner(key) => {
+                write!(f, "account {} is not owned by the budget program", key)
+            }
+            FinPlanError::DuplicateWitness => write!(f, "witness was already applied"),
+            FinPlanError::InvalidRecurrence => write!(f, "invalid recurring payment schedule"),
+            FinPlanError::UnsupportedInstruction(tag) => {
+                write!(f, "unsupported instruction {}", tag)
+            }
+            FinPlanError::NonMonotonicTimestamp => {
+                write!(f, "timestamp is earlier than one already applied")
+            }
+            FinPlanError::ZeroTokens => write!(f, "contract moves no tokens"),
+            FinPlanError::DuplicateKey(key) => write!(f, "key {} is used twice", key),
+            FinPlanError::InvalidSplit => write!(f, "split shares don't sum to 100"),
+            FinPlanError::UnreferencedKey(key) => {
+                write!(f, "plan pays {}, which the transaction doesn't name", key)
+            }
+            FinPlanError::ContractNotClosable => {
+                write!(f, "contract is still pending or holds tokens")
+            }
+            FinPlanError::NotContractSource(key) => {
+                write!(f, "{} didn't fund the contract", key)
+            }
+            FinPlanError::FixedPlanAmount => write!(f, "plan amount can't be increased"),
+            FinPlanError::MemoTooLarge => {
+                write!(f, "memo is longer than {} bytes", MAX_MEMO_SIZE)
+            }
+            FinPlanError::NotAnEscrow => write!(f, "contract would pay out immediately"),
+            FinPlanError::UnusedWitness => write!(f, "witness doesn't advance the plan"),
+            FinPlanError::EmptyCondition => write!(f, "plan waits on an empty condition"),
+        }
+    }
+}
+
+impl FinPlanError {
+    /// Bump the counter for this kind of error, so each failure class can be watched on its
+    /// own.
+    fn record(&self) {
+        self.count(true);
+    }
+
+    /// Times this kind of error has failed a transaction since startup, bumped first if
+    /// `record` is set.
+    fn count(&self, record: bool) -> &'static AtomicUsize {
+        macro_rules! count {
+            ($name:expr) => {{
+                static COUNT: AtomicUsize = AtomicUsize::new(0);
+                if record {
+                    inc_new_counter_info!($name, 1);
+                    COUNT.fetch_add(1, Ordering::Relaxed);
+                }
+                &COUNT
+            }};
+        }
+        match self {
+            FinPlanError::InsufficientFunds { .. } => count!("fin_plan-insufficient_funds"),
+            FinPlanError::ContractAlreadyExists(_) => count!("fin_plan-contract_already_exists"),
+            FinPlanError::ContractNotPending(_) => count!("fin_plan-contract_not_pending"),
+            FinPlanError::SourceIsPendingContract(_) => {
+                count!("fin_plan-source_is_pending_contract")
+            }
+            FinPlanError::UninitializedContract(_) => count!("fin_plan-uninitialized_contract"),
+            FinPlanError::TokensOutOfRange => count!("fin_plan-tokens_out_of_range"),
+            FinPlanError::DestinationMissing(_) => count!("fin_plan-destination_missing"),
+            FinPlanError::FailedWitness => count!("fin_plan-failed_witness"),
+            FinPlanError::UserdataTooSmall => count!("fin_plan-userdata_too_small"),
+            FinPlanError::UserdataDeserializeFailure => {
+                count!("fin_plan-userdata_deserialize_failure")
+            }
+            FinPlanError::InvalidThreshold => count!("fin_plan-invalid_threshold"),
+            FinPlanError::TokenOverflow(_) => count!("fin_plan-token_overflow"),
+            FinPlanError::MissingKeys => count!("fin_plan-missing_keys"),
+            FinPlanError::FutureBlockHeight(_) => count!("fin_plan-future_block_height"),
+            FinPlanError::WrongProgramOwner(_) => count!("fin_plan-wrong_program_owner"),
+            FinPlanError::DuplicateWitness => count!("fin_plan-duplicate_witness"),
+            FinPlanError::InvalidRecurrence => count!("fin_plan-invalid_recurrence"),
+            FinPlanError::UnsupportedInstruction(_) => count!("fin_plan-unsupported_instruction"),
+            FinPlanError::NonMonotonicTimestamp => count!("fin_plan-non_monotonic_timestamp"),
+            FinPlanError::ZeroTokens => count!("fin_plan-zero_tokens"),
+            FinPlanError::DuplicateKey(_) => count!("fin_plan-duplicate_key"),
+            FinPlanError::InvalidSplit => count!("fin_plan-invalid_split"),
+            FinPlanError::UnreferencedKey(_) => count!("fin_plan-unreferenced_key"),
+            FinPlanError::ContractNotClosable => count!("fin_plan-contract_not_closable"),
+            FinPlanError::NotContractSource(_) => count!("fin_plan-not_contract_source"),
+            FinPlanError::FixedPlanAmount => count!("fin_plan-fixed_plan_amount"),
+            FinPlanError::MemoTooLarge => count!("fin_plan-memo_too_large"),
+            FinPlanError::NotAnEscrow => count!("fin_plan-not_an_escrow"),
+            FinPlanError::UnusedWitness => count!("fin_plan-unused_witness"),
+            FinPlanError::EmptyCondition => count!("fin_plan-empty_condition"),
+        }
+    }
+}
+
+/// Number of transactions failed with the same kind of error as `error`, whatever its
+/// fields, since startup.
+pub fn fin_plan_error_count(error: &FinPlanError) -> usize {
+    error.count(false).load(Ordering::Relaxed)
+}
+
+impl error::Error for FinPlanError {}
+
+/// A payment made by a pending contract once a witness completed its plan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionRecord {
+    /// The contract the tokens were paid out of.
+    pub contract: Pubkey,
+    /// The account that funded the contract, if the contract remembers it.
+    pub source: Option<Pubkey>,
+    pub destination: Pubkey,
+    pub tokens: u64,
+    /// The witness that completed the plan and the key that presented it.
+    pub witness: Witness,
+    pub witness_key: Pubkey,
+    /// The memo the contract was created with.
+    pub memo: Option<Vec<u8>>,
+}
+
+/// What `FinPlanState::process_transaction` would do to a set of accounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationResult {
+    /// The token balance of each account afterwards, in the order the accounts were given.
+    pub balances: Vec<i64>,
+    /// The state of the contract in `accounts[1]` afterwards, if it holds one.
+    pub state: Option<FinPlanState>,
+    /// Whether the contract is still waiting on a witness.
+    pub pending: bool,
+}
+
+/// What `FinPlanState::process_transaction_verbose` did to a set of accounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxReceipt {
+    /// The instruction decoded from the transaction's userdata.
+    pub instruction: Instruction,
+    /// Tokens the debit took from the source in `accounts[0]`.
+    pub debited: i64,
+    /// The change the credit made to each account's tokens, in the order the accounts
+    /// were given.
+    pub credited: Vec<i64>,
+    /// Whether the contract in `accounts[1]` is still waiting on a witness.
+    pub pending: bool,
+}
+
+/// What creating a contract costs its source, and who is paid for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContractFee {
+    pub tokens: u64,
+    /// Set by the node rather than the payer, and passed among `tx.keys` after the source
+    /// and the contract.
+    pub collector: Pubkey,
+}
+
+/// The slot of `tx.keys` each account an instruction works on is read from. Destinations
+/// have no fixed slot: a payment is credited to whichever slot from `first_destination` on
+/// holds `payment.to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AccountRoles {
+    /// The key that presents a witness, or funds a new contract. A transaction's one
+    /// signature is over `keys[0]`, so no other key in it can stand in for a signer.
+    signer: usize,
+    /// The contract the instruction creates or applies a witness to.
+    contract: usize,
+    first_destination: usize,
+}
+
+impl AccountRoles {
+    fn of(instruction: &Instruction) -> Self {
+        match instruction {
+            // Tokens paid out right away may go to any key after the source
+            Instruction::NewContract(_) | Instruction::NewSplitContract { .. } => AccountRoles {
+                signer: 0,
+                contract: 1,
+                first_destination: 1,
+            },
+            _ => AccountRoles {
+                signer: 0,
+                contract: 1,
+                first_destination: 2,
+            },
+        }
+    }
+}
+
+/// Where a contract account is in its life, as reported by `FinPlanState::status`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractStatus {
+    /// The account holds no contract yet, or userdata that doesn't parse as one.
+    Uninitialized,
+    /// The contract is waiting on a witness.
+    Pending { plan_summary: PlanSummary },
+    /// The contract has paid out, so its tokens can be spent.
+    Completed,
+}
+
+/// The outline of a pending plan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanSummary {
+    /// Tokens the plan pays out once it resolves.
+    pub tokens: u64,
+    /// Every key the plan could pay.
+    pub destinations: Vec<Pubkey>,
+    /// The conditions the plan is waiting on.
+    pub conditions: Vec<Condition>,
+}
+
+impl<'a> From<&'a FinPlan> for PlanSummary {
+    fn from(fin_plan: &'a FinPlan) -> Self {
+        PlanSummary {
+            tokens: fin_plan.tokens(),
+            destinations: fin_plan.payments().iter().map(|payment| payment.to).collect(),
+            conditions: fin_plan.conditions().into_iter().cloned().collect(),
+        }
+    }
+}
+
+/// What a pending contract is waiting on before it pays its destination.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReleaseCondition {
+    /// A timestamp at or after the date, from the key.
+    OnDate(DateTime<Utc>, Pubkey),
+    /// A signature from the key.
+    OnSignature(Pubkey),
+    /// A signature from any one of the keys.
+    OnAnySignature(Vec<Pubkey>),
+    /// The ledger reaching the height.
+    OnBlockHeight(u64),
+    /// Signatures from `threshold` of `signers`.
+    OnSignatures { signers: Vec<Pubkey>, threshold: u64 },
+    /// Several conditions together.
+    All(Vec<ReleaseCondition>),
+}
+
+impl<'a> From<&'a Condition> for ReleaseCondition {
+    fn from(condition: &'a Condition) -> Self {
+        match condition {
+            Condition::Timestamp(dt, pubkey) => ReleaseCondition::OnDate(*dt, *pubkey),
+            Condition::Signature(pubkey) => ReleaseCondition::OnSignature(*pubkey),
+            Condition::SignatureAnyOf(pubkeys) => ReleaseCondition::OnAnySignature(pubkeys.clone()),
+            Condition::BlockHeight(height) => ReleaseCondition::OnBlockHeight(*height),
+            Condition::All(conditions) => {
+                ReleaseCondition::All(conditions.iter().map(ReleaseCondition::from).collect())
+            }
+        }
+    }
+}
+
+/// Who a pending contract pays, how much, and what it is waiting on, for showing to a
+/// user without walking the plan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractSummary {
+    pub to: Pubkey,
+    pub tokens: u64,
+    pub release: ReleaseCondition,
+}
+
+/// The schedule of a contract created by `Instruction::NewRecurringContract`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Recurrence {
+    /// Seconds between due dates.
+    pub interval: u64,
+    /// Tokens paid out each period.
+    pub tokens: u64,
+    /// The key trusted to witness that a period is due.
+    pub dt_pubkey: Pubkey,
+    pub to: Pubkey,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct FinPlanState {
     pub initialized: bool,
     pub pending_fin_plan: Option<FinPlan>,
+    /// When the pending plan stops paying its destination and refunds instead.
+    pub expiry: Option<DateTime<Utc>>,
+    /// The source key refunded after `expiry`, or when it cancels a recurring contract.
+    pub refund: Option<Pubkey>,
+    /// Witnesses already applied to the pending plan, with the key that presented them.
+    pub witnessed: Vec<(Witness, Pubkey)>,
+    /// The schedule of a recurring contract.
+    pub recurrence: Option<Recurrence>,
+    /// When the current period of a recurring contract is due.
+    pub next_due: Option<DateTime<Utc>>,
+    /// Periods of a recurring contract not yet paid out, the current one included.
+    pub remaining_count: u64,
+    /// The latest timestamp applied to the contract. Earlier ones are refused.
+    pub last_timestamp: Option<DateTime<Utc>>,
+    /// The ledger height the contract was created at. `None` for contracts stored before
+    /// userdata version 6.
+    pub created_height: Option<u64>,
+    /// The key that funded the contract, the only one that may add to it. `None` for
+    /// contracts stored before userdata version 7.
+    pub source: Option<Pubkey>,
+    /// The memo the contract was created with, if any.
+    pub memo: Option<Vec<u8>>,
+}
+
+/// `FinPlanState` as encoded by userdata versions 0 and 1.
+#[derive(Deserialize)]
+struct FinPlanStateV0 {
+    initialized: bool,
+    pending_fin_plan: Option<FinPlan>,
+}
+
+impl From<FinPlanStateV0> for FinPlanState {
+    fn from(state: FinPlanStateV0) -> Self {
+        FinPlanState {
+            initialized: state.initialized,
+            pending_fin_plan: state.pending_fin_plan,
+            expiry: None,
+            refund: None,
+            witnessed: vec![],
+            recurrence: None,
+            next_due: None,
+            remaining_count: 0,
+            last_timestamp: None,
+            created_height: None,
+            source: None,
+            memo: None,
+        }
+    }
+}
+
+/// `FinPlanState` as encoded by userdata version 2.
+#[derive(Deserialize)]
+struct FinPlanStateV2 {
+    initialized: bool,
+    pending_fin_plan: Option<FinPlan>,
+    expiry: Option<DateTime<Utc>>,
+    refund: Option<Pubkey>,
+}
+
+impl From<FinPlanStateV2> for FinPlanState {
+    fn from(state: FinPlanStateV2) -> Self {
+        FinPlanState {
+            initialized: state.initialized,
+            pending_fin_plan: state.pending_fin_plan,
+            expiry: state.expiry,
+            refund: state.refund,
+            witnessed: vec![],
+            recurrence: None,
+            next_due: None,
+            remaining_count: 0,
+            last_timestamp: None,
+            created_height: None,
+            source: None,
+            memo: None,
+        }
+    }
+}
+
+/// `FinPlanState` as encoded by userdata version 3.
+#[derive(Deserialize)]
+struct FinPlanStateV3 {
+    initialized: bool,
+    pending_fin_plan: Option<FinPlan>,
+    expiry: Option<DateTime<Utc>>,
+    refund: Option<Pubkey>,
+    witnessed: Vec<(Witness, Pubkey)>,
+}
+
+impl From<FinPlanStateV3> for FinPlanState {
+    fn from(state: FinPlanStateV3) -> Self {
+        FinPlanState {
+            initialized: state.initialized,
+            pending_fin_plan: state.pending_fin_plan,
+            expiry: state.expiry,
+            refund: state.refund,
+            witnessed: state.witnessed,
+            recurrence: None,
+            next_due: None,
+            remaining_count: 0,
+            last_timestamp: None,
+            created_height: None,
+            source: None,
+            memo: None,
+        }
+    }
+}
+
+/// `FinPlanState` as encoded by userdata version 4.
+#[derive(Deserialize)]
+struct FinPlanStateV4 {
+    initialized: bool,
+    pending_fin_plan: Option<FinPlan>,
+    expiry: Option<DateTime<Utc>>,
+    refund: Option<Pubkey>,
+    witnessed: Vec<(Witness, Pubkey)>,
+    recurrence: Option<Recurrence>,
+    next_due: Option<DateTime<Utc>>,
+    remaining_count: u64,
+}
+
+impl From<FinPlanStateV4> for FinPlanState {
+    fn from(state: FinPlanStateV4) -> Self {
+        FinPlanState {
+            initialized: state.initialized,
+            pending_fin_plan: state.pending_fin_plan,
+            expiry: state.expiry,
+            refund: state.refund,
+            witnessed: state.witnessed,
+            recurrence: state.recurrence,
+            next_due: state.next_due,
+            remaining_count: state.remaining_count,
+            last_timestamp: None,
+            created_height: None,
+            source: None,
+            memo: None,
+        }
+    }
+}
+
+/// `FinPlanState` as encoded by userdata version 5.
+#[derive(Deserialize)]
+struct FinPlanStateV5 {
+    initialized: bool,
+    pending_fin_plan: Option<FinPlan>,
+    expiry: Option<DateTime<Utc>>,
+    refund: Option<Pubkey>,
+    witnessed: Vec<(Witness, Pubkey)>,
+    recurrence: Option<Recurrence>,
+    next_due: Option<DateTime<Utc>>,
+    remaining_count: u64,
+    last_timestamp: Option<DateTime<Utc>>,
+}
+
+impl From<FinPlanStateV5> for FinPlanState {
+    fn from(state: FinPlanStateV5) -> Self {
+        FinPlanState {
+            initialized: state.initialized,
+            pending_fin_plan: state.pending_fin_plan,
+            expiry: state.expiry,
+            refund: state.refund,
+            witnessed: state.witnessed,
+            recurrence: state.recurrence,
+            next_due: state.next_due,
+            remaining_count: state.remaining_count,
+            last_timestamp: state.last_timestamp,
+            created_height: None,
+            source: None,
+            memo: None,
+        }
+    }
+}
+
+/// `FinPlanState` as encoded by userdata version 6.
+#[derive(Deserialize)]
+struct FinPlanStateV6 {
+    initialized: bool,
+    pending_fin_plan: Option<FinPlan>,
+    expiry: Option<DateTime<Utc>>,
+    refund: Option<Pubkey>,
+    witnessed: Vec<(Witness, Pubkey)>,
+    recurrence: Option<Recurrence>,
+    next_due: Option<DateTime<Utc>>,
+    remaining_count: u64,
+    last_timestamp: Option<DateTime<Utc>>,
+    created_height: Option<u64>,
+}
+
+impl From<FinPlanStateV6> for FinPlanState {
+    fn from(state: FinPlanStateV6) -> Self {
+        FinPlanState {
+            initialized: state.initialized,
+            pending_fin_plan: state.pending_fin_plan,
+            expiry: state.expiry,
+            refund: state.refund,
+            witnessed: state.witnessed,
+            recurrence: state.recurrence,
+            next_due: state.next_due,
+            remaining_count: state.remaining_count,
+            last_timestamp: state.last_timestamp,
+            created_height: state.created_height,
+            source: None,
+            memo: None,
+        }
+    }
+}
+
+/// `FinPlanState` as encoded by userdata version 7.
+#[derive(Deserialize)]
+struct FinPlanStateV7 {
+    initialized: bool,
+    pending_fin_plan: Option<FinPlan>,
+    expiry: Option<DateTime<Utc>>,
+    refund: Option<Pubkey>,
+    witnessed: Vec<(Witness, Pubkey)>,
+    recurrence: Option<Recurrence>,
+    next_due: Option<DateTime<Utc>>,
+    remaining_count: u64,
+    last_timestamp: Option<DateTime<Utc>>,
+    created_height: Option<u64>,
+    source: Option<Pubkey>,
+}
+
+impl From<FinPlanStateV7> for FinPlanState {
+    fn from(state: FinPlanStateV7) -> Self {
+        FinPlanState {
+            initialized: state.initialized,
+            pending_fin_plan: state.pending_fin_plan,
+            expiry: state.expiry,
+            refund: state.refund,
+            witnessed: state.witnessed,
+            recurrence: state.recurrence,
+            next_due: state.next_due,
+            remaining_count: state.remaining_count,
+            last_timestamp: state.last_timestamp,
+            created_height: state.created_height,
+            source: state.source,
+            memo: None,
+        }
+    }
 }
 
+/// Userdata layout version of the original `FinPlanState` encoding, which had no version
+/// tag. Its length prefix always has a zero high byte, so it reads as version 0.
+pub const FIN_PLAN_STATE_VERSION_0: u8 = 0;
+
+/// Userdata layout version that added the version tag.
+pub const FIN_PLAN_STATE_VERSION_1: u8 = 1;
+
+/// Userdata layout version that added contract expiry.
+pub const FIN_PLAN_STATE_VERSION_2: u8 = 2;
+
+/// Userdata layout version that added witness replay protection.
+pub const FIN_PLAN_STATE_VERSION_3: u8 = 3;
+
+/// Userdata layout version that added recurring contracts.
+pub const FIN_PLAN_STATE_VERSION_4: u8 = 4;
+
+/// Userdata layout version that added monotonic timestamps.
+pub const FIN_PLAN_STATE_VERSION_5: u8 = 5;
+
+/// Userdata layout version that added the contract's creation height.
+pub const FIN_PLAN_STATE_VERSION_6: u8 = 6;
+
+/// Userdata layout version that added the contract's source.
+pub const FIN_PLAN_STATE_VERSION_7: u8 = 7;
+
+/// Userdata layout version written by `FinPlanState::serialize`.
+pub const FIN_PLAN_STATE_VERSION: u8 = 8;
+
+/// Size of the header in front of the serialized state: a little-endian `u64` holding the
+/// state's length in its low 56 bits and the layout version in its high byte. This keeps
+/// the header the same shape as the original, untagged length prefix.
+const FIN_PLAN_STATE_HEADER_SIZE: usize = 8;
+
+/// Where the layout version sits in the header.
+const FIN_PLAN_STATE_VERSION_SHIFT: u64 = 56;
+
+/// The bits of the header that hold the state's length.
+const FIN_PLAN_STATE_LEN_MASK: u64 = (1 << FIN_PLAN_STATE_VERSION_SHIFT) - 1;
+
+/// Size of the balance written by `Instruction::GetBalance`, a bincode encoded `i64` at the
+/// start of the output account's userdata.
+pub const BALANCE_OUTPUT_SIZE: usize = 8;
+
+/// The budget program's id. `FinPlanState::id` and `FinPlanState::check_id` are the only
+/// readers; use them rather than this directly.
 pub const BUDGET_PROGRAM_ID: [u8; 32] = [
     1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
+
+lazy_static! {
+    /// `BUDGET_PROGRAM_ID` as a `Pubkey`, built once for `id` and `check_id` to share.
+    static ref BUDGET_PROGRAM_PUBKEY: Pubkey = Pubkey::new(&BUDGET_PROGRAM_ID);
+}
+
 impl FinPlanState {
     fn is_pending(&self) -> bool {
         self.pending_fin_plan != None
     }
+
+    /// The payment the pending plan is set up to make, or `None` if there is no pending
+    /// plan or it splits its tokens between several payments. A plan that can also pay
+    /// out a refund is summarized by the payment to its destination.
+    pub fn summary(&self) -> Option<ContractSummary> {
+        let (payment, release) = match self.pending_fin_plan.as_ref()? {
+            FinPlan::After(condition, payment)
+            | FinPlan::Or((condition, payment), _)
+            | FinPlan::CancelableAfter {
+                pay: (condition, payment),
+                ..
+            } => (payment.clone(), ReleaseCondition::from(condition)),
+            FinPlan::And(first, second, payment) => (
+                payment.clone(),
+                ReleaseCondition::All(vec![
+                    ReleaseCondition::from(first),
+                    ReleaseCondition::from(second),
+                ]),
+            ),
+            FinPlan::Multisig {
+                signers,
+                threshold,
+                payment,
+                ..
+            } => (
+                payment.clone(),
+                ReleaseCondition::OnSignatures {
+                    signers: signers.clone(),
+                    threshold: *threshold,
+                },
+            ),
+            // Every tranche goes to the same key, so report what is left to release
+            FinPlan::Installments {
+                conditions,
+                payment,
+                remaining,
+            } => (
+                Payment {
+                    tokens: *remaining,
+                    to: payment.to,
+                },
+                ReleaseCondition::from(conditions.first()?),
+            ),
+            FinPlan::Pay(_) | FinPlan::Split(_) | FinPlan::AfterSplit(..) | FinPlan::Vesting(_) => {
+                return None
+            }
+        };
+        Some(ContractSummary {
+            to: payment.to,
+            tokens: payment.tokens,
+            release,
+        })
+    }
+    /// When the pending plan stops paying its destination and can be refunded instead, if
+    /// it ever does.
+    pub fn expiry(&self) -> Option<DateTime<Utc>> {
+        match (&self.pending_fin_plan, self.refund) {
+            (Some(_), Some(_)) => self.expiry,
+            _ => None,
+        }
+    }
+    /// Every condition the pending plan is still waiting on, including each signature a
+    /// multisig plan hasn't collected yet. Empty once the contract has paid out.
+    pub fn pending_conditions(&self) -> Vec<Condition> {
+        match self.pending_fin_plan {
+            Some(FinPlan::Multisig {
+                ref signers,
+                ref signed,
+                ..
+            }) => signers
+                .iter()
+                .filter(|signer| !signed.contains(signer))
+                .map(|signer| Condition::Signature(*signer))
+                .collect(),
+            Some(ref fin_plan) => fin_plan.conditions().into_iter().cloned().collect(),
+            None => vec![],
+        }
+    }
     pub fn id() -> Pubkey {
-        Pubkey::new(&BUDGET_PROGRAM_ID)
+        *BUDGET_PROGRAM_PUBKEY
     }
+    /// Compares in constant time, so dispatch timing says nothing about `program_id`.
     pub fn check_id(program_id: &Pubkey) -> bool {
-        program_id.as_ref() == BUDGET_PROGRAM_ID
+        constant_time::verify_slices_are_equal(program_id.as_ref(), BUDGET_PROGRAM_PUBKEY.as_ref())
+            .is_ok()
     }
 
     /// Process a Witness Signature. Any payment plans waiting on this signature
-    /// will progress one step.
+    /// will progress one step. Returns the payments made if the plan completed.
     fn apply_signature(
         &mut self,
         keys: &[Pubkey],
+        roles: AccountRoles,
         account: &mut [Account],
-    ) -> Result<(), FinPlanError> {
-        let mut final_payment = None;
+    ) -> Result<Vec<Payment>, FinPlanError> {
+        if keys.len() <= cmp::max(roles.signer, roles.contract) {
+            trace!("missing keys");
+            return Err(FinPlanError::MissingKeys);
+        }
+        let signer = keys[roles.signer];
+        self.check_duplicate_witness(&Witness::Signature, &signer)?;
+        let mut final_payments = None;
+        let mut advanced = false;
         if let Some(ref mut fin_plan) = self.pending_fin_plan {
-            fin_plan.apply_witness(&Witness::Signature, &keys[0]);
-            final_payment = fin_plan.final_payment();
+            if fin_plan.is_premature_refund(&Witness::Signature, &signer) {
+                trace!("refund deadline has not passed");
+                return Err(FinPlanError::FailedWitness);
+            }
+            let before = fin_plan.clone();
+            fin_plan.apply_witness(&Witness::Signature, &signer);
+            advanced = *fin_plan != before;
+            final_payments = fin_plan.final_payments();
         }
+        self.record_witness(Witness::Signature, signer, advanced)?;
 
-        if let Some(payment) = final_payment {
-            if keys.len() < 2 || payment.to != keys[2] {
-                trace!("destination missing");
-                return Err(FinPlanError::DestinationMissing(payment.to));
+        match final_payments {
+            Some(payments) => {
+                self.apply_payments(keys, roles, account, &payments)?;
+                // A signature only ever completes a recurring contract by cancelling it
+                self.remaining_count = 0;
+                self.next_due = None;
+                Ok(payments)
             }
-            self.pending_fin_plan = None;
-            account[1].tokens -= payment.tokens;
-            account[2].tokens += payment.tokens;
+            None => Ok(vec![]),
         }
-        Ok(())
     }
 
     /// Process a Witness Timestamp. Any payment plans waiting on this timestamp
-    /// will progress one step.
+    /// will progress one step. Returns the payments made if the plan completed.
     fn apply_timestamp(
         &mut self,
         keys: &[Pubkey],
+        roles: AccountRoles,
         accounts: &mut [Account],
         dt: DateTime<Utc>,
-    ) -> Result<(), FinPlanError> {
+    ) -> Result<Vec<Payment>, FinPlanError> {
+        if keys.len() <= cmp::max(roles.signer, roles.contract) {
+            trace!("missing keys");
+            return Err(FinPlanError::MissingKeys);
+        }
+        let signer = keys[roles.signer];
+        // Only a key the plan already trusts for timestamps may expire the contract or move
+        // its clock
+        let is_time_source = self.pending_fin_plan.as_ref().map_or(false, |fin_plan| {
+            fin_plan
+                .conditions()
+                .into_iter()
+                .flat_map(Condition::leaves)
+                .any(|cond| match cond {
+                    Condition::Timestamp(_, pubkey) => *pubkey == signer,
+                    _ => false,
+                })
+        });
+        if is_time_source {
+            self.check_monotonic_timestamp(dt)?;
+        }
+        self.check_duplicate_witness(&Witness::Timestamp(dt), &signer)?;
         // Check to see if any timelocked transactions can be completed.
-        let mut final_payment = None;
+        let mut final_payments = None;
+        let mut drawdown = vec![];
+        let mut advanced = false;
+
+        if let Some(ref mut fin_plan) = self.pending_fin_plan {
+            match (self.expiry, self.refund) {
+                (Some(expiry), Some(refund)) if is_time_source && dt >= expiry => {
+                    trace!("contract expired");
+                    final_payments = Some(vec![Payment {
+                        tokens: fin_plan.tokens(),
+                        to: refund,
+                    }]);
+                    advanced = true;
+                }
+                _ => {
+                    let before = fin_plan.clone();
+                    drawdown = fin_plan.apply_drawdown(&Witness::Timestamp(dt), &signer);
+                    if drawdown.is_empty() {
+                        fin_plan.apply_witness(&Witness::Timestamp(dt), &signer);
+                        final_payments = fin_plan.final_payments();
+                    }
+                    advanced = *fin_plan != before;
+                }
+            }
+        }
+        self.record_witness(Witness::Timestamp(dt), signer, advanced)?;
+
+        // Tranches leave the rest of the plan pending until it is drained
+        if !drawdown.is_empty() {
+            Self::move_payments(
+                keys,
+                accounts,
+                &drawdown,
+                Some(roles.contract),
+                roles.first_destination,
+            )?;
+            if self.pending_fin_plan.as_ref().map_or(false, FinPlan::is_drained) {
+                self.pending_fin_plan = None;
+                self.witnessed.clear();
+            }
+            return Ok(drawdown);
+        }
+
+        match final_payments {
+            Some(payments) => {
+                self.apply_payments(keys, roles, accounts, &payments)?;
+                self.schedule_next_period();
+                Ok(payments)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Process a Witness BlockHeight. Any payment plans waiting on this entry height
+    /// will progress one step. Returns the payments made if the plan completed.
+    fn apply_block_height(
+        &mut self,
+        keys: &[Pubkey],
+        roles: AccountRoles,
+        accounts: &mut [Account],
+        height: u64,
+        entry_height: u64,
+    ) -> Result<Vec<Payment>, FinPlanError> {
+        if keys.len() <= cmp::max(roles.signer, roles.contract) {
+            trace!("missing keys");
+            return Err(FinPlanError::MissingKeys);
+        }
+        if height > entry_height {
+            trace!("block height {} not reached, at {}", height, entry_height);
+            return Err(FinPlanError::FutureBlockHeight(height));
+        }
+        let signer = keys[roles.signer];
+        self.check_duplicate_witness(&Witness::BlockHeight(height), &signer)?;
+        let mut final_payments = None;
+        let mut advanced = false;
 
         if let Some(ref mut fin_plan) = self.pending_fin_plan {
-            fin_plan.apply_witness(&Witness::Timestamp(dt), &keys[0]);
-            final_payment = fin_plan.final_payment();
+            let before = fin_plan.clone();
+            fin_plan.apply_witness(&Witness::BlockHeight(height), &signer);
+            advanced = *fin_plan != before;
+            final_payments = fin_plan.final_payments();
+        }
+        self.record_witness(Witness::BlockHeight(height), signer, advanced)?;
+
+        match final_payments {
+            Some(payments) => {
+                self.apply_payments(keys, roles, accounts, &payments)?;
+                Ok(payments)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Credit the contract with `tokens` the source in `keys` was already debited, and grow
+    /// the pending plan's payout to match. Only the key that funded the contract may top it
+    /// up, and only if the plan pays a single amount.
+    fn add_funds(
+        &mut self,
+        keys: &[Pubkey],
+        roles: AccountRoles,
+        accounts: &mut [Account],
+        tokens: u64,
+    ) -> Result<(), FinPlanError> {
+        let signer = keys[roles.signer];
+        if self.source != Some(signer) {
+            trace!("top-up from {} isn't from the source", signer);
+            return Err(FinPlanError::NotContractSource(signer));
+        }
+        // A recurring contract rebuilds its plan every period, which would drop the top-up
+        if self.recurrence.is_some() {
+            return Err(FinPlanError::FixedPlanAmount);
+        }
+        let balance = accounts[roles.contract]
+            .tokens
+            .checked_add(Self::account_tokens(tokens)?)
+            .ok_or(FinPlanError::TokenOverflow(keys[roles.contract]))?;
+        let added = match self.pending_fin_plan {
+            Some(ref mut fin_plan) => fin_plan.add_tokens(tokens),
+            None => false,
+        };
+        if !added {
+            trace!("plan amount is fixed");
+            return Err(FinPlanError::FixedPlanAmount);
+        }
+        accounts[roles.contract].tokens = balance;
+        Ok(())
+    }
+
+    /// Move the resolved `payments` from the contract to their destinations and retire the
+    /// pending plan.
+    fn apply_payments(
+        &mut self,
+        keys: &[Pubkey],
+        roles: AccountRoles,
+        accounts: &mut [Account],
+        payments: &[Payment],
+    ) -> Result<(), FinPlanError> {
+        Self::move_payments(
+            keys,
+            accounts,
+            payments,
+            Some(roles.contract),
+            roles.first_destination,
+        )?;
+        self.pending_fin_plan = None;
+        self.witnessed.clear();
+        Ok(())
+    }
+
+    /// Once a period of a recurring contract has been paid, make the next one pending. The
+    /// contract stops being pending after its last period.
+    fn schedule_next_period(&mut self) {
+        if self.remaining_count == 0 {
+            return;
+        }
+        self.remaining_count -= 1;
+        self.next_due = match (self.next_due, &self.recurrence) {
+            (Some(due), Some(recurrence)) if self.remaining_count > 0 => {
+                due.checked_add_signed(Duration::seconds(recurrence.interval as i64))
+            }
+            _ => None,
+        };
+        self.pending_fin_plan = self.recurring_period_plan();
+    }
+
+    /// The plan for the current period of a recurring contract: pay one period to the
+    /// destination once it is due, or refund every remaining period if the source cancels.
+    fn recurring_period_plan(&self) -> Option<FinPlan> {
+        match (&self.recurrence, self.next_due, self.refund) {
+            (Some(recurrence), Some(due), Some(source)) => Some(FinPlan::Or(
+                (
+                    Condition::Timestamp(due, recurrence.dt_pubkey),
+                    Payment {
+                        tokens: recurrence.tokens,
+                        to: recurrence.to,
+                    },
+                ),
+                (
+                    Condition::Signature(source),
+                    Payment {
+                        tokens: recurrence.tokens * self.remaining_count,
+                        to: source,
+                    },
+                ),
+            )),
+            _ => None,
+        }
+    }
+
+    /// The due date of the last of `count` periods spaced `interval` seconds apart from
+    /// `start`, or `None` if it can't be represented.
+    fn last_due_date(start: DateTime<Utc>, interval: u64, count: u64) -> Option<DateTime<Utc>> {
+        let secs = interval.checked_mul(count.checked_sub(1)?)?;
+        // `Duration::seconds` panics past this
+        if secs > i64::max_value() as u64 / 1000 {
+            return None;
+        }
+        start.checked_add_signed(Duration::seconds(secs as i64))
+    }
+
+    /// Remember `dt` as the latest timestamp from the plan's time source, failing if it is
+    /// earlier than one already applied. A witness could otherwise turn the clock back to
+    /// dodge or trigger a date condition. Other keys' timestamps are never remembered, so
+    /// they can't push the clock past what the time source will report.
+    fn check_monotonic_timestamp(&mut self, dt: DateTime<Utc>) -> Result<(), FinPlanError> {
+        if let Some(last_timestamp) = self.last_timestamp {
+            if dt < last_timestamp {
+                trace!("timestamp {} is before {}", dt, last_timestamp);
+                return Err(FinPlanError::NonMonotonicTimestamp);
+            }
+        }
+        self.last_timestamp = Some(dt);
+        Ok(())
+    }
+
+    /// Fail if `from` already presented `witness`. Replaying a witness would otherwise
+    /// advance plans that accumulate witnesses more than once.
+    fn check_duplicate_witness(
+        &self,
+        witness: &Witness,
+        from: &Pubkey,
+    ) -> Result<(), FinPlanError> {
+        if self
+            .witnessed
+            .iter()
+            .any(|(w, key)| w == witness && key == from)
+        {
+            trace!("duplicate witness");
+            return Err(FinPlanError::DuplicateWitness);
+        }
+        Ok(())
+    }
+
+    /// Remember that `from` presented `witness` if it `advanced` the pending plan, and fail
+    /// if it didn't. Only witnesses that did something are kept, so `witnessed` can't be
+    /// grown past what the userdata holds by witnesses the plan ignores. Failing, rather than
+    /// accepting the witness as a no-op, tells the sender it was early or unrelated.
+    fn record_witness(
+        &mut self,
+        witness: Witness,
+        from: Pubkey,
+        advanced: bool,
+    ) -> Result<(), FinPlanError> {
+        if !advanced {
+            trace!("witness doesn't advance the plan");
+            return Err(FinPlanError::UnusedWitness);
         }
+        self.witnessed.push((witness, from));
+        Ok(())
+    }
 
-        if let Some(payment) = final_payment {
-            if keys.len() < 2 || payment.to != keys[2] {
-                trace!("destination missing");
-                return Err(FinPlanError::DestinationMissing(payment.to));
+    /// Credit each of `payments` to the account whose key matches `payment.to`, searching
+    /// `keys[first_destination..]`, and debit the total from `accounts[source]` if given.
+    /// Either every payment is applied or, on error, no account is modified.
+    fn move_payments(
+        keys: &[Pubkey],
+        accounts: &mut [Account],
+        payments: &[Payment],
+        source: Option<usize>,
+        first_destination: usize,
+    ) -> Result<(), FinPlanError> {
+        let mut tokens: Vec<i64> = accounts.iter().map(|account| account.tokens).collect();
+        for payment in payments {
+            let to = keys
+                .iter()
+                .take(accounts.len())
+                .skip(first_destination)
+                .position(|key| *key == payment.to)
+                .map(|i| i + first_destination);
+            let to = match to {
+                Some(to) => to,
+                None => {
+                    trace!("destination missing");
+                    return Err(FinPlanError::DestinationMissing(payment.to));
+                }
+            };
+            let amount = Self::account_tokens(payment.tokens)?;
+            if let Some(source) = source {
+                tokens[source] = tokens[source]
+                    .checked_sub(amount)
+                    .ok_or(FinPlanError::TokenOverflow(keys[source]))?;
             }
-            self.pending_fin_plan = None;
-            accounts[1].tokens -= payment.tokens;
-            accounts[2].tokens += payment.tokens;
+            tokens[to] = tokens[to]
+                .checked_add(amount)
+                .ok_or(FinPlanError::TokenOverflow(keys[to]))?;
+        }
+        for (account, tokens) in accounts.iter_mut().zip(tokens) {
+            account.tokens = tokens;
         }
         Ok(())
     }
 
+    /// Split `tokens` between `recipients` by their percentage share. Each recipient gets
+    /// its share rounded down, then the tokens left over go one each to the recipients
+    /// with the largest remainders, earlier recipients first on a tie, so the payments
+    /// always sum to `tokens`.
+    fn split_payments(
+        tokens: u64,
+        recipients: &[(Pubkey, u8)],
+    ) -> Result<Vec<Payment>, FinPlanError> {
+        let total: u64 = recipients.iter().map(|(_, share)| u64::from(*share)).sum();
+        if total != 100 {
+            trace!("split shares sum to {}", total);
+            return Err(FinPlanError::InvalidSplit);
+        }
+        // tokens * share / 100 without overflowing: split tokens into hundreds and the rest
+        let (hundreds, rest) = (tokens / 100, tokens % 100);
+        let mut payments = vec![];
+        let mut remainders = vec![];
+        for (i, (to, share)) in recipients.iter().enumerate() {
+            let share = u64::from(*share);
+            payments.push(Payment {
+                tokens: hundreds * share + rest * share / 100,
+                to: *to,
+            });
+            remainders.push((rest * share % 100, i));
+        }
+        let paid: u64 = payments.iter().map(|payment| payment.tokens).sum();
+        remainders.sort_by(|(a, i), (b, j)| b.cmp(a).then(i.cmp(j)));
+        for &(_, i) in remainders.iter().take((tokens - paid) as usize) {
+            payments[i].tokens += 1;
+        }
+        Ok(payments)
+    }
+
+    /// Convert a plan's token amount to the signed amount accounts hold. Plans serialized
+    /// before token amounts became unsigned use the same encoding, so a negative amount
+    /// from one of those shows up here as a value past `i64::max_value()`.
+    fn account_tokens(tokens: u64) -> Result<i64, FinPlanError> {
+        if tokens > i64::max_value() as u64 {
+            trace!("tokens out of range");
+            Err(FinPlanError::TokensOutOfRange)
+        } else {
+            Ok(tokens as i64)
+        }
+    }
+
     /// Deduct tokens from the source account if it has sufficient funds and the contract isn't
     /// pending
+    /// Creating a contract also costs the source `fee`, if any, credited to its collector.
     fn apply_debits_to_fin_plan_state(
         tx: &Transaction,
         accounts: &mut [Account],
         instruction: &Instruction,
+        fee: Option<&ContractFee>,
     ) -> Result<(), FinPlanError> {
         {
             // if the source account userdata is not empty, this is a pending contract
@@ -109,17 +1181,78 @@ impl FinPlanState {
                 trace!("source is pending");
                 return Err(FinPlanError::SourceIsPendingContract(tx.keys[0]));
             }
-            if let Instruction::NewContract(contract) = &instruction {
-                if contract.tokens < 0 {
-                    trace!("negative tokens");
-                    return Err(FinPlanError::NegativeTokens);
+            // A contract that moves nothing would only tie up the contract account
+            match instruction {
+                Instruction::NewContract(Contract { tokens: 0, .. })
+                | Instruction::NewMultisigContract { tokens: 0, .. }
+                | Instruction::NewRecurringContract { tokens: 0, .. }
+                | Instruction::NewSplitContract { tokens: 0, .. }
+                | Instruction::AddFunds(0) => {
+                    trace!("contract has no tokens");
+                    return Err(FinPlanError::ZeroTokens);
                 }
-
-                if accounts[0].tokens < contract.tokens {
+                _ => (),
+            }
+            let tokens = match instruction {
+                Instruction::NewContract(contract) => Some(contract.tokens),
+                Instruction::NewMultisigContract { tokens, .. }
+                | Instruction::NewSplitContract { tokens, .. } => Some(*tokens),
+                Instruction::NewRecurringContract { tokens, count, .. } => Some(
+                    tokens
+                        .checked_mul(*count)
+                        .ok_or(FinPlanError::TokensOutOfRange)?,
+                ),
+                Instruction::AddFunds(tokens) => Some(*tokens),
+                _ => None,
+            };
+            if let Some(tokens) = tokens {
+                let tokens = Self::account_tokens(tokens)?;
+                // A top-up creates nothing, so there is no fee for it
+                let fee = match (instruction, fee) {
+                    (Instruction::AddFunds(_), _) | (_, None) => None,
+                    (_, Some(fee)) if fee.tokens == 0 => None,
+                    (_, Some(fee)) => Some(fee),
+                };
+                let (fee, collector) = match fee {
+                    Some(fee) => {
+                        let collector = tx
+                            .keys
+                            .iter()
+                            .skip(2)
+                            .position(|key| *key == fee.collector)
+                            .map(|index| index + 2)
+                            .filter(|index| *index < accounts.len());
+                        match collector {
+                            Some(collector) => (Self::account_tokens(fee.tokens)?, collector),
+                            None => {
+                                trace!("missing fee collector");
+                                return Err(FinPlanError::MissingKeys);
+                            }
+                        }
+                    }
+                    None => (0, 0),
+                };
+                let total = tokens
+                    .checked_add(fee)
+                    .ok_or(FinPlanError::TokensOutOfRange)?;
+                if accounts[0].tokens < total {
                     trace!("insufficient funds");
-                    return Err(FinPlanError::InsufficientFunds(tx.keys[0]));
+                    return Err(FinPlanError::InsufficientFunds {
+                        who: tx.keys[0],
+                        needed: total,
+                        available: accounts[0].tokens,
+                    });
                 } else {
-                    accounts[0].tokens -= contract.tokens;
+                    if fee > 0 {
+                        accounts[collector].tokens = accounts[collector]
+                            .tokens
+                            .checked_add(fee)
+                            .ok_or(FinPlanError::TokenOverflow(tx.keys[collector]))?;
+                    }
+                    accounts[0].tokens = accounts[0]
+                        .tokens
+                        .checked_sub(total)
+                        .ok_or(FinPlanError::TokenOverflow(tx.keys[0]))?;
                 }
             };
         }
@@ -132,102 +1265,530 @@ impl FinPlanState {
         tx: &Transaction,
         accounts: &mut [Account],
         instruction: &Instruction,
+        entry_height: u64,
+        strict: bool,
+        on_complete: Option<&mut Vec<CompletionRecord>>,
     ) -> Result<(), FinPlanError> {
+        let roles = AccountRoles::of(instruction);
         match instruction {
             Instruction::NewContract(contract) => {
+                Self::account_tokens(contract.tokens)?;
+                for payment in contract.fin_plan.payments() {
+                    Self::account_tokens(payment.tokens)?;
+                }
+                if !contract.fin_plan.conditions().iter().all(|cond| cond.is_valid()) {
+                    trace!("plan waits on an empty condition");
+                    return Err(FinPlanError::EmptyCondition);
+                }
+                if let Some(ref memo) = contract.memo {
+                    if memo.len() > MAX_MEMO_SIZE {
+                        trace!("memo is {} bytes", memo.len());
+                        return Err(FinPlanError::MemoTooLarge);
+                    }
+                }
                 let fin_plan = contract.fin_plan.clone();
-                if let Some(payment) = fin_plan.final_payment() {
-                    accounts[1].tokens += payment.tokens;
-                    Ok(())
-                } else {
-                    let existing = Self::deserialize(&accounts[1].userdata).ok();
-                    if Some(true) == existing.map(|x| x.initialized) {
-                        trace!("contract already exists");
-                        Err(FinPlanError::ContractAlreadyExists(tx.keys[1]))
-                    } else {
-                        let mut state = FinPlanState::default();
-                        state.pending_fin_plan = Some(fin_plan);
-                        accounts[1].tokens += contract.tokens;
-                        state.initialized = true;
-                        state.serialize(&mut accounts[1].userdata)
+                if let Some(payments) = fin_plan.final_payments() {
+                    if strict {
+                        trace!("plan pays out immediately");
+                        return Err(FinPlanError::NotAnEscrow);
                     }
+                    // The source was already debited, only credit the destinations
+                    let first_destination = roles.first_destination;
+                    Self::move_payments(&tx.keys, accounts, &payments, None, first_destination)
+                } else {
+                    Self::new_pending_contract(
+                        tx,
+                        accounts,
+                        fin_plan,
+                        contract.tokens,
+                        contract.expiry,
+                        contract.memo.clone(),
+                        entry_height,
+                    )
+                }
+            }
+            Instruction::NewMultisigContract {
+                signers,
+                threshold,
+                tokens,
+                to,
+            } => {
+                if *threshold == 0 || *threshold > signers.len() as u64 {
+                    trace!("invalid multisig threshold");
+                    return Err(FinPlanError::InvalidThreshold);
                 }
+                let fin_plan =
+                    FinPlan::new_multisig_payment(signers.clone(), *threshold, *tokens, *to);
+                Self::new_pending_contract(
+                    tx,
+                    accounts,
+                    fin_plan,
+                    *tokens,
+                    None,
+                    None,
+                    entry_height,
+                )
+            }
+            Instruction::NewRecurringContract {
+                start,
+                interval,
+                count,
+                tokens,
+                dt_pubkey,
+                to,
+            } => {
+                if *interval == 0 || Self::last_due_date(*start, *interval, *count).is_none() {
+                    trace!("invalid recurring schedule");
+                    return Err(FinPlanError::InvalidRecurrence);
+                }
+                let total = tokens
+                    .checked_mul(*count)
+                    .ok_or(FinPlanError::TokensOutOfRange)?;
+                let mut state = FinPlanState::default();
+                state.refund = Some(tx.keys[0]);
+                state.recurrence = Some(Recurrence {
+                    interval: *interval,
+                    tokens: *tokens,
+                    dt_pubkey: *dt_pubkey,
+                    to: *to,
+                });
+                state.next_due = Some(*start);
+                state.remaining_count = *count;
+                state.pending_fin_plan = state.recurring_period_plan();
+                Self::initialize_contract(tx, accounts, state, total, entry_height)
             }
             Instruction::ApplyTimestamp(dt) => {
-                if let Ok(mut state) = Self::deserialize(&accounts[1].userdata) {
+                Self::check_version(&accounts[roles.contract].userdata)?;
+                if let Ok(mut state) = Self::deserialize(&accounts[roles.contract].userdata) {
                     if !state.is_pending() {
-                        Err(FinPlanError::ContractNotPending(tx.keys[1]))
+                        Err(FinPlanError::ContractNotPending(tx.keys[roles.contract]))
                     } else if !state.initialized {
                         trace!("contract is uninitialized");
-                        Err(FinPlanError::UninitializedContract(tx.keys[1]))
+                        Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
                     } else {
                         trace!("apply timestamp");
-                        state.apply_timestamp(&tx.keys, accounts, *dt)?;
+                        let payments = state.apply_timestamp(&tx.keys, roles, accounts, *dt)?;
+                        state.record_completions(
+                            on_complete,
+                            tx,
+                            roles,
+                            &payments,
+                            Witness::Timestamp(*dt),
+                        );
                         trace!("apply timestamp committed");
-                        state.serialize(&mut accounts[1].userdata)
+                        state.serialize(&mut accounts[roles.contract].userdata)
                     }
                 } else {
-                    Err(FinPlanError::UninitializedContract(tx.keys[1]))
+                    Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
                 }
             }
             Instruction::ApplySignature => {
-                if let Ok(mut state) = Self::deserialize(&accounts[1].userdata) {
+                Self::check_version(&accounts[roles.contract].userdata)?;
+                if let Ok(mut state) = Self::deserialize(&accounts[roles.contract].userdata) {
                     if !state.is_pending() {
-                        Err(FinPlanError::ContractNotPending(tx.keys[1]))
+                        Err(FinPlanError::ContractNotPending(tx.keys[roles.contract]))
                     } else if !state.initialized {
                         trace!("contract is uninitialized");
-                        Err(FinPlanError::UninitializedContract(tx.keys[1]))
+                        Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
                     } else {
                         trace!("apply signature");
-                        state.apply_signature(&tx.keys, accounts)?;
+                        let payments = state.apply_signature(&tx.keys, roles, accounts)?;
+                        state.record_completions(
+                            on_complete,
+                            tx,
+                            roles,
+                            &payments,
+                            Witness::Signature,
+                        );
                         trace!("apply signature committed");
-                        state.serialize(&mut accounts[1].userdata)
+                        state.serialize(&mut accounts[roles.contract].userdata)
                     }
                 } else {
-                    Err(FinPlanError::UninitializedContract(tx.keys[1]))
+                    Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
                 }
             }
-            Instruction::NewVote(_vote) => {
-                // TODO: move vote instruction into a different contract
-                trace!("GOT VOTE! last_id={}", tx.last_id);
-                Ok(())
+            Instruction::ApplyBlockHeight(height) => {
+                Self::check_version(&accounts[roles.contract].userdata)?;
+                if let Ok(mut state) = Self::deserialize(&accounts[roles.contract].userdata) {
+                    if !state.is_pending() {
+                        Err(FinPlanError::ContractNotPending(tx.keys[roles.contract]))
+                    } else if !state.initialized {
+                        trace!("contract is uninitialized");
+                        Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
+                    } else {
+                        trace!("apply block height");
+                        let payments = state.apply_block_height(
+                            &tx.keys,
+                            roles,
+                            accounts,
+                            *height,
+                            entry_height,
+                        )?;
+                        state.record_completions(
+                            on_complete,
+                            tx,
+                            roles,
+                            &payments,
+                            Witness::BlockHeight(*height),
+                        );
+                        trace!("apply block height committed");
+                        state.serialize(&mut accounts[roles.contract].userdata)
+                    }
+                } else {
+                    Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
+                }
+            }
+            Instruction::GetBalance => {
+                if tx.keys.len() < 3 || accounts.len() < 3 {
+                    trace!("missing output account");
+                    return Err(FinPlanError::MissingKeys);
+                }
+                let balance = Self::get_balance(&accounts[1])?;
+                Self::write_balance(balance, &mut accounts[2].userdata)
+            }
+            Instruction::NewSplitContract { recipients, tokens } => {
+                let payments = Self::split_payments(*tokens, recipients)?;
+                // The source was already debited, only credit the recipients
+                Self::move_payments(&tx.keys, accounts, &payments, None, roles.first_destination)
+            }
+            Instruction::AddFunds(tokens) => {
+                Self::check_version(&accounts[roles.contract].userdata)?;
+                if let Ok(mut state) = Self::deserialize(&accounts[roles.contract].userdata) {
+                    if !state.is_pending() {
+                        Err(FinPlanError::ContractNotPending(tx.keys[roles.contract]))
+                    } else if !state.initialized {
+                        trace!("contract is uninitialized");
+                        Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
+                    } else {
+                        trace!("add funds");
+                        state.add_funds(&tx.keys, roles, accounts, *tokens)?;
+                        trace!("add funds committed");
+                        state.serialize(&mut accounts[roles.contract].userdata)
+                    }
+                } else {
+                    Err(FinPlanError::UninitializedContract(tx.keys[roles.contract]))
+                }
             }
         }
     }
-    fn serialize(&self, outx_creatort: &mut [u8]) -> Result<(), FinPlanError> {
-        let len = serialized_size(self).unwrap() as u64;
-        if outx_creatort.len() < len as usize {
-            warn!(
+    /// Append a `CompletionRecord` to `on_complete` for each of the `payments` the contract
+    /// in `tx.keys` made after its signer presented `witness`.
+    fn record_completions(
+        &self,
+        on_complete: Option<&mut Vec<CompletionRecord>>,
+        tx: &Transaction,
+        roles: AccountRoles,
+        payments: &[Payment],
+        witness: Witness,
+    ) {
+        if let Some(records) = on_complete {
+            for payment in payments {
+                records.push(CompletionRecord {
+                    contract: tx.keys[roles.contract],
+                    source: self.refund,
+                    destination: payment.to,
+                    tokens: payment.tokens,
+                    witness: witness.clone(),
+                    witness_key: tx.keys[roles.signer],
+                    memo: self.memo.clone(),
+                });
+            }
+        }
+    }
+
+    /// Store `fin_plan` as the pending plan of the contract in `accounts[1]`, along with
+    /// `memo`. If `expiry` is set, the source in `keys[0]` is remembered as the refund key.
+    fn new_pending_contract(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        fin_plan: FinPlan,
+        tokens: u64,
+        expiry: Option<DateTime<Utc>>,
+        memo: Option<Vec<u8>>,
+        entry_height: u64,
+    ) -> Result<(), FinPlanError> {
+        let mut state = FinPlanState::default();
+        state.pending_fin_plan = Some(fin_plan);
+        state.memo = memo;
+        if expiry.is_some() {
+            state.expiry = expiry;
+            state.refund = Some(tx.keys[0]);
+        }
+        Self::initialize_contract(tx, accounts, state, tokens, entry_height)
+    }
+
+    /// Fund the contract in `accounts[1]` with `tokens` from the source in `keys[0]` and store
+    /// `state` in it, created at `entry_height`, unless a contract already lives there.
+    fn initialize_contract(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        mut state: FinPlanState,
+        tokens: u64,
+        entry_height: u64,
+    ) -> Result<(), FinPlanError> {
+        let tokens = Self::account_tokens(tokens)?;
+        let existing = Self::deserialize(&accounts[1].userdata).ok();
+        if Some(true) == existing.map(|x| x.initialized) {
+            trace!("contract already exists");
+            Err(FinPlanError::ContractAlreadyExists(tx.keys[1]))
+        } else {
+            let balance = accounts[1]
+                .tokens
+                .checked_add(tokens)
+                .ok_or(FinPlanError::TokenOverflow(tx.keys[1]))?;
+            state.initialized = true;
+            state.created_height = Some(entry_height);
+            state.source = Some(tx.keys[0]);
+            // Only fund the contract once its state is stored
+            state.serialize(&mut accounts[1].userdata)?;
+            accounts[1].tokens = balance;
+            Ok(())
+        }
+    }
+
+    /// Userdata space a contract account needs to hold `fin_plan` for its whole life: with
+    /// the refund details of an expiring contract, the longest memo, and with every
+    /// condition of the plan witnessed once. Recurring contracts carry their schedule on top
+    /// of this.
+    pub fn space_required(fin_plan: &FinPlan) -> usize {
+        let state = Self::fully_witnessed(fin_plan);
+        FIN_PLAN_STATE_HEADER_SIZE + serialized_size(&state).unwrap() as usize
+    }
+
+    /// A budget program account holding `tokens`, with the userdata space a contract
+    /// holding `fin_plan` needs, so it can't be made too small for it.
+    pub fn new_contract_account(tokens: i64, fin_plan: &FinPlan) -> Account {
+        Account::new(tokens, Self::space_required(fin_plan), Self::id())
+    }
+
+    /// The largest state a contract holding `fin_plan` grows to.
+    fn fully_witnessed(fin_plan: &FinPlan) -> Self {
+        // Timestamps serialize as text, so use the widest one a witness is likely to send
+        let dt = DateTime::<Utc>::from_utc(
+            NaiveDate::from_ymd(9999, 12, 31).and_hms_nano(23, 59, 59, 999_999_999),
+            Utc,
+        );
+        let witnessed = fin_plan
+            .conditions()
+            .into_iter()
+            .flat_map(Condition::leaves)
+            .filter_map(|condition| match condition {
+                Condition::Timestamp(_, key) => Some((Witness::Timestamp(dt), *key)),
+                Condition::Signature(key) => Some((Witness::Signature, *key)),
+                // One signer is enough, and every key serializes to the same size
+                Condition::SignatureAnyOf(keys) => {
+                    keys.first().map(|key| (Witness::Signature, *key))
+                }
+                Condition::BlockHeight(_) => {
+                    Some((Witness::BlockHeight(u64::max_value()), Pubkey::default()))
+                }
+                // Leaves are never `All`
+                Condition::All(_) => None,
+            }).collect();
+        FinPlanState {
+            initialized: true,
+            pending_fin_plan: Some(fin_plan.clone()),
+            expiry: Some(dt),
+            refund: Some(Pubkey::default()),
+            witnessed,
+            last_timestamp: Some(dt),
+            created_height: Some(u64::max_value()),
+            source: Some(Pubkey::default()),
+            memo: Some(vec![0; MAX_MEMO_SIZE]),
+            ..FinPlanState::default()
+        }
+    }
+
+    fn serialize(&self, outx_creatort: &mut [u8]) -> Result<(), FinPlanError> {
+        let len = serialized_size(self).unwrap() as u64;
+        if outx_creatort.len() < FIN_PLAN_STATE_HEADER_SIZE + len as usize {
+            warn!(
                 "{} bytes required to serialize, only have {} bytes",
-                len,
+                FIN_PLAN_STATE_HEADER_SIZE + len as usize,
                 outx_creatort.len()
             );
             return Err(FinPlanError::UserdataTooSmall);
         }
+        let header = len | u64::from(FIN_PLAN_STATE_VERSION) << FIN_PLAN_STATE_VERSION_SHIFT;
         {
-            let writer = io::BufWriter::new(&mut outx_creatort[..8]);
-            serialize_into(writer, &len).unwrap();
+            let writer = io::BufWriter::new(&mut outx_creatort[..FIN_PLAN_STATE_HEADER_SIZE]);
+            serialize_into(writer, &header).unwrap();
         }
 
         {
-            let writer = io::BufWriter::new(&mut outx_creatort[8..8 + len as usize]);
+            let writer = io::BufWriter::new(
+                &mut outx_creatort
+                    [FIN_PLAN_STATE_HEADER_SIZE..FIN_PLAN_STATE_HEADER_SIZE + len as usize],
+            );
             serialize_into(writer, self).unwrap();
         }
         Ok(())
     }
 
+    /// The layout version and state length in the header of `input`, if it has one.
+    fn read_header(input: &[u8]) -> Option<(u8, u64)> {
+        if input.len() < FIN_PLAN_STATE_HEADER_SIZE {
+            return None;
+        }
+        let header: u64 = deserialize(&input[..FIN_PLAN_STATE_HEADER_SIZE]).unwrap();
+        Some((
+            (header >> FIN_PLAN_STATE_VERSION_SHIFT) as u8,
+            header & FIN_PLAN_STATE_LEN_MASK,
+        ))
+    }
+
     pub fn deserialize(input: &[u8]) -> bincode::Result<Self> {
-        if input.len() < 8 {
-            return Err(Box::new(bincode::ErrorKind::SizeLimit));
+        let (version, len) = match Self::read_header(input) {
+            Some(header) => header,
+            None => return Err(Box::new(bincode::ErrorKind::SizeLimit)),
+        };
+        let body = &input[FIN_PLAN_STATE_HEADER_SIZE..];
+        match version {
+            FIN_PLAN_STATE_VERSION_0 | FIN_PLAN_STATE_VERSION_1 => {
+                Self::deserialize_body::<FinPlanStateV0>(body, len).map(Into::into)
+            }
+            FIN_PLAN_STATE_VERSION_2 => {
+                Self::deserialize_body::<FinPlanStateV2>(body, len).map(Into::into)
+            }
+            FIN_PLAN_STATE_VERSION_3 => {
+                Self::deserialize_body::<FinPlanStateV3>(body, len).map(Into::into)
+            }
+            FIN_PLAN_STATE_VERSION_4 => {
+                Self::deserialize_body::<FinPlanStateV4>(body, len).map(Into::into)
+            }
+            FIN_PLAN_STATE_VERSION_5 => {
+                Self::deserialize_body::<FinPlanStateV5>(body, len).map(Into::into)
+            }
+            FIN_PLAN_STATE_VERSION_6 => {
+                Self::deserialize_body::<FinPlanStateV6>(body, len).map(Into::into)
+            }
+            FIN_PLAN_STATE_VERSION_7 => {
+                Self::deserialize_body::<FinPlanStateV7>(body, len).map(Into::into)
+            }
+            FIN_PLAN_STATE_VERSION => Self::deserialize_body(body, len),
+            version => Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "unknown FinPlanState version {}",
+                version
+            )))),
         }
-        let len: u64 = deserialize(&input[..8]).unwrap();
-        if len < 2 {
+    }
+
+    fn deserialize_body<T: DeserializeOwned>(body: &[u8], len: u64) -> bincode::Result<T> {
+        // Compare as u64 so a huge `len` can't be truncated by the cast to usize
+        if len < 2 || len > body.len() as u64 {
             return Err(Box::new(bincode::ErrorKind::SizeLimit));
         }
-        if input.len() < 8 + len as usize {
-            return Err(Box::new(bincode::ErrorKind::SizeLimit));
+        deserialize(&body[..len as usize])
+    }
+
+    /// Fail if `userdata` carries a version tag this program doesn't know how to read.
+    fn check_version(userdata: &[u8]) -> Result<(), FinPlanError> {
+        match Self::read_header(userdata) {
+            None
+            | Some((FIN_PLAN_STATE_VERSION_0, _))
+            | Some((FIN_PLAN_STATE_VERSION_1, _))
+            | Some((FIN_PLAN_STATE_VERSION_2, _))
+            | Some((FIN_PLAN_STATE_VERSION_3, _))
+            | Some((FIN_PLAN_STATE_VERSION_4, _))
+            | Some((FIN_PLAN_STATE_VERSION_5, _))
+            | Some((FIN_PLAN_STATE_VERSION_6, _))
+            | Some((FIN_PLAN_STATE_VERSION_7, _))
+            | Some((FIN_PLAN_STATE_VERSION, _)) => Ok(()),
+            Some((version, _)) => {
+                trace!("unknown userdata version {}", version);
+                Err(FinPlanError::UserdataDeserializeFailure)
+            }
+        }
+    }
+
+    /// The contract in `keys[1]` can be neither the source, a witness, nor one of the
+    /// accounts it pays; debiting and crediting the same account in two slots would leave
+    /// its balance depending on which slot was written back last.
+    fn check_distinct_keys(
+        tx: &Transaction,
+        instruction: &Instruction,
+    ) -> Result<(), FinPlanError> {
+        let contract = tx.keys[1];
+        if tx.keys.iter().filter(|key| **key == contract).count() > 1 {
+            trace!("contract key is reused");
+            return Err(FinPlanError::DuplicateKey(contract));
+        }
+        let pays_contract = match instruction {
+            Instruction::NewContract(c) => {
+                c.fin_plan.final_payments().is_none()
+                    && c.fin_plan.payments().iter().any(|payment| payment.to == contract)
+            }
+            Instruction::NewMultisigContract { to, .. }
+            | Instruction::NewRecurringContract { to, .. } => *to == contract,
+            _ => false,
+        };
+        if pays_contract {
+            trace!("contract pays itself");
+            return Err(FinPlanError::DuplicateKey(contract));
+        }
+        Ok(())
+    }
+
+    /// Every key a new contract's plan may pay or wait on a witness from has to be named by
+    /// the transaction that creates it. A plan referencing anyone else could never be
+    /// witnessed to completion, and its tokens would be stranded in the contract.
+    fn check_referenced_keys(
+        tx: &Transaction,
+        instruction: &Instruction,
+    ) -> Result<(), FinPlanError> {
+        let referenced = match instruction {
+            Instruction::NewContract(contract) => contract.fin_plan.keys(),
+            Instruction::NewMultisigContract { signers, to, .. } => {
+                let mut keys = vec![*to];
+                keys.extend(signers.iter().cloned());
+                keys
+            }
+            Instruction::NewRecurringContract { dt_pubkey, to, .. } => vec![*to, *dt_pubkey],
+            _ => vec![],
+        };
+        for key in referenced {
+            if !tx.keys.contains(&key) {
+                trace!("plan references unreferenced key {}", key);
+                return Err(FinPlanError::UnreferencedKey(key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify that every account whose tokens or userdata this instruction manages is owned by
+    /// the budget program. Recipients of an immediate payment are only credited, so they may
+    /// belong to any program.
+    fn check_owners(
+        tx: &Transaction,
+        accounts: &[Account],
+        instruction: &Instruction,
+    ) -> Result<(), FinPlanError> {
+        let (debits_source, owns_contract, owns_output) = match instruction {
+            Instruction::NewContract(contract) => {
+                (true, contract.fin_plan.final_payments().is_none(), false)
+            }
+            Instruction::NewMultisigContract { .. } | Instruction::NewRecurringContract { .. } => {
+                (true, true, false)
+            }
+            Instruction::ApplyTimestamp(_)
+            | Instruction::ApplySignature
+            | Instruction::ApplyBlockHeight(_) => (false, true, false),
+            Instruction::GetBalance => (false, true, true),
+            Instruction::NewSplitContract { .. } => (true, false, false),
+            Instruction::AddFunds(_) => (true, true, false),
+        };
+        let owned = [
+            (debits_source, 0),
+            (owns_contract, 1),
+            (owns_output && accounts.len() > 2, 2),
+        ];
+        for &(check, i) in owned.iter() {
+            if check && !Self::check_id(&accounts[i].program_id) {
+                trace!("account {} has the wrong owner", i);
+                return Err(FinPlanError::WrongProgramOwner(tx.keys[i]));
+            }
         }
-        deserialize(&input[8..8 + len as usize])
+        Ok(())
     }
 
     /// FinPlan DSL contract interface
@@ -239,41 +1800,474 @@ impl FinPlanState {
         tx: &Transaction,
         accounts: &mut [Account],
     ) -> Result<(), FinPlanError> {
+        Self::process_transaction_at_height(tx, accounts, 0)
+    }
+
+    /// Like `process_transaction`, but `entry_height` is the current height of the ledger,
+    /// which bounds the heights an `ApplyBlockHeight` instruction may witness.
+    pub fn process_transaction_at_height(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        entry_height: u64,
+    ) -> Result<(), FinPlanError> {
+        Self::process_transaction_with_completions(tx, accounts, entry_height, None)
+    }
+
+    /// Like `process_transaction`, but reports what each stage of the transaction did.
+    pub fn process_transaction_verbose(
+        tx: &Transaction,
+        accounts: &mut [Account],
+    ) -> Result<TxReceipt, FinPlanError> {
+        Self::process_instruction(tx, accounts, 0, None, false, None)
+    }
+
+    /// Like `process_transaction_with_fee`, but only for escrow: a `NewContract` whose
+    /// plan pays out at once, and so is a plain transfer, fails with `NotAnEscrow`.
+    pub fn process_transaction_strict(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        entry_height: u64,
+        fee: Option<&ContractFee>,
+    ) -> Result<(), FinPlanError> {
+        Self::process_instruction(tx, accounts, entry_height, fee, true, None).map(|_| ())
+    }
+
+    /// Like `process_transaction_at_height`, but creating a contract also costs its source
+    /// `fee`, if any. The transaction has to pass the fee's collector among its keys, or it
+    /// fails with `MissingKeys`.
+    pub fn process_transaction_with_fee(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        entry_height: u64,
+        fee: Option<&ContractFee>,
+    ) -> Result<(), FinPlanError> {
+        Self::process_instruction(tx, accounts, entry_height, fee, false, None).map(|_| ())
+    }
+
+    /// Like `process_transaction_at_height`, but every payment made by a contract that
+    /// completes is also recorded in `on_complete`.
+    pub fn process_transaction_with_completions(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        entry_height: u64,
+        on_complete: Option<&mut Vec<CompletionRecord>>,
+    ) -> Result<(), FinPlanError> {
+        Self::process_instruction(tx, accounts, entry_height, None, false, on_complete)
+            .map(|_| ())
+    }
+
+    fn process_instruction(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        entry_height: u64,
+        fee: Option<&ContractFee>,
+        strict: bool,
+        on_complete: Option<&mut Vec<CompletionRecord>>,
+    ) -> Result<TxReceipt, FinPlanError> {
+        let result = Self::apply_instruction(tx, accounts, entry_height, fee, strict, on_complete);
+        if let Err(ref e) = result {
+            e.record();
+        }
+        result
+    }
+
+    fn apply_instruction(
+        tx: &Transaction,
+        accounts: &mut [Account],
+        entry_height: u64,
+        fee: Option<&ContractFee>,
+        strict: bool,
+        on_complete: Option<&mut Vec<CompletionRecord>>,
+    ) -> Result<TxReceipt, FinPlanError> {
         if let Ok(instruction) = deserialize(&tx.userdata) {
             trace!("process_transaction: {:?}", instruction);
-            Self::apply_debits_to_fin_plan_state(tx, accounts, &instruction)
-                .and_then(|_| Self::apply_credits_to_fin_plan_state(tx, accounts, &instruction))
+            if tx.keys.len() < 2 || accounts.len() < 2 {
+                trace!("missing keys");
+                return Err(FinPlanError::MissingKeys);
+            }
+            Self::check_distinct_keys(tx, &instruction)?;
+            Self::check_referenced_keys(tx, &instruction)?;
+            Self::check_owners(tx, accounts, &instruction)?;
+            // A failed transaction must not move tokens, even if it debited before failing
+            let balances: Vec<i64> = accounts.iter().map(|account| account.tokens).collect();
+            let result =
+                Self::apply_debits_to_fin_plan_state(tx, accounts, &instruction, fee)
+                    .and_then(|_| {
+                        let debited: Vec<i64> =
+                            accounts.iter().map(|account| account.tokens).collect();
+                        Self::apply_credits_to_fin_plan_state(
+                            tx,
+                            accounts,
+                            &instruction,
+                            entry_height,
+                            strict,
+                            on_complete,
+                        )?;
+                        Ok(TxReceipt {
+                            instruction: instruction.clone(),
+                            debited: balances[0] - debited[0],
+                            credited: accounts
+                                .iter()
+                                .zip(&debited)
+                                .map(|(account, tokens)| account.tokens - tokens)
+                                .collect(),
+                            pending: Self::deserialize(&accounts[1].userdata)
+                                .map(|state| state.is_pending())
+                                .unwrap_or(false),
+                        })
+                    });
+            if result.is_err() {
+                for (account, tokens) in accounts.iter_mut().zip(balances) {
+                    account.tokens = tokens;
+                }
+            }
+            result
         } else {
             info!("Invalid transaction userdata: {:?}", tx.userdata);
-            Err(FinPlanError::UserdataDeserializeFailure)
+            Err(Self::userdata_error(&tx.userdata))
         }
     }
 
-    //TODO the contract needs to provide a "get_balance" introspection call of the userdata
-    pub fn get_balance(account: &Account) -> i64 {
-        if let Ok(state) = deserialize(&account.userdata) {
-            let state: FinPlanState = state;
-            if state.is_pending() {
-                0
-            } else {
-                account.tokens
+    /// Process each of `txs` against its own accounts, in order, and report every
+    /// transaction's result. A failed transaction leaves its accounts as they were and
+    /// doesn't stop the ones after it.
+    /// * accounts - the accounts of each transaction, at the same index as the transaction
+    pub fn process_transactions(
+        txs: &[Transaction],
+        accounts: &mut [Vec<Account>],
+    ) -> Vec<Result<(), FinPlanError>> {
+        txs.iter()
+            .enumerate()
+            .map(|(i, tx)| match accounts.get_mut(i) {
+                Some(tx_accounts) => Self::process_transaction(tx, tx_accounts),
+                None => Err(FinPlanError::MissingKeys),
+            }).collect()
+    }
+
+    /// Process `txs` as `process_transaction` would one after another, running transactions
+    /// that share no accounts concurrently.
+    /// * accounts - one account per distinct key, in the order the keys first appear in `txs`
+    ///
+    /// Transactions are grouped in order until one touches an account the group already
+    /// uses; groups then run one after another, so conflicting transactions still apply
+    /// serially.
+    pub fn process_transactions_parallel(
+        txs: &[Transaction],
+        accounts: &mut [Account],
+    ) -> Vec<Result<(), FinPlanError>> {
+        let mut index_of = HashMap::new();
+        let tx_indices: Vec<Vec<usize>> = txs
+            .iter()
+            .map(|tx| {
+                tx.keys
+                    .iter()
+                    .map(|key| {
+                        let next = index_of.len();
+                        *index_of.entry(*key).or_insert(next)
+                    }).collect()
+            }).collect();
+
+        let mut results = Vec::with_capacity(txs.len());
+        let mut start = 0;
+        while start < txs.len() {
+            let mut used = HashSet::new();
+            let mut end = start;
+            while end < txs.len() && tx_indices[end].iter().all(|i| !used.contains(i)) {
+                used.extend(tx_indices[end].iter().cloned());
+                end += 1;
             }
-        } else {
-            account.tokens
+
+            let mut loaded: Vec<Option<Vec<Account>>> = tx_indices[start..end]
+                .iter()
+                .map(|indices| indices.iter().map(|&i| accounts.get(i).cloned()).collect())
+                .collect();
+            let batch_results: Vec<_> = txs[start..end]
+                .par_iter()
+                .zip(loaded.par_iter_mut())
+                .map(|(tx, tx_accounts)| match *tx_accounts {
+                    Some(ref mut tx_accounts) => Self::process_transaction(tx, tx_accounts),
+                    None => Err(FinPlanError::MissingKeys),
+                }).collect();
+
+            for (indices, tx_accounts) in tx_indices[start..end].iter().zip(loaded) {
+                if let Some(tx_accounts) = tx_accounts {
+                    for (&i, account) in indices.iter().zip(tx_accounts) {
+                        accounts[i] = account;
+                    }
+                }
+            }
+            results.extend(batch_results);
+            start = end;
+        }
+        results
+    }
+
+    /// Run `tx` against a scratch copy of `accounts` and report the outcome, leaving
+    /// `accounts` untouched.
+    pub fn simulate_transaction(
+        tx: &Transaction,
+        accounts: &[Account],
+    ) -> Result<SimulationResult, FinPlanError> {
+        let mut scratch = accounts.to_vec();
+        Self::process_transaction(tx, &mut scratch)?;
+        let state = scratch
+            .get(1)
+            .and_then(|account| Self::deserialize(&account.userdata).ok());
+        Ok(SimulationResult {
+            balances: scratch.iter().map(|account| account.tokens).collect(),
+            pending: state.as_ref().map_or(false, FinPlanState::is_pending),
+            state,
+        })
+    }
+
+    /// Tell an instruction from a newer program apart from userdata that is just malformed.
+    /// bincode leads an enum with its variant index as a `u32`.
+    fn userdata_error(userdata: &[u8]) -> FinPlanError {
+        match userdata.get(..4).and_then(|tag| deserialize::<u32>(tag).ok()) {
+            Some(tag) if tag >= INSTRUCTION_VARIANTS => {
+                trace!("unsupported instruction {}", tag);
+                FinPlanError::UnsupportedInstruction(tag)
+            }
+            _ => FinPlanError::UserdataDeserializeFailure,
+        }
+    }
+
+    fn write_balance(balance: i64, output: &mut [u8]) -> Result<(), FinPlanError> {
+        if output.len() < BALANCE_OUTPUT_SIZE {
+            warn!(
+                "{} bytes required to write the balance, only have {} bytes",
+                BALANCE_OUTPUT_SIZE,
+                output.len()
+            );
+            return Err(FinPlanError::UserdataTooSmall);
+        }
+        let writer = io::BufWriter::new(&mut output[..BALANCE_OUTPUT_SIZE]);
+        serialize_into(writer, &balance).unwrap();
+        Ok(())
+    }
+
+    /// Tell whether `account` holds no contract, a pending one, or one that has paid out.
+    pub fn status(account: &Account) -> ContractStatus {
+        match Self::deserialize(&account.userdata) {
+            Ok(ref state) if !state.initialized => ContractStatus::Uninitialized,
+            Ok(FinPlanState {
+                pending_fin_plan: Some(ref fin_plan),
+                ..
+            }) => ContractStatus::Pending {
+                plan_summary: PlanSummary::from(fin_plan),
+            },
+            Ok(_) => ContractStatus::Completed,
+            Err(_) => ContractStatus::Uninitialized,
+        }
+    }
+
+    /// Load the contract held by `account`. An account whose userdata is too short for a
+    /// contract, or that never had one written to it, holds `None`; one whose contract
+    /// fails to deserialize is corrupt.
+    pub fn try_load(account: &Account) -> Result<Option<FinPlanState>, FinPlanError> {
+        let userdata = &account.userdata;
+        if userdata.len() < FIN_PLAN_STATE_HEADER_SIZE
+            || userdata[..FIN_PLAN_STATE_HEADER_SIZE].iter().all(|b| *b == 0)
+        {
+            return Ok(None);
+        }
+        Self::deserialize(userdata)
+            .map(Some)
+            .map_err(|_| FinPlanError::UserdataDeserializeFailure)
+    }
+
+    /// Find the contract held under `key`, wherever it sits among `accounts`. An `Account`
+    /// doesn't carry its own key, so `keys[i]` must be the key of `accounts[i]`, as with a
+    /// transaction's `keys`. Only an account owned by this program that holds a contract
+    /// matches; its index in `accounts` is returned along with its state.
+    pub fn find(
+        keys: &[Pubkey],
+        accounts: &[Account],
+        key: &Pubkey,
+    ) -> Option<(usize, FinPlanState)> {
+        for (i, (k, account)) in keys.iter().zip(accounts).enumerate() {
+            if k != key || !Self::check_id(&account.program_id) {
+                continue;
+            }
+            if let Ok(Some(state)) = Self::try_load(account) {
+                return Some((i, state));
+            }
+        }
+        None
+    }
+
+    /// The tokens `account` can spend: none while it holds a pending contract.
+    pub fn get_balance(account: &Account) -> Result<i64, FinPlanError> {
+        match Self::try_load(account)? {
+            Some(ref state) if state.is_pending() => Ok(0),
+            _ => Ok(account.tokens),
+        }
+    }
+
+    /// Free the userdata of a contract that has paid out and been emptied. Once its
+    /// userdata is gone a zero-token account is no longer kept by the bank.
+    pub fn reclaim(account: &mut Account) -> Result<(), FinPlanError> {
+        match Self::try_load(account)? {
+            Some(ref state) if state.initialized && !state.is_pending() && account.tokens == 0 => {
+                account.userdata.clear();
+                Ok(())
+            }
+            _ => Err(FinPlanError::ContractNotClosable),
+        }
+    }
+}
+
+/// Fixed contract states and instructions, each with the bytes this program encodes it as.
+#[cfg(any(test, feature = "test"))]
+#[derive(Debug, PartialEq)]
+pub struct GoldenBytes {
+    /// States as stored in contract userdata, version header included.
+    pub states: Vec<(FinPlanState, Vec<u8>)>,
+    /// Instructions as carried in transaction userdata.
+    pub instructions: Vec<(Instruction, Vec<u8>)>,
+}
+
+#[cfg(any(test, feature = "test"))]
+impl FinPlanState {
+    /// Encode a fixed set of states and instructions. SDKs can regenerate their fixtures
+    /// from this and diff them, so a layout change shows up as a change in output rather
+    /// than a stale byte vector.
+    pub fn golden_bytes() -> GoldenBytes {
+        let from = Pubkey::new(&[1; 32]);
+        let to = Pubkey::new(&[2; 32]);
+        let witness = Pubkey::new(&[3; 32]);
+        let dt =
+            DateTime::<Utc>::from_utc(NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11), Utc);
+        let fin_plan = FinPlan::new_cancelable_future_payment(dt, witness, 192, to);
+
+        let states = vec![
+            FinPlanState::default(),
+            FinPlanState {
+                initialized: true,
+                pending_fin_plan: Some(fin_plan.clone()),
+                expiry: Some(dt),
+                refund: Some(from),
+                witnessed: vec![(Witness::Timestamp(dt), witness)],
+                last_timestamp: Some(dt),
+                created_height: Some(42),
+                source: Some(from),
+                memo: Some(b"invoice-42".to_vec()),
+                ..FinPlanState::default()
+            },
+            FinPlanState {
+                initialized: true,
+                refund: Some(from),
+                recurrence: Some(Recurrence {
+                    interval: 60,
+                    tokens: 10,
+                    dt_pubkey: witness,
+                    to,
+                }),
+                next_due: Some(dt),
+                remaining_count: 3,
+                source: Some(from),
+                ..FinPlanState::default()
+            },
+        ];
+        let instructions = vec![
+            Instruction::NewContract(Contract {
+                tokens: 192,
+                fin_plan: FinPlan::new_payment(192, to),
+                expiry: None,
+                memo: None,
+            }),
+            Instruction::NewContract(Contract {
+                tokens: 192,
+                fin_plan,
+                expiry: Some(dt),
+                memo: Some(b"invoice-42".to_vec()),
+            }),
+            Instruction::ApplyTimestamp(dt),
+            Instruction::ApplySignature,
+            Instruction::NewMultisigContract {
+                signers: vec![from, witness],
+                threshold: 2,
+                tokens: 192,
+                to,
+            },
+            Instruction::ApplyBlockHeight(1024),
+            Instruction::GetBalance,
+            Instruction::NewRecurringContract {
+                start: dt,
+                interval: 60,
+                count: 3,
+                tokens: 10,
+                dt_pubkey: witness,
+                to,
+            },
+            Instruction::NewSplitContract {
+                recipients: vec![(from, 40), (to, 60)],
+                tokens: 192,
+            },
+            Instruction::AddFunds(64),
+        ];
+
+        GoldenBytes {
+            states: states
+                .into_iter()
+                .map(|state| {
+                    let len = serialized_size(&state).unwrap() as usize;
+                    let mut userdata = vec![0; FIN_PLAN_STATE_HEADER_SIZE + len];
+                    state.serialize(&mut userdata).unwrap();
+                    (state, userdata)
+                }).collect(),
+            instructions: instructions
+                .into_iter()
+                .map(|instruction| {
+                    let userdata = bincode::serialize(&instruction).unwrap();
+                    (instruction, userdata)
+                }).collect(),
         }
     }
 }
+
 #[cfg(test)]
 mod test {
-    use bincode::serialize;
-    use fin_plan_program::{FinPlanError, FinPlanState};
+    use bincode::{deserialize, serialize};
+    use fin_plan::{Condition, FinPlan};
+    use fin_plan_instruction::{Contract, Instruction, INSTRUCTION_VARIANTS, MAX_MEMO_SIZE};
+    use fin_plan_program::{
+        fin_plan_error_count, AccountRoles, CompletionRecord, ContractFee, ContractStatus,
+        ContractSummary, FinPlanError, FinPlanState, ReleaseCondition, SimulationResult,
+        BALANCE_OUTPUT_SIZE, BUDGET_PROGRAM_ID, FIN_PLAN_STATE_HEADER_SIZE, FIN_PLAN_STATE_LEN_MASK,
+        FIN_PLAN_STATE_VERSION, FIN_PLAN_STATE_VERSION_0, FIN_PLAN_STATE_VERSION_2,
+        FIN_PLAN_STATE_VERSION_3, FIN_PLAN_STATE_VERSION_4, FIN_PLAN_STATE_VERSION_5,
+        FIN_PLAN_STATE_VERSION_6, FIN_PLAN_STATE_VERSION_7, FIN_PLAN_STATE_VERSION_SHIFT,
+    };
     use fin_plan_transaction::FinPlanTransaction;
     use chrono::prelude::{DateTime, NaiveDate, Utc};
+    use chrono::Duration;
     use hash::Hash;
+    use rand::{thread_rng, Rng};
     use signature::{GenKeys, Keypair, KeypairUtil};
+    use std::error::Error;
     use xpz_program_interface::account::Account;
     use xpz_program_interface::pubkey::Pubkey;
     use transaction::Transaction;
+    use trx_out::{Payment, Witness};
+
+    #[test]
+    fn test_check_id() {
+        assert_eq!(FinPlanState::id(), Pubkey::new(&BUDGET_PROGRAM_ID));
+        assert!(FinPlanState::check_id(&FinPlanState::id()));
+        assert!(!FinPlanState::check_id(&Pubkey::default()));
+        let mut id = BUDGET_PROGRAM_ID;
+        id[31] = 1;
+        assert!(!FinPlanState::check_id(&Pubkey::new(&id)));
+    }
+
+    /// Userdata holding `body` behind a header for layout `version`.
+    fn versioned(version: u8, body: Vec<u8>) -> Vec<u8> {
+        let header = body.len() as u64 | u64::from(version) << FIN_PLAN_STATE_VERSION_SHIFT;
+        let mut userdata = serialize(&header).unwrap();
+        userdata.extend(body);
+        userdata
+    }
 
     #[test]
     fn test_serializer() {
@@ -281,184 +2275,3449 @@ mod test {
         let b = FinPlanState::default();
         b.serialize(&mut a.userdata).unwrap();
         let buf = serialize(&b).unwrap();
+        assert_eq!(a.userdata[7], FIN_PLAN_STATE_VERSION);
         assert_eq!(a.userdata[8..8 + buf.len()], buf[0..]);
         let c = FinPlanState::deserialize(&a.userdata).unwrap();
         assert_eq!(b, c);
     }
 
-    #[test]
-    fn test_serializer_userdata_too_small() {
-        let mut a = Account::new(0, 1, FinPlanState::id());
-        let b = FinPlanState::default();
+    #[test]
+    fn test_deserialize_v0() {
+        let mut b = FinPlanState::default();
+        b.initialized = true;
+        b.pending_fin_plan = Some(FinPlan::new_payment(42, Pubkey::default()));
+        let buf = serialize(&(b.initialized, &b.pending_fin_plan)).unwrap();
+
+        let v0 = versioned(FIN_PLAN_STATE_VERSION_0, buf);
+        assert_eq!(FinPlanState::deserialize(&v0).unwrap(), b);
+
+        // Round trip through the current writer
+        let mut a = Account::new(0, 512, FinPlanState::id());
+        FinPlanState::deserialize(&v0)
+            .unwrap()
+            .serialize(&mut a.userdata)
+            .unwrap();
+        assert_eq!(FinPlanState::deserialize(&a.userdata).unwrap(), b);
+    }
+
+    #[test]
+    fn test_deserialize_baseline() {
+        // A default state as written before the version tag: its length, 2, then the body
+        let mut userdata = vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        userdata.resize(512, 0);
+        assert_eq!(
+            FinPlanState::deserialize(&userdata).unwrap(),
+            FinPlanState::default()
+        );
+        assert_eq!(FinPlanState::check_version(&userdata), Ok(()));
+
+        // A pending `After(Signature([3; 32]), Payment { tokens: 42, to: [2; 32] })`
+        let mut userdata = vec![82, 0, 0, 0, 0, 0, 0, 0];
+        userdata.extend(&[1, 1, 1, 0, 0, 0, 1, 0, 0, 0]);
+        userdata.extend(&[3; 32]);
+        userdata.extend(&[42, 0, 0, 0, 0, 0, 0, 0]);
+        userdata.extend(&[2; 32]);
+        userdata.resize(512, 0);
+        let state = FinPlanState::deserialize(&userdata).unwrap();
+        assert!(state.initialized);
+        assert_eq!(
+            state.pending_fin_plan,
+            Some(FinPlan::new_authorized_payment(
+                Pubkey::new(&[3; 32]),
+                42,
+                Pubkey::new(&[2; 32])
+            ))
+        );
+        assert_eq!(state.expiry, None);
+        assert_eq!(state.source, None);
+    }
+
+    #[test]
+    fn test_deserialize_v2() {
+        let mut b = FinPlanState::default();
+        b.initialized = true;
+        b.pending_fin_plan = Some(FinPlan::new_payment(42, Pubkey::default()));
+        b.expiry = Some(Utc::now());
+        b.refund = Some(Pubkey::default());
+        let buf = serialize(&(b.initialized, &b.pending_fin_plan, b.expiry, b.refund)).unwrap();
+
+        let v2 = versioned(FIN_PLAN_STATE_VERSION_2, buf);
+        assert_eq!(FinPlanState::deserialize(&v2).unwrap(), b);
+    }
+
+    #[test]
+    fn test_deserialize_v3() {
+        let mut b = FinPlanState::default();
+        b.initialized = true;
+        b.pending_fin_plan = Some(FinPlan::new_payment(42, Pubkey::default()));
+        b.witnessed = vec![(Witness::Signature, Pubkey::default())];
+        let buf = serialize(&(
+            b.initialized,
+            &b.pending_fin_plan,
+            b.expiry,
+            b.refund,
+            &b.witnessed,
+        )).unwrap();
+
+        let v3 = versioned(FIN_PLAN_STATE_VERSION_3, buf);
+        assert_eq!(FinPlanState::deserialize(&v3).unwrap(), b);
+    }
+
+    #[test]
+    fn test_deserialize_v4() {
+        let mut b = FinPlanState::default();
+        b.initialized = true;
+        b.pending_fin_plan = Some(FinPlan::new_payment(42, Pubkey::default()));
+        b.next_due = Some(Utc::now());
+        b.remaining_count = 2;
+        let buf = serialize(&(
+            b.initialized,
+            &b.pending_fin_plan,
+            b.expiry,
+            b.refund,
+            &b.witnessed,
+            &b.recurrence,
+            b.next_due,
+            b.remaining_count,
+        )).unwrap();
+
+        let v4 = versioned(FIN_PLAN_STATE_VERSION_4, buf);
+        assert_eq!(FinPlanState::deserialize(&v4).unwrap(), b);
+    }
+
+    #[test]
+    fn test_deserialize_v5() {
+        let mut b = FinPlanState::default();
+        b.initialized = true;
+        b.pending_fin_plan = Some(FinPlan::new_payment(42, Pubkey::default()));
+        b.last_timestamp = Some(Utc::now());
+        let buf = serialize(&(
+            b.initialized,
+            &b.pending_fin_plan,
+            b.expiry,
+            b.refund,
+            &b.witnessed,
+            &b.recurrence,
+            b.next_due,
+            b.remaining_count,
+            b.last_timestamp,
+        )).unwrap();
+
+        let v5 = versioned(FIN_PLAN_STATE_VERSION_5, buf);
+        let state = FinPlanState::deserialize(&v5).unwrap();
+        assert_eq!(state.created_height, None);
+        assert_eq!(state, b);
+    }
+
+    #[test]
+    fn test_deserialize_v6() {
+        let mut b = FinPlanState::default();
+        b.initialized = true;
+        b.pending_fin_plan = Some(FinPlan::new_payment(42, Pubkey::default()));
+        b.created_height = Some(7);
+        let buf = serialize(&(
+            b.initialized,
+            &b.pending_fin_plan,
+            b.expiry,
+            b.refund,
+            &b.witnessed,
+            &b.recurrence,
+            b.next_due,
+            b.remaining_count,
+            b.last_timestamp,
+            b.created_height,
+        )).unwrap();
+
+        let v6 = versioned(FIN_PLAN_STATE_VERSION_6, buf);
+        let state = FinPlanState::deserialize(&v6).unwrap();
+        assert_eq!(state.source, None);
+        assert_eq!(state, b);
+    }
+
+    #[test]
+    fn test_deserialize_v7() {
+        let mut b = FinPlanState::default();
+        b.initialized = true;
+        b.pending_fin_plan = Some(FinPlan::new_payment(42, Pubkey::default()));
+        b.source = Some(Pubkey::new(&[1; 32]));
+        let buf = serialize(&(
+            b.initialized,
+            &b.pending_fin_plan,
+            b.expiry,
+            b.refund,
+            &b.witnessed,
+            &b.recurrence,
+            b.next_due,
+            b.remaining_count,
+            b.last_timestamp,
+            b.created_height,
+            b.source,
+        )).unwrap();
+
+        let v7 = versioned(FIN_PLAN_STATE_VERSION_7, buf);
+        let state = FinPlanState::deserialize(&v7).unwrap();
+        assert_eq!(state.memo, None);
+        assert_eq!(state, b);
+    }
+
+    #[test]
+    fn test_created_height() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let tx = Transaction::fin_plan_new_when_signed(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction_at_height(&tx, &mut accounts, 7).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.created_height, Some(7));
+
+        // Survives a write and read back
+        let mut a = Account::new(0, 512, FinPlanState::id());
+        state.serialize(&mut a.userdata).unwrap();
+        assert_eq!(FinPlanState::deserialize(&a.userdata).unwrap(), state);
+    }
+
+    #[test]
+    fn test_add_funds() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let mut accounts = vec![
+            Account::new(10, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let tx = Transaction::fin_plan_new_when_signed(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            from.pubkey(),
+            None,
+            4,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.source, Some(from.pubkey()));
+
+        let tx = Transaction::fin_plan_new_add_funds(&from, contract.pubkey(), 6, Hash::default());
+        FinPlanState::process_transaction(&tx, &mut accounts[..2]).unwrap();
+        assert_eq!(accounts[0].tokens, 0);
+        assert_eq!(accounts[1].tokens, 10);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.pending_fin_plan.unwrap().tokens(), 10);
+
+        // The contract pays out everything it now holds
+        let tx = Transaction::fin_plan_new_signature(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 10);
+
+        // and takes nothing more once it has
+        accounts[0].tokens = 1;
+        let tx = Transaction::fin_plan_new_add_funds(&from, contract.pubkey(), 1, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts[..2]),
+            Err(FinPlanError::ContractNotPending(contract.pubkey()))
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+    }
+
+    #[test]
+    fn test_add_funds_out_of_range() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let mut accounts = vec![
+            Account::new(i64::max_value(), 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let tx = Transaction::fin_plan_new_when_signed(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            from.pubkey(),
+            None,
+            4,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        // More than any account can hold. A top-up of -1 from before they were unsigned
+        // has the same encoding
+        let tx = Transaction::fin_plan_new_add_funds(
+            &from,
+            contract.pubkey(),
+            u64::max_value(),
+            Hash::default(),
+        );
+        assert_eq!(
+            tx.userdata,
+            vec![8, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts[..2]),
+            Err(FinPlanError::TokensOutOfRange)
+        );
+
+        // Enough to overflow the contract's balance, though not the source's
+        accounts[1].tokens = i64::max_value() - 4;
+        let tx = Transaction::fin_plan_new_add_funds(&from, contract.pubkey(), 5, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts[..2]),
+            Err(FinPlanError::TokenOverflow(contract.pubkey()))
+        );
+        assert_eq!(accounts[0].tokens, i64::max_value() - 4);
+        assert_eq!(accounts[1].tokens, i64::max_value() - 4);
+    }
+
+    fn new_contract_with_memo(
+        from: &Keypair,
+        contract: Pubkey,
+        to: Pubkey,
+        memo: Option<Vec<u8>>,
+    ) -> Transaction {
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 1,
+            fin_plan: FinPlan::new_authorized_payment(from.pubkey(), 1, to),
+            expiry: None,
+            memo,
+        });
+        Transaction::new(
+            from,
+            &[contract, to],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        )
+    }
+
+    #[test]
+    fn test_contract_trailing_fields() {
+        // A NewContract written before contracts had a memo or expiry
+        let mut userdata = vec![0, 0, 0, 0, 192, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        userdata.extend(&[192, 0, 0, 0, 0, 0, 0, 0]);
+        userdata.extend(&[1; 32]);
+        let contract = Contract {
+            tokens: 192,
+            fin_plan: FinPlan::new_payment(192, Pubkey::new(&[1; 32])),
+            memo: None,
+            expiry: None,
+        };
+        assert_eq!(
+            deserialize::<Instruction>(&userdata).unwrap(),
+            Instruction::NewContract(contract.clone())
+        );
+        assert_eq!(
+            serialize(&Instruction::NewContract(contract.clone())).unwrap(),
+            userdata
+        );
+
+        // Every mix of trailing fields survives a round trip
+        let expiry = Some(Utc::now());
+        for (memo, expiry) in vec![
+            (Some(vec![1, 2, 3]), None),
+            (None, expiry),
+            (Some(vec![1, 2, 3]), expiry),
+        ] {
+            let instruction = Instruction::NewContract(Contract {
+                memo,
+                expiry,
+                ..contract.clone()
+            });
+            let userdata = serialize(&instruction).unwrap();
+            assert_eq!(deserialize::<Instruction>(&userdata).unwrap(), instruction);
+        }
+    }
+
+    #[test]
+    fn test_memo() {
+        let from = Keypair::new();
+        let to = Keypair::new();
+        for memo in vec![None, Some(b"invoice-42".to_vec())] {
+            let contract = Keypair::new();
+            let mut accounts = vec![
+                Account::new(1, 0, FinPlanState::id()),
+                Account::new(0, 512, FinPlanState::id()),
+                Account::new(0, 0, FinPlanState::id()),
+            ];
+            let tx = new_contract_with_memo(&from, contract.pubkey(), to.pubkey(), memo.clone());
+            let instruction = deserialize::<Instruction>(&tx.userdata).unwrap();
+            match instruction {
+                Instruction::NewContract(ref c) => assert_eq!(c.memo, memo),
+                _ => panic!("not a new contract"),
+            }
+            FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+            assert_eq!(state.memo, memo);
+
+            // The memo leaves the payment alone and comes back with it
+            let tx = Transaction::fin_plan_new_signature(
+                &from,
+                contract.pubkey(),
+                to.pubkey(),
+                Hash::default(),
+            );
+            let mut records = vec![];
+            FinPlanState::process_transaction_with_completions(
+                &tx,
+                &mut accounts,
+                0,
+                Some(&mut records),
+            ).unwrap();
+            assert_eq!(accounts[2].tokens, 1);
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].memo, memo);
+        }
+    }
+
+    #[test]
+    fn test_memo_too_large() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let memo = vec![0; MAX_MEMO_SIZE + 1];
+        let tx = new_contract_with_memo(&from, contract.pubkey(), to.pubkey(), Some(memo));
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::MemoTooLarge)
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+
+        let memo = vec![0; MAX_MEMO_SIZE];
+        let tx = new_contract_with_memo(&from, contract.pubkey(), to.pubkey(), Some(memo));
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+    }
+
+    #[test]
+    fn test_add_funds_not_source() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let mut accounts = vec![
+            Account::new(4, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let tx = Transaction::fin_plan_new_when_signed(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            from.pubkey(),
+            None,
+            4,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        let stranger = Keypair::new();
+        let mut stranger_accounts = vec![
+            Account::new(6, 0, FinPlanState::id()),
+            accounts[1].clone(),
+        ];
+        let tx =
+            Transaction::fin_plan_new_add_funds(&stranger, contract.pubkey(), 6, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut stranger_accounts),
+            Err(FinPlanError::NotContractSource(stranger.pubkey()))
+        );
+        assert_eq!(stranger_accounts[0].tokens, 6);
+        assert_eq!(stranger_accounts[1].tokens, 4);
+        let state = FinPlanState::deserialize(&stranger_accounts[1].userdata).unwrap();
+        assert_eq!(state.pending_fin_plan.unwrap().tokens(), 4);
+    }
+
+    #[test]
+    fn test_deserialize_short_buffers() {
+        let mut rng = thread_rng();
+        for len in 0..64 {
+            let mut input = vec![0u8; len];
+            rng.fill(&mut input[..]);
+            if let Some(version) = input.get_mut(7) {
+                *version = FIN_PLAN_STATE_VERSION;
+            }
+            // Success if there was no panic...
+            let _ = FinPlanState::deserialize(&input);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_huge_length() {
+        let lens = [
+            u64::max_value(),
+            u64::max_value() - 7,
+            usize::max_value() as u64,
+            u32::max_value() as u64 + 2,
+            513,
+        ];
+        for len in lens.iter() {
+            let version = u64::from(FIN_PLAN_STATE_VERSION) << FIN_PLAN_STATE_VERSION_SHIFT;
+            let header = len & FIN_PLAN_STATE_LEN_MASK | version;
+            let mut input = serialize(&header).unwrap();
+            input.resize(512, 0);
+            assert!(FinPlanState::deserialize(&input).is_err());
+        }
+    }
+
+    #[test]
+    fn test_deserialize_unknown_version() {
+        let mut a = Account::new(0, 512, FinPlanState::id());
+        FinPlanState::default().serialize(&mut a.userdata).unwrap();
+        a.userdata[7] = FIN_PLAN_STATE_VERSION + 1;
+        assert!(FinPlanState::deserialize(&a.userdata).is_err());
+
+        let mut accounts = vec![
+            Account::new(0, 0, FinPlanState::id()),
+            a,
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        let tx = Transaction::fin_plan_new_signature(&from, contract, to, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UserdataDeserializeFailure)
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        let key = Pubkey::default();
+        assert_eq!(
+            FinPlanError::InsufficientFunds {
+                who: key,
+                needed: 3,
+                available: 2,
+            }.to_string(),
+            format!("insufficient funds in {}: needed 3, available 2", key)
+        );
+        assert_eq!(
+            FinPlanError::UserdataTooSmall.to_string(),
+            "userdata too small"
+        );
+        let err: Box<Error> = Box::new(FinPlanError::TokensOutOfRange);
+        assert_eq!(err.to_string(), "token amount out of range");
+    }
+
+    #[test]
+    fn test_space_required() {
+        let from = Keypair::new();
+        let dt = Utc::now();
+        let fin_plans = vec![
+            FinPlan::new_payment(42, from.pubkey()),
+            FinPlan::new_cancelable_future_payment(dt, from.pubkey(), 42, from.pubkey()),
+            FinPlan::new_multisig_payment(vec![from.pubkey(); 3], 2, 42, from.pubkey()),
+            FinPlan::new_installment_payment(
+                vec![dt, dt + Duration::days(30)],
+                from.pubkey(),
+                21,
+                42,
+                from.pubkey(),
+            ),
+        ];
+        for fin_plan in fin_plans {
+            let space = FinPlanState::space_required(&fin_plan);
+            let state = FinPlanState::fully_witnessed(&fin_plan);
+
+            let mut a = Account::new(0, space, FinPlanState::id());
+            state.serialize(&mut a.userdata).unwrap();
+            assert_eq!(FinPlanState::deserialize(&a.userdata).unwrap(), state);
+
+            let mut a = Account::new(0, space - 1, FinPlanState::id());
+            assert_eq!(
+                state.serialize(&mut a.userdata),
+                Err(FinPlanError::UserdataTooSmall)
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_contract_account() {
+        let from = Keypair::new();
+        let witness = Keypair::new();
+        let to = Keypair::new();
+        let contract = Keypair::new();
+        let dt = Utc::now();
+        let fin_plan = FinPlan::new_cancelable_future_payment(dt, witness.pubkey(), 1, to.pubkey());
+
+        let account = FinPlanState::new_contract_account(0, &fin_plan);
+        assert_eq!(account.program_id, FinPlanState::id());
+        assert_eq!(account.userdata.len(), FinPlanState::space_required(&fin_plan));
+
+        // The contract fits from creation until its last witness
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            account,
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 1,
+            fin_plan: fin_plan.clone(),
+            expiry: Some(dt),
+            memo: None,
+        });
+        // The cancel refund goes to the witness, so it has to be named too
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to.pubkey(), witness.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        FinPlanState::fully_witnessed(&fin_plan)
+            .serialize(&mut accounts[1].userdata)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_serializer_userdata_too_small() {
+        let mut a = Account::new(0, 1, FinPlanState::id());
+        let b = FinPlanState::default();
+        assert_eq!(
+            b.serialize(&mut a.userdata),
+            Err(FinPlanError::UserdataTooSmall)
+        );
+    }
+    #[test]
+    fn test_invalid_instruction() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey()],
+            FinPlanState::id(),
+            vec![1, 2, 3], // <== garbage instruction
+            Hash::default(),
+            0,
+        );
+        assert!(FinPlanState::process_transaction(&tx, &mut accounts).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_instruction() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+
+        // The last variant this program knows is tagged one short of the count
+        let userdata = serialize(&Instruction::AddFunds(1)).unwrap();
+        assert_eq!(
+            deserialize::<u32>(&userdata[..4]).unwrap(),
+            INSTRUCTION_VARIANTS - 1
+        );
+
+        // An instruction from a newer program, with a payload this one can't guess at
+        let mut userdata = serialize(&INSTRUCTION_VARIANTS).unwrap();
+        userdata.extend_from_slice(&[0; 16]);
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey()],
+            FinPlanState::id(),
+            userdata,
+            Hash::default(),
+            0,
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UnsupportedInstruction(INSTRUCTION_VARIANTS))
+        );
+        assert_eq!(accounts[0].tokens, 1);
+
+        // A known tag with a malformed payload is still just bad userdata
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey()],
+            FinPlanState::id(),
+            serialize(&0u32).unwrap(),
+            Hash::default(),
+            0,
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UserdataDeserializeFailure)
+        );
+    }
+
+    #[test]
+    fn test_error_counters() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let tx = Transaction::fin_plan_new_when_signed(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            from.pubkey(),
+            None,
+            2,
+            Hash::default(),
+        );
+        let insufficient_funds = FinPlanError::InsufficientFunds {
+            who: from.pubkey(),
+            needed: 2,
+            available: 1,
+        };
+        // Other tests fail transactions concurrently, so only look for an increase
+        let before = fin_plan_error_count(&insufficient_funds);
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(insufficient_funds.clone())
+        );
+        assert!(fin_plan_error_count(&insufficient_funds) > before);
+
+        let userdata = serialize(&Instruction::ApplySignature).unwrap();
+        let tx = Transaction::new(&from, &[], FinPlanState::id(), userdata, Hash::default(), 0);
+        let before = fin_plan_error_count(&FinPlanError::MissingKeys);
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts[..1]),
+            Err(FinPlanError::MissingKeys)
+        );
+        assert!(fin_plan_error_count(&FinPlanError::MissingKeys) > before);
+    }
+
+    #[test]
+    fn test_missing_keys() {
+        let from = Keypair::new();
+        let userdata = serialize(&Instruction::ApplySignature).unwrap();
+
+        let mut tx = Transaction::new(
+            &from,
+            &[],
+            FinPlanState::id(),
+            userdata,
+            Hash::default(),
+            0,
+        );
+        let mut accounts = vec![Account::new(1, 0, FinPlanState::id())];
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::MissingKeys)
+        );
+
+        tx.keys.clear();
+        let mut accounts = vec![];
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::MissingKeys)
+        );
+
+        let mut state = FinPlanState::default();
+        let roles = AccountRoles::of(&Instruction::ApplySignature);
+        assert_eq!(
+            state.apply_signature(&[], roles, &mut []),
+            Err(FinPlanError::MissingKeys)
+        );
+        assert_eq!(
+            state.apply_timestamp(&[from.pubkey()], roles, &mut [], Utc::now()),
+            Err(FinPlanError::MissingKeys)
+        );
+    }
+
+    #[test]
+    fn test_witness_reports_payments() {
+        let from = Keypair::new().pubkey();
+        let contract = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        let keys = [from, contract, to];
+        let mut accounts = vec![
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(1, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let dt = Utc::now();
+        let mut state = FinPlanState::default();
+        state.pending_fin_plan = Some(FinPlan::after(dt, from, to, 1));
+        let roles = AccountRoles::of(&Instruction::ApplySignature);
+
+        // A witness the plan isn't waiting on is refused and leaves it pending
+        assert_eq!(
+            state.apply_signature(&keys, roles, &mut accounts),
+            Err(FinPlanError::UnusedWitness)
+        );
+        assert!(state.is_pending());
+        assert!(state.witnessed.is_empty());
+        assert_eq!(accounts[2].tokens, 0);
+
+        // The witness that completes the plan reports the payment it made
+        assert_eq!(
+            state.apply_timestamp(&keys, roles, &mut accounts, dt),
+            Ok(vec![Payment { tokens: 1, to }])
+        );
+        assert!(!state.is_pending());
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 1);
+    }
+
+    #[test]
+    fn test_destination_at_any_index() {
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let bystander = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        let dt = Utc::now();
+        let plans = vec![
+            (
+                FinPlan::after(dt, from.pubkey(), to, 1),
+                Instruction::ApplyTimestamp(dt),
+            ),
+            (
+                FinPlan::new_authorized_payment(from.pubkey(), 1, to),
+                Instruction::ApplySignature,
+            ),
+        ];
+        for (fin_plan, witness) in plans {
+            let mut accounts = vec![
+                Account::new(1, 0, FinPlanState::id()),
+                Account::new(0, 512, FinPlanState::id()),
+                Account::new(0, 0, FinPlanState::id()),
+                Account::new(0, 0, FinPlanState::id()),
+            ];
+            // The destination sits past an unrelated key rather than in the first slot
+            // after the contract
+            let keys = [contract, bystander, to];
+            let instruction = Instruction::NewContract(Contract {
+                fin_plan,
+                tokens: 1,
+                expiry: None,
+                memo: None,
+            });
+            let tx = Transaction::new(
+                &from,
+                &keys,
+                FinPlanState::id(),
+                serialize(&instruction).unwrap(),
+                Hash::default(),
+                0,
+            );
+            FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            assert_eq!(accounts[1].tokens, 1);
+
+            let tx = Transaction::new(
+                &from,
+                &keys,
+                FinPlanState::id(),
+                serialize(&witness).unwrap(),
+                Hash::default(),
+                0,
+            );
+            FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            assert_eq!(accounts[1].tokens, 0);
+            assert_eq!(accounts[2].tokens, 0);
+            assert_eq!(accounts[3].tokens, 1);
+        }
+    }
+
+    #[test]
+    fn test_transfer_on_date() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from_account = 0;
+        let contract_account = 1;
+        let to_account = 2;
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let rando = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(state.is_pending());
+
+        // Attack! Try to payout to a rando key
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            rando.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DestinationMissing(to.pubkey()))
+        );
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 1);
+        assert_eq!(accounts[to_account].tokens, 0);
+
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(state.is_pending());
+
+        // Now, acknowledge the time in the condition occurred and
+        // that pubkey's funds are now available.
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 0);
+        assert_eq!(accounts[to_account].tokens, 1);
+
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(!state.is_pending());
+
+        // try to replay the timestamp contract
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::ContractNotPending(contract.pubkey()))
+        );
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 0);
+        assert_eq!(accounts[to_account].tokens, 1);
+    }
+    #[test]
+    fn test_contract_status() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        assert_eq!(
+            FinPlanState::status(&accounts[1]),
+            ContractStatus::Uninitialized
+        );
+
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        match FinPlanState::status(&accounts[1]) {
+            ContractStatus::Pending { plan_summary } => {
+                assert_eq!(plan_summary.tokens, 1);
+                assert_eq!(plan_summary.destinations, vec![to.pubkey()]);
+                assert_eq!(plan_summary.conditions.len(), 1);
+            }
+            status => panic!("expected a pending contract, got {:?}", status),
+        }
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(FinPlanState::status(&accounts[1]), ContractStatus::Completed);
+    }
+
+    #[test]
+    fn test_transfer_before_expiry() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let expiry = dt + Duration::days(1);
+        let tx = Transaction::fin_plan_new_on_date_with_expiry(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            Some(expiry),
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.expiry, Some(expiry));
+        assert_eq!(state.refund, Some(from.pubkey()));
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 1);
+    }
+
+    #[test]
+    fn test_completion_record() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        let mut records = vec![];
+        FinPlanState::process_transaction_with_completions(&tx, &mut accounts, 0, Some(&mut records))
+            .unwrap();
+        assert!(records.is_empty());
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction_with_completions(&tx, &mut accounts, 0, Some(&mut records))
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![CompletionRecord {
+                contract: contract.pubkey(),
+                source: None,
+                destination: to.pubkey(),
+                tokens: 1,
+                witness: Witness::Timestamp(dt),
+                witness_key: from.pubkey(),
+                memo: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_process_transactions() {
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let to = Keypair::new().pubkey();
+        let dt = Utc::now();
+        let new_accounts = || {
+            vec![
+                Account::new(1, 0, FinPlanState::id()),
+                Account::new(0, 512, FinPlanState::id()),
+                Account::new(0, 0, FinPlanState::id()),
+            ]
+        };
+        let txs = vec![
+            Transaction::fin_plan_new_on_date(
+                &from,
+                to,
+                contract,
+                dt,
+                from.pubkey(),
+                None,
+                1,
+                Hash::default(),
+            ),
+            // More tokens than the source holds
+            Transaction::fin_plan_new_on_date(
+                &from,
+                to,
+                contract,
+                dt,
+                from.pubkey(),
+                None,
+                2,
+                Hash::default(),
+            ),
+            // The contract was created in another set of accounts
+            Transaction::fin_plan_new_timestamp(&from, contract, to, dt, Hash::default()),
+            // No accounts for this one
+            Transaction::fin_plan_new_signature(&from, contract, to, Hash::default()),
+        ];
+        let mut accounts = vec![new_accounts(), new_accounts(), new_accounts()];
+        let results = FinPlanState::process_transactions(&txs, &mut accounts);
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(FinPlanError::InsufficientFunds {
+                    who: from.pubkey(),
+                    needed: 2,
+                    available: 1,
+                }),
+                Err(FinPlanError::UninitializedContract(contract)),
+                Err(FinPlanError::MissingKeys),
+            ]
+        );
+        // The failure didn't stop the batch, and left its accounts alone
+        assert_eq!(accounts[0][1].tokens, 1);
+        assert_eq!(accounts[1][0].tokens, 1);
+        assert_eq!(accounts[1][1].tokens, 0);
+    }
+
+    #[test]
+    fn test_process_transactions_parallel() {
+        let dt = Utc::now();
+        let mut txs = vec![];
+        let mut timestamps = vec![];
+        let mut keys: Vec<Pubkey> = vec![];
+        let mut accounts = vec![];
+        for _ in 0..16 {
+            let from = Keypair::new();
+            let contract = Keypair::new().pubkey();
+            let to = Keypair::new().pubkey();
+            txs.push(Transaction::fin_plan_new_on_date(
+                &from,
+                to,
+                contract,
+                dt,
+                from.pubkey(),
+                None,
+                1,
+                Hash::default(),
+            ));
+            timestamps.push(Transaction::fin_plan_new_timestamp(
+                &from,
+                contract,
+                to,
+                dt,
+                Hash::default(),
+            ));
+            keys.extend(&[from.pubkey(), contract, to]);
+            accounts.push(Account::new(1, 0, FinPlanState::id()));
+            accounts.push(Account::new(0, 512, FinPlanState::id()));
+            accounts.push(Account::new(0, 0, FinPlanState::id()));
+        }
+        // Each timestamp conflicts with its contract's creation, so it must see it applied
+        txs.extend(timestamps);
+
+        let mut serial_accounts = accounts.clone();
+        let serial_results: Vec<_> = txs
+            .iter()
+            .map(|tx| {
+                let indices: Vec<_> = tx
+                    .keys
+                    .iter()
+                    .map(|key| keys.iter().position(|k| k == key).unwrap())
+                    .collect();
+                let mut tx_accounts: Vec<_> =
+                    indices.iter().map(|&i| serial_accounts[i].clone()).collect();
+                let result = FinPlanState::process_transaction(tx, &mut tx_accounts);
+                for (&i, account) in indices.iter().zip(tx_accounts) {
+                    serial_accounts[i] = account;
+                }
+                result
+            }).collect();
+
+        let results = FinPlanState::process_transactions_parallel(&txs, &mut accounts);
+        assert_eq!(results, serial_results);
+        assert!(results.iter().all(Result::is_ok));
+        for (account, serial_account) in accounts.iter().zip(&serial_accounts) {
+            assert_eq!(account.tokens, serial_account.tokens);
+            assert_eq!(account.userdata, serial_account.userdata);
+        }
+        // Every `to` account was paid
+        assert!(accounts.chunks(3).all(|accounts| accounts[2].tokens == 1));
+    }
+
+    #[test]
+    fn test_process_transaction_verbose() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        let receipt = FinPlanState::process_transaction_verbose(&tx, &mut accounts).unwrap();
+        assert_eq!(Some(receipt.instruction), tx.instruction());
+        assert_eq!(receipt.debited, 1);
+        assert_eq!(receipt.credited, vec![0, 1, 0]);
+        assert!(receipt.pending);
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        let receipt = FinPlanState::process_transaction_verbose(&tx, &mut accounts).unwrap();
+        assert_eq!(receipt.instruction, Instruction::ApplyTimestamp(dt));
+        assert_eq!(receipt.debited, 0);
+        assert_eq!(receipt.credited, vec![0, -1, 1]);
+        assert!(!receipt.pending);
+    }
+
+    #[test]
+    fn test_simulate_transaction() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        let simulated = FinPlanState::simulate_transaction(&tx, &accounts).unwrap();
+        assert_eq!(accounts[0].tokens, 1);
+        assert!(accounts[1].userdata.iter().all(|b| *b == 0));
+
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(
+            simulated,
+            SimulationResult {
+                balances: vec![0, 1, 0],
+                state: Some(state),
+                pending: true,
+            }
+        );
+
+        // Simulating the witness reports the payment without making it
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        let userdata = accounts[1].userdata.clone();
+        let simulated = FinPlanState::simulate_transaction(&tx, &accounts).unwrap();
+        assert_eq!(simulated.balances, vec![0, 0, 1]);
+        assert!(!simulated.pending);
+        assert_eq!(accounts[1].tokens, 1);
+        assert_eq!(accounts[1].userdata, userdata);
+
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let balances: Vec<i64> = accounts.iter().map(|account| account.tokens).collect();
+        assert_eq!(simulated.balances, balances);
+        assert_eq!(
+            simulated.state,
+            Some(FinPlanState::deserialize(&accounts[1].userdata).unwrap())
+        );
+
+        // Failures are reported the same way too
+        assert_eq!(
+            FinPlanState::simulate_transaction(&tx, &accounts),
+            Err(FinPlanError::ContractNotPending(contract.pubkey()))
+        );
+    }
+
+    #[test]
+    fn test_installment_transfer() {
+        let mut accounts = vec![
+            Account::new(100, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let dates = vec![dt, dt + Duration::days(30), dt + Duration::days(60)];
+        let tx = Transaction::fin_plan_new_installments(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dates.clone(),
+            from.pubkey(),
+            40,
+            100,
+            Hash::default(),
+        );
+        assert!(tx.verify_plan());
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[0].tokens, 0);
+        assert_eq!(accounts[1].tokens, 100);
+
+        // Each timestamp releases one tranche, the last one only what is left
+        for (date, (contract_tokens, to_tokens)) in
+            dates.iter().zip(vec![(60, 40), (20, 80), (0, 100)])
+        {
+            let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+            assert!(state.is_pending());
+
+            let tx = Transaction::fin_plan_new_timestamp(
+                &from,
+                contract.pubkey(),
+                to.pubkey(),
+                *date,
+                Hash::default(),
+            );
+            FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            assert_eq!(accounts[1].tokens, contract_tokens);
+            assert_eq!(accounts[2].tokens, to_tokens);
+        }
+
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_installment_early_timestamp() {
+        let mut accounts = vec![
+            Account::new(100, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_installments(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            vec![dt, dt + Duration::days(30)],
+            from.pubkey(),
+            50,
+            100,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        // Replaying the first date doesn't release the second tranche early
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DuplicateWitness)
+        );
+        assert_eq!(accounts[1].tokens, 50);
+        assert_eq!(accounts[2].tokens, 50);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.is_pending());
+    }
+
+    #[test]
+    fn test_vesting_transfer() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tranches = vec![
+            (dt, Payment { tokens: 50, to: to.pubkey() }),
+            (dt + Duration::days(30), Payment { tokens: 30, to: to.pubkey() }),
+            (dt + Duration::days(60), Payment { tokens: 20, to: to.pubkey() }),
+        ];
+        let fin_plan = FinPlan::new_vesting_payment(tranches, from.pubkey());
+        let mut accounts = vec![
+            Account::new(100, 0, FinPlanState::id()),
+            FinPlanState::new_contract_account(0, &fin_plan),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 100,
+            fin_plan,
+            expiry: None,
+            memo: None,
+        });
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 100);
+
+        // Between the second and third dates, the first two tranches are released together
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt + Duration::days(45),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 20);
+        assert_eq!(accounts[2].tokens, 80);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.is_pending());
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt + Duration::days(60),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 100);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_recurring_transfer() {
+        // A recurring contract carries its schedule as well as the pending plan
+        let mut accounts = vec![
+            Account::new(100, 0, FinPlanState::id()),
+            Account::new(0, 1024, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let interval = Duration::days(30);
+        let tx = Transaction::fin_plan_new_recurring(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            interval.num_seconds() as u64,
+            3,
+            from.pubkey(),
+            30,
+            Hash::default(),
+        );
+        assert!(tx.verify_plan());
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[0].tokens, 10);
+        assert_eq!(accounts[1].tokens, 90);
+
+        // Each period is released by a timestamp at or after its due date
+        for (i, (contract_tokens, to_tokens)) in vec![(60, 30), (30, 60), (0, 90)]
+            .into_iter()
+            .enumerate()
+        {
+            let due = dt + interval * i as i32;
+            let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+            assert!(state.is_pending());
+            assert_eq!(state.next_due, Some(due));
+            assert_eq!(state.remaining_count, 3 - i as u64);
+
+            // Not yet due, so nothing is paid
+            let tx = Transaction::fin_plan_new_timestamp(
+                &from,
+                contract.pubkey(),
+                to.pubkey(),
+                due - Duration::seconds(1),
+                Hash::default(),
+            );
+            assert_eq!(
+                FinPlanState::process_transaction(&tx, &mut accounts),
+                Err(FinPlanError::UnusedWitness)
+            );
+            assert_eq!(accounts[1].tokens, contract_tokens + 30);
+
+            let tx = Transaction::fin_plan_new_timestamp(
+                &from,
+                contract.pubkey(),
+                to.pubkey(),
+                due,
+                Hash::default(),
+            );
+            FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            assert_eq!(accounts[1].tokens, contract_tokens);
+            assert_eq!(accounts[2].tokens, to_tokens);
+        }
+
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+        assert_eq!(state.remaining_count, 0);
+        assert_eq!(state.next_due, None);
+    }
+
+    #[test]
+    fn test_recurring_cancel() {
+        // A recurring contract carries its schedule as well as the pending plan
+        let mut accounts = vec![
+            Account::new(90, 0, FinPlanState::id()),
+            Account::new(0, 1024, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_recurring(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            Duration::days(30).num_seconds() as u64,
+            3,
+            from.pubkey(),
+            30,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[2].tokens, 30);
+
+        // The source cancels and takes back the two periods that haven't been paid
+        let tx = Transaction::fin_plan_new_signature(
+            &from,
+            contract.pubkey(),
+            from.pubkey(),
+            Hash::default(),
+        );
+        // unit test hack, `from` is passed in the slot of the pay account, which ends up
+        // holding the first period and the refund
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 90);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+        assert_eq!(state.remaining_count, 0);
+
+        // Later periods are never released
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt + Duration::days(30),
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::ContractNotPending(contract.pubkey()))
+        );
+    }
+
+    #[test]
+    fn test_recurring_invalid_schedule() {
+        let accounts = vec![
+            Account::new(90, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        for &(interval, count) in [(0, 3), (60, 0), (u64::max_value(), 2)].iter() {
+            let tx = Transaction::fin_plan_new_recurring(
+                &from,
+                from.pubkey(),
+                contract.pubkey(),
+                Utc::now(),
+                interval,
+                count,
+                from.pubkey(),
+                30,
+                Hash::default(),
+            );
+            assert_eq!(
+                FinPlanState::process_transaction(&tx, &mut accounts.clone()),
+                Err(FinPlanError::InvalidRecurrence)
+            );
+        }
+    }
+
+    #[test]
+    fn test_early_timestamp_refused() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        // A timestamp before `dt` fails the transaction instead of being accepted as a no-op,
+        // and isn't remembered however often it's sent
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt - Duration::days(1),
+            Hash::default(),
+        );
+        for _ in 0..2 {
+            assert_eq!(
+                FinPlanState::process_transaction(&tx, &mut accounts),
+                Err(FinPlanError::UnusedWitness)
+            );
+        }
+        assert_eq!(accounts[1].tokens, 1);
+        assert_eq!(accounts[2].tokens, 0);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.is_pending());
+        assert!(state.witnessed.is_empty());
+
+        // A later timestamp still completes the contract
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[2].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.witnessed.is_empty());
+    }
+
+    #[test]
+    fn test_unused_witness_not_recorded() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let userdata = accounts[1].userdata.clone();
+
+        // Signatures from keys the plan doesn't name can't fill up the contract's userdata
+        for _ in 0..32 {
+            let stranger = Keypair::new();
+            let tx = Transaction::fin_plan_new_signature(
+                &stranger,
+                contract.pubkey(),
+                to.pubkey(),
+                Hash::default(),
+            );
+            assert_eq!(
+                FinPlanState::process_transaction(&tx, &mut accounts),
+                Err(FinPlanError::UnusedWitness)
+            );
+        }
+        assert_eq!(accounts[1].userdata, userdata);
+        assert_eq!(accounts[1].tokens, 1);
+    }
+
+    #[test]
+    fn test_non_monotonic_timestamp() {
+        let mut accounts = vec![
+            Account::new(100, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_installments(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            vec![dt, dt + Duration::days(30)],
+            from.pubkey(),
+            50,
+            100,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[2].tokens, 50);
+
+        // Once a time has been witnessed, the clock can't be turned back
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt - Duration::days(1),
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::NonMonotonicTimestamp)
+        );
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.is_pending());
+        assert_eq!(state.last_timestamp, Some(dt));
+
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt + Duration::days(30),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[2].tokens, 100);
+    }
+
+    #[test]
+    fn test_untrusted_timestamp_leaves_clock() {
+        let mut accounts = vec![
+            Account::new(100, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let oracle = Keypair::new();
+        let stranger = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_installments(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            vec![dt, dt + Duration::days(30)],
+            oracle.pubkey(),
+            50,
+            100,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        // A far-future date from a key the plan doesn't trust moves nothing
+        let tx = Transaction::fin_plan_new_timestamp(
+            &stranger,
+            contract.pubkey(),
+            to.pubkey(),
+            dt + Duration::days(365),
+            Hash::default(),
+        );
+        assert!(FinPlanState::process_transaction(&tx, &mut accounts).is_err());
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.last_timestamp, None);
+
+        // so the oracle's own, earlier dates still apply
+        let tx = Transaction::fin_plan_new_timestamp(
+            &oracle,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[2].tokens, 50);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.last_timestamp, Some(dt));
+    }
+
+    #[test]
+    fn test_refund_after_expiry() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let expiry = dt + Duration::days(1);
+        let tx = Transaction::fin_plan_new_on_date_with_expiry(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            Some(expiry),
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        // Past expiry the destination can no longer collect
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            expiry,
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DestinationMissing(from.pubkey()))
+        );
+
+        // The source gets its tokens back. `accounts[2]` stands in for the source account.
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            from.pubkey(),
+            expiry,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_transfer_at_block_height() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 1,
+            fin_plan: FinPlan::new_block_height_payment(10, 1, to.pubkey()),
+            expiry: None,
+            memo: None,
+        });
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        // The ledger hasn't reached the claimed height yet
+        let tx = Transaction::fin_plan_new_block_height(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            10,
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction_at_height(&tx, &mut accounts, 9),
+            Err(FinPlanError::FutureBlockHeight(10))
+        );
+
+        // An honest height below the target doesn't release the payment
+        let tx = Transaction::fin_plan_new_block_height(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            9,
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction_at_height(&tx, &mut accounts, 9),
+            Err(FinPlanError::UnusedWitness)
+        );
+        assert_eq!(accounts[1].tokens, 1);
+        assert_eq!(accounts[2].tokens, 0);
+
+        let tx = Transaction::fin_plan_new_block_height(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            10,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction_at_height(&tx, &mut accounts, 10).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_cancel_transfer() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from_account = 0;
+        let contract_account = 1;
+        let pay_account = 2;
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            Some(from.pubkey()),
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(state.is_pending());
+
+        // Attack! try to put the tokens into the wrong account with cancel
+        let tx =
+            Transaction::fin_plan_new_signature(&to, contract.pubkey(), to.pubkey(), Hash::default());
+        // unit test hack, the `from account` is passed instead of the `to` account to avoid
+        // creating more account vectors
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UnusedWitness)
+        );
+        // nothing should be changed because apply witness didn't finalize a payment
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 1);
+        // this would be the `to.pubkey()` account
+        assert_eq!(accounts[pay_account].tokens, 0);
+
+        // Now, cancel the transaction. from gets her funds back
+        let tx = Transaction::fin_plan_new_signature(
+            &from,
+            contract.pubkey(),
+            from.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 0);
+        assert_eq!(accounts[pay_account].tokens, 1);
+
+        // try to replay the signature contract
+        let tx = Transaction::fin_plan_new_signature(
+            &from,
+            contract.pubkey(),
+            from.pubkey(),
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::ContractNotPending(contract.pubkey()))
+        );
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 0);
+        assert_eq!(accounts[pay_account].tokens, 1);
+    }
+
+    #[test]
+    fn test_cancelable_after() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from_account = 0;
+        let contract_account = 1;
+        let pay_account = 2;
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_cancelable_after(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 1);
+
+        // The refund window hasn't opened yet
+        let tx = Transaction::fin_plan_new_signature(
+            &from,
+            contract.pubkey(),
+            from.pubkey(),
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::FailedWitness)
+        );
+        assert_eq!(accounts[contract_account].tokens, 1);
+        assert_eq!(accounts[pay_account].tokens, 0);
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(state.is_pending());
+
+        // Open it, then cancel. The signature refused earlier is accepted now
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            from.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[contract_account].tokens, 1);
+        let tx = Transaction::fin_plan_new_signature(
+            &from,
+            contract.pubkey(),
+            from.pubkey(),
+            Hash::default(),
+        );
+        // unit test hack, the `from account` is passed instead of the `to` account to avoid
+        // creating more account vectors
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[contract_account].tokens, 0);
+        assert_eq!(accounts[pay_account].tokens, 1);
+    }
+
+    #[test]
+    fn test_cancelable_after_destination_signature() {
+        let from = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        // Both before and after the refund window opens
+        for &open_window in &[false, true] {
+            let mut accounts = vec![
+                Account::new(1, 0, FinPlanState::id()),
+                Account::new(0, 512, FinPlanState::id()),
+                Account::new(0, 0, FinPlanState::id()),
+            ];
+            let contract = Keypair::new();
+            let tx = Transaction::fin_plan_new_cancelable_after(
+                &from,
+                to.pubkey(),
+                contract.pubkey(),
+                dt,
+                from.pubkey(),
+                1,
+                Hash::default(),
+            );
+            FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            if open_window {
+                let tx = Transaction::fin_plan_new_timestamp(
+                    &from,
+                    contract.pubkey(),
+                    to.pubkey(),
+                    dt,
+                    Hash::default(),
+                );
+                FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            }
+
+            let tx = Transaction::fin_plan_new_signature(
+                &to,
+                contract.pubkey(),
+                to.pubkey(),
+                Hash::default(),
+            );
+            FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+            assert_eq!(accounts[1].tokens, 0);
+            assert_eq!(accounts[2].tokens, 1);
+            let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+            assert!(!state.is_pending());
+        }
+    }
+
+    #[test]
+    fn test_multisig_transfer() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from_account = 0;
+        let contract_account = 1;
+        let to_account = 2;
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let signer0 = Keypair::new();
+        let signer1 = Keypair::new();
+        let signer2 = Keypair::new();
+        let tx = Transaction::fin_plan_new_multisig(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            vec![signer0.pubkey(), signer1.pubkey(), signer2.pubkey()],
+            2,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[from_account].tokens, 0);
+        assert_eq!(accounts[contract_account].tokens, 1);
+
+        let tx = Transaction::fin_plan_new_signature(
+            &signer0,
+            contract.pubkey(),
+            to.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(state.is_pending());
+
+        // Replaying the same signer must not satisfy the threshold
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DuplicateWitness)
+        );
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(state.is_pending());
+        assert_eq!(accounts[to_account].tokens, 0);
+
+        let tx = Transaction::fin_plan_new_signature(
+            &signer2,
+            contract.pubkey(),
+            to.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[contract_account].tokens, 0);
+        assert_eq!(accounts[to_account].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_signature_from_unsigned_key() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let witness = Keypair::new();
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 1,
+            fin_plan: FinPlan::new_authorized_payment(witness.pubkey(), 1, to.pubkey()),
+            expiry: None,
+            memo: None,
+        });
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to.pubkey(), witness.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+
+        // Naming the witness doesn't make its signature part of the transaction
+        let rando = Keypair::new();
+        let tx = Transaction::new(
+            &rando,
+            &[contract.pubkey(), to.pubkey(), witness.pubkey()],
+            FinPlanState::id(),
+            serialize(&Instruction::ApplySignature).unwrap(),
+            Hash::default(),
+            0,
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UnusedWitness)
+        );
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.is_pending());
+        assert_eq!(accounts[2].tokens, 0);
+
+        // The witness signing its own transaction releases the payment, whoever funded it
+        let tx = Transaction::fin_plan_new_signature(
+            &witness,
+            contract.pubkey(),
+            to.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts[..3]).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 1);
+    }
+
+    #[test]
+    fn test_multisig_invalid_threshold() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let tx = Transaction::fin_plan_new_multisig(
+            &from,
+            from.pubkey(),
+            contract.pubkey(),
+            vec![from.pubkey()],
+            2,
+            1,
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::InvalidThreshold)
+        );
+    }
+
+    #[test]
+    fn test_token_overflow() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(i64::max_value(), 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new(&from, to.pubkey(), 1, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::TokenOverflow(to.pubkey()))
+        );
+        assert_eq!(accounts[1].tokens, i64::max_value());
+    }
+
+    fn new_contract_with_collector(
+        from: &Keypair,
+        contract: Pubkey,
+        collector: Pubkey,
+        tokens: u64,
+    ) -> Transaction {
+        let to = Keypair::new().pubkey();
+        let instruction = Instruction::NewContract(Contract {
+            fin_plan: FinPlan::new_future_payment(Utc::now(), from.pubkey(), tokens, to),
+            tokens,
+            expiry: None,
+            memo: None,
+        });
+        Transaction::new(
+            from,
+            &[contract, to, collector],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        )
+    }
+
+    #[test]
+    fn test_contract_fee() {
+        let mut accounts = vec![
+            Account::new(3, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let collector = Keypair::new().pubkey();
+        let tx = new_contract_with_collector(&from, contract, collector, 1);
+        let fee = ContractFee {
+            tokens: 2,
+            collector,
+        };
+
+        // The source holds exactly the contract's tokens plus the fee
+        FinPlanState::process_transaction_with_fee(&tx, &mut accounts, 0, Some(&fee)).unwrap();
+        assert_eq!(accounts[0].tokens, 0);
+        assert_eq!(accounts[1].tokens, 1);
+        assert_eq!(accounts[3].tokens, 2);
+    }
+
+    #[test]
+    fn test_contract_fee_insufficient_funds() {
+        let mut accounts = vec![
+            Account::new(2, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let collector = Keypair::new().pubkey();
+        let tx = new_contract_with_collector(&from, contract, collector, 1);
+        let fee = ContractFee {
+            tokens: 2,
+            collector,
+        };
+
+        // Enough for the tokens, but not for the fee on top of them
+        assert_eq!(
+            FinPlanState::process_transaction_with_fee(&tx, &mut accounts, 0, Some(&fee)),
+            Err(FinPlanError::InsufficientFunds {
+                who: from.pubkey(),
+                needed: 3,
+                available: 2,
+            })
+        );
+        assert_eq!(accounts[0].tokens, 2);
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[3].tokens, 0);
+
+        // Without a fee the same transaction goes through
+        FinPlanState::process_transaction_with_fee(&tx, &mut accounts, 0, None).unwrap();
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[3].tokens, 0);
+
+        // A shortfall on the tokens alone reports the exact deficit too
+        let contract = Keypair::new().pubkey();
+        let tx = new_contract_with_collector(&from, contract, collector, 5);
+        match FinPlanState::process_transaction_with_fee(&tx, &mut accounts, 0, None) {
+            Err(FinPlanError::InsufficientFunds {
+                who,
+                needed,
+                available,
+            }) => {
+                assert_eq!(who, from.pubkey());
+                assert_eq!(needed - available, 4);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_contract_fee_missing_collector() {
+        let mut accounts = vec![
+            Account::new(3, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            from.pubkey(),
+            contract,
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        let fee = ContractFee {
+            tokens: 2,
+            collector: Keypair::new().pubkey(),
+        };
+        assert_eq!(
+            FinPlanState::process_transaction_with_fee(&tx, &mut accounts, 0, Some(&fee)),
+            Err(FinPlanError::MissingKeys)
+        );
+        assert_eq!(accounts[0].tokens, 3);
+    }
+
+    #[test]
+    fn test_contract_fee_payer_picked_collector() {
+        let mut accounts = vec![
+            Account::new(3, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new().pubkey();
+        let fee = ContractFee {
+            tokens: 2,
+            collector: Keypair::new().pubkey(),
+        };
+
+        // A payer can't route the fee back to a key of their own choosing
+        let own_key = Keypair::new().pubkey();
+        let tx = new_contract_with_collector(&from, contract, own_key, 1);
+        assert_eq!(
+            FinPlanState::process_transaction_with_fee(&tx, &mut accounts, 0, Some(&fee)),
+            Err(FinPlanError::MissingKeys)
+        );
+        assert_eq!(accounts[0].tokens, 3);
+        assert_eq!(accounts[3].tokens, 0);
+    }
+
+    #[test]
+    fn test_duplicate_key_source_is_contract() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            from.pubkey(),
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DuplicateKey(from.pubkey()))
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+    }
+
+    #[test]
+    fn test_duplicate_key_contract_is_destination() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            contract.pubkey(),
+            contract.pubkey(),
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DuplicateKey(contract.pubkey()))
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+
+        // A witness can't pay the contract back into itself either
+        let tx = Transaction::fin_plan_new_signature(
+            &from,
+            contract.pubkey(),
+            contract.pubkey(),
+            Hash::default(),
+        );
+        let mut accounts = vec![
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(1, 512, FinPlanState::id()),
+            Account::new(1, 512, FinPlanState::id()),
+        ];
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DuplicateKey(contract.pubkey()))
+        );
+    }
+
+    #[test]
+    fn test_split_payments_rounding() {
+        let keys: Vec<Pubkey> = (0..3).map(|_| Keypair::new().pubkey()).collect();
+        let split = |tokens, shares: &[u8]| -> Vec<u64> {
+            let recipients: Vec<_> = keys.iter().cloned().zip(shares.iter().cloned()).collect();
+            FinPlanState::split_payments(tokens, &recipients)
+                .unwrap()
+                .iter()
+                .map(|payment| payment.tokens)
+                .collect()
+        };
+        // The largest remainder gets the leftover token
+        assert_eq!(split(10, &[33, 33, 34]), vec![3, 3, 4]);
+        assert_eq!(split(2, &[33, 33, 34]), vec![1, 0, 1]);
+        // Equal remainders favor earlier recipients
+        assert_eq!(split(2, &[25, 25, 50]), vec![1, 0, 1]);
+        assert_eq!(split(100, &[33, 33, 34]), vec![33, 33, 34]);
+        assert_eq!(split(1, &[50, 25, 25]), vec![1, 0, 0]);
+        // No dust is lost, however large the amount
+        let tokens = i64::max_value() as u64;
+        assert_eq!(split(tokens, &[33, 33, 34]).iter().sum::<u64>(), tokens);
+
+        let recipients = vec![(keys[0], 50), (keys[1], 49)];
+        assert_eq!(
+            FinPlanState::split_payments(10, &recipients),
+            Err(FinPlanError::InvalidSplit)
+        );
+    }
+
+    #[test]
+    fn test_split_contract() {
+        let from = Keypair::new();
+        let recipients: Vec<(Pubkey, u8)> = vec![
+            (Keypair::new().pubkey(), 33),
+            (Keypair::new().pubkey(), 33),
+            (Keypair::new().pubkey(), 34),
+        ];
+        let mut accounts = vec![
+            Account::new(11, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let tx = Transaction::fin_plan_new_split(&from, recipients.clone(), 10, Hash::default());
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let balances: Vec<i64> = accounts.iter().map(|account| account.tokens).collect();
+        assert_eq!(balances, vec![1, 3, 3, 4]);
+
+        // Shares that don't add up move nothing
+        let mut recipients = recipients;
+        recipients[2].1 = 33;
+        let tx = Transaction::fin_plan_new_split(&from, recipients, 1, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::InvalidSplit)
+        );
+        let balances: Vec<i64> = accounts.iter().map(|account| account.tokens).collect();
+        assert_eq!(balances, vec![1, 3, 3, 4]);
+    }
+
+    #[test]
+    fn test_empty_all_condition() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let fin_plan = FinPlan::After(
+            Condition::All(vec![]),
+            Payment {
+                tokens: 1,
+                to: to.pubkey(),
+            },
+        );
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 1,
+            fin_plan,
+            expiry: None,
+            memo: None,
+        });
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+
+        // Otherwise a stranger's signature would release the tokens
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::EmptyCondition)
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+        assert!(FinPlanState::deserialize(&accounts[1].userdata).is_err());
+    }
+
+    #[test]
+    fn test_unreferenced_key() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 1,
+            fin_plan: FinPlan::new_future_payment(Utc::now(), from.pubkey(), 1, to.pubkey()),
+            expiry: None,
+            memo: None,
+        });
+        // The destination is left out of the transaction
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UnreferencedKey(to.pubkey()))
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+
+        // So is a key the plan only waits on a witness from
+        let witness = Keypair::new();
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 1,
+            fin_plan: FinPlan::new_authorized_payment(witness.pubkey(), 1, to.pubkey()),
+            expiry: None,
+            memo: None,
+        });
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UnreferencedKey(witness.pubkey()))
+        );
+
+        // And a multisig signer
+        let instruction = Instruction::NewMultisigContract {
+            signers: vec![witness.pubkey()],
+            threshold: 1,
+            tokens: 1,
+            to: to.pubkey(),
+        };
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
+            Hash::default(),
+            0,
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UnreferencedKey(witness.pubkey()))
+        );
+        let tx = Transaction::fin_plan_new_multisig(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            vec![witness.pubkey()],
+            1,
+            1,
+            Hash::default(),
+        );
+        assert_eq!(
+            tx.keys,
+            vec![from.pubkey(), contract.pubkey(), to.pubkey(), witness.pubkey()]
+        );
+
+        // Constructed transactions name every account their plan references
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            Utc::now(),
+            from.pubkey(),
+            Some(from.pubkey()),
+            1,
+            Hash::default(),
+        );
+        assert_eq!(tx.keys, vec![from.pubkey(), contract.pubkey(), to.pubkey()]);
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+    }
+
+    #[test]
+    fn test_zero_tokens() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new(&from, to.pubkey(), 0, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::ZeroTokens)
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+    }
+
+    #[test]
+    fn test_strict_rejects_instant_payment() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new(&from, to.pubkey(), 1, Hash::default());
+        assert_eq!(
+            FinPlanState::process_transaction_strict(&tx, &mut accounts, 0, None),
+            Err(FinPlanError::NotAnEscrow)
+        );
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 0);
+
+        // Outside strict mode it's a transfer
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[0].tokens, 0);
+        assert_eq!(accounts[1].tokens, 1);
+    }
+
+    #[test]
+    fn test_strict_allows_escrow() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction_strict(&tx, &mut accounts, 0, None).unwrap();
+        assert_eq!(accounts[0].tokens, 0);
+        assert_eq!(accounts[1].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.is_pending());
+    }
+
+    /// The signed layout `Payment`, `Contract` and `Instruction` had before token amounts
+    /// became unsigned.
+    #[derive(Serialize)]
+    struct LegacyPayment {
+        tokens: i64,
+        to: Pubkey,
+    }
+
+    #[derive(Serialize)]
+    enum LegacyFinPlan {
+        Pay(LegacyPayment),
+    }
+
+    #[derive(Serialize)]
+    struct LegacyContract {
+        tokens: i64,
+        fin_plan: LegacyFinPlan,
+        expiry: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Serialize)]
+    enum LegacyInstruction {
+        NewContract(LegacyContract),
+    }
+
+    fn legacy_new_contract(tokens: i64, payment_tokens: i64, to: Pubkey) -> Vec<u8> {
+        serialize(&LegacyInstruction::NewContract(LegacyContract {
+            tokens,
+            fin_plan: LegacyFinPlan::Pay(LegacyPayment {
+                tokens: payment_tokens,
+                to,
+            }),
+            expiry: None,
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_signed_contract_deserialize() {
+        let to = Keypair::new().pubkey();
+        let userdata = legacy_new_contract(42, 42, to);
+        let instruction: Instruction = deserialize(&userdata).unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::NewContract(Contract {
+                tokens: 42,
+                fin_plan: FinPlan::new_payment(42, to),
+                expiry: None,
+                memo: None,
+            })
+        );
+
+        let mut accounts = vec![
+            Account::new(42, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let tx = Transaction::new(
+            &from,
+            &[to],
+            FinPlanState::id(),
+            userdata,
+            Hash::default(),
+            0,
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[0].tokens, 0);
+        assert_eq!(accounts[1].tokens, 42);
+    }
+
+    #[test]
+    fn test_legacy_negative_payment() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(1, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let to = Keypair::new();
+        let userdata = legacy_new_contract(1, -1, to.pubkey()); // <== attack!
+        let tx = Transaction::new(
+            &from,
+            &[to.pubkey()],
+            FinPlanState::id(),
+            userdata,
+            Hash::default(),
+            0,
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::TokensOutOfRange)
+        );
+        assert_eq!(accounts[1].tokens, 1);
+    }
+
+    #[test]
+    fn test_get_balance_instruction() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, BALANCE_OUTPUT_SIZE, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let output = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            from.pubkey(),
+            contract.pubkey(),
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 1);
+
+        // A pending contract has nothing to spend
+        let tx = Transaction::fin_plan_new_get_balance(
+            &from,
+            contract.pubkey(),
+            output.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let balance: i64 = deserialize(&accounts[2].userdata).unwrap();
+        assert_eq!(balance, 0);
+
+        // A resolved payment is spendable
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, BALANCE_OUTPUT_SIZE, FinPlanState::id()),
+        ];
+        let tx = Transaction::fin_plan_new(&from, contract.pubkey(), 1, Hash::default());
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let tx = Transaction::fin_plan_new_get_balance(
+            &from,
+            contract.pubkey(),
+            output.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let balance: i64 = deserialize(&accounts[2].userdata).unwrap();
+        assert_eq!(balance, 1);
+
+        // The output account must be able to hold the balance
+        accounts[2].userdata = vec![];
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UserdataTooSmall)
+        );
+    }
+
+    #[test]
+    fn test_pending_conditions() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            Some(from.pubkey()),
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(
+            state.pending_conditions(),
+            vec![
+                Condition::Timestamp(dt, from.pubkey()),
+                Condition::Signature(from.pubkey()),
+            ]
+        );
+
+        // Nothing is outstanding once the contract pays out
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.pending_conditions(), vec![]);
+
+        // A multisig plan reports only the signers still missing
+        let signers: Vec<Pubkey> = (0..3).map(|_| Keypair::new().pubkey()).collect();
+        let mut state = FinPlanState::default();
+        state.pending_fin_plan = Some(FinPlan::Multisig {
+            signers: signers.clone(),
+            threshold: 2,
+            signed: vec![signers[1]],
+            payment: Payment {
+                tokens: 1,
+                to: to.pubkey(),
+            },
+        });
+        assert_eq!(
+            state.pending_conditions(),
+            vec![
+                Condition::Signature(signers[0]),
+                Condition::Signature(signers[2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reclaim() {
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
+
+        // Nothing to close before a contract is written
+        assert_eq!(
+            FinPlanState::reclaim(&mut accounts[1]),
+            Err(FinPlanError::ContractNotClosable)
+        );
+
+        // A pending contract can't be closed
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            None,
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(
+            FinPlanState::reclaim(&mut accounts[1]),
+            Err(FinPlanError::ContractNotClosable)
+        );
+
+        // Nor a completed one that still holds tokens
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        accounts[1].tokens = 1;
         assert_eq!(
-            b.serialize(&mut a.userdata),
-            Err(FinPlanError::UserdataTooSmall)
+            FinPlanState::reclaim(&mut accounts[1]),
+            Err(FinPlanError::ContractNotClosable)
         );
+        assert_eq!(FinPlanState::status(&accounts[1]), ContractStatus::Completed);
+
+        // A completed, empty contract gives its userdata up
+        accounts[1].tokens = 0;
+        assert_eq!(FinPlanState::reclaim(&mut accounts[1]), Ok(()));
+        assert!(accounts[1].userdata.is_empty());
+        assert_eq!(FinPlanState::try_load(&accounts[1]), Ok(None));
     }
+
     #[test]
-    fn test_invalid_instruction() {
+    fn test_try_load() {
+        // Not a contract
+        let account = Account::new(1, 0, FinPlanState::id());
+        assert_eq!(FinPlanState::try_load(&account), Ok(None));
+        assert_eq!(FinPlanState::get_balance(&account), Ok(1));
+        let account = Account::new(1, 512, FinPlanState::id());
+        assert_eq!(FinPlanState::try_load(&account), Ok(None));
+        assert_eq!(FinPlanState::get_balance(&account), Ok(1));
+
+        // A pending contract
         let mut accounts = vec![
             Account::new(1, 0, FinPlanState::id()),
             Account::new(0, 512, FinPlanState::id()),
         ];
         let from = Keypair::new();
         let contract = Keypair::new();
-
-        let tx = Transaction::new(
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
             &from,
-            &[contract.pubkey()],
-            FinPlanState::id(),
-            vec![1, 2, 3], // <== garbage instruction
+            to.pubkey(),
+            contract.pubkey(),
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
             Hash::default(),
-            0,
         );
-        assert!(FinPlanState::process_transaction(&tx, &mut accounts).is_err());
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::try_load(&accounts[1]).unwrap().unwrap();
+        assert!(state.is_pending());
+        assert_eq!(FinPlanState::get_balance(&accounts[1]), Ok(0));
+
+        // A corrupt contract doesn't pass for spendable tokens
+        accounts[1].userdata[FIN_PLAN_STATE_HEADER_SIZE] = 0xff;
+        accounts[1].userdata[FIN_PLAN_STATE_HEADER_SIZE + 1] = 0xff;
+        assert_eq!(
+            FinPlanState::try_load(&accounts[1]),
+            Err(FinPlanError::UserdataDeserializeFailure)
+        );
+        assert_eq!(
+            FinPlanState::get_balance(&accounts[1]),
+            Err(FinPlanError::UserdataDeserializeFailure)
+        );
     }
 
     #[test]
-    fn test_transfer_on_date() {
+    fn test_summary() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let dt = Utc::now();
         let mut accounts = vec![
             Account::new(1, 0, FinPlanState::id()),
             Account::new(0, 512, FinPlanState::id()),
             Account::new(0, 0, FinPlanState::id()),
         ];
-        let from_account = 0;
-        let contract_account = 1;
-        let to_account = 2;
+        let tx = Transaction::fin_plan_new_on_date(
+            &from,
+            to.pubkey(),
+            contract.pubkey(),
+            dt,
+            from.pubkey(),
+            Some(from.pubkey()),
+            1,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(
+            state.summary(),
+            Some(ContractSummary {
+                to: to.pubkey(),
+                tokens: 1,
+                release: ReleaseCondition::OnDate(dt, from.pubkey()),
+            })
+        );
+
+        // Once it pays out there's nothing left to summarize
+        let tx = Transaction::fin_plan_new_timestamp(
+            &from,
+            contract.pubkey(),
+            to.pubkey(),
+            dt,
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert_eq!(state.summary(), None);
+        assert_eq!(FinPlanState::default().summary(), None);
+
+        // An amount no account could hold is still reported as it is, not wrapped
+        let signer = Keypair::new().pubkey();
+        let state = FinPlanState {
+            pending_fin_plan: Some(FinPlan::After(
+                Condition::Signature(signer),
+                Payment {
+                    tokens: u64::max_value(),
+                    to: to.pubkey(),
+                },
+            )),
+            ..FinPlanState::default()
+        };
+        assert_eq!(state.summary().unwrap().tokens, u64::max_value());
+    }
+
+    #[test]
+    fn test_find() {
         let from = Keypair::new();
         let contract = Keypair::new();
         let to = Keypair::new();
-        let rando = Keypair::new();
-        let dt = Utc::now();
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+        ];
         let tx = Transaction::fin_plan_new_on_date(
             &from,
             to.pubkey(),
             contract.pubkey(),
-            dt,
+            Utc::now(),
             from.pubkey(),
             None,
             1,
             Hash::default(),
         );
         FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 1);
-        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
+        let contract_account = accounts.pop().unwrap();
+
+        // Put the contract among accounts that aren't contracts, or aren't this program's
+        let other = Keypair::new();
+        let mut foreign = contract_account.clone();
+        foreign.program_id = Pubkey::default();
+        let keys = vec![from.pubkey(), other.pubkey(), contract.pubkey(), to.pubkey()];
+        let accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            foreign,
+            contract_account,
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        let (index, state) = FinPlanState::find(&keys, &accounts, &contract.pubkey()).unwrap();
+        assert_eq!(index, 2);
         assert!(state.is_pending());
+        assert_eq!(Some(state), FinPlanState::try_load(&accounts[2]).unwrap());
 
-        // Attack! Try to payout to a rando key
-        let tx = Transaction::fin_plan_new_timestamp(
+        // Right key, but not a budget contract
+        assert_eq!(FinPlanState::find(&keys, &accounts, &from.pubkey()), None);
+        assert_eq!(FinPlanState::find(&keys, &accounts, &to.pubkey()), None);
+        let keys = vec![from.pubkey(), contract.pubkey()];
+        assert_eq!(FinPlanState::find(&keys, &accounts, &contract.pubkey()), None);
+        assert_eq!(FinPlanState::find(&keys, &accounts, &Keypair::new().pubkey()), None);
+    }
+
+    #[test]
+    fn test_wrong_program_owner() {
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
             &from,
+            to.pubkey(),
             contract.pubkey(),
-            rando.pubkey(),
-            dt,
+            Utc::now(),
+            from.pubkey(),
+            None,
+            1,
             Hash::default(),
         );
+
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 512, Pubkey::default()), // <== not a budget account
+        ];
         assert_eq!(
             FinPlanState::process_transaction(&tx, &mut accounts),
-            Err(FinPlanError::DestinationMissing(to.pubkey()))
+            Err(FinPlanError::WrongProgramOwner(contract.pubkey()))
         );
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 1);
-        assert_eq!(accounts[to_account].tokens, 0);
 
-        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
-        assert!(state.is_pending());
+        let mut accounts = vec![
+            Account::new(1, 0, Pubkey::default()), // <== not a budget account
+            Account::new(0, 512, FinPlanState::id()),
+        ];
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::WrongProgramOwner(from.pubkey()))
+        );
+        assert_eq!(accounts[0].tokens, 1);
 
-        // Now, acknowledge the time in the condition occurred and
-        // that pubkey's funds are now available.
-        let tx = Transaction::fin_plan_new_timestamp(
+        // An immediate payment may credit an account owned by any program
+        let mut accounts = vec![
+            Account::new(1, 0, FinPlanState::id()),
+            Account::new(0, 0, Pubkey::default()),
+        ];
+        let tx = Transaction::fin_plan_new(&from, to.pubkey(), 1, Hash::default());
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 1);
+    }
+
+    #[test]
+    fn test_split_transfer() {
+        let mut accounts = vec![
+            Account::new(100, 0, FinPlanState::id()),
+            Account::new(0, 512, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+            Account::new(0, 0, FinPlanState::id()),
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to0 = Keypair::new();
+        let to1 = Keypair::new();
+        let fin_plan = FinPlan::new_authorized_split_payment(
+            from.pubkey(),
+            vec![
+                Payment {
+                    tokens: 70,
+                    to: to0.pubkey(),
+                },
+                Payment {
+                    tokens: 30,
+                    to: to1.pubkey(),
+                },
+            ],
+        );
+        let instruction = Instruction::NewContract(Contract {
+            tokens: 100,
+            fin_plan,
+            expiry: None,
+            memo: None,
+        });
+        let tx = Transaction::new(
             &from,
-            contract.pubkey(),
-            to.pubkey(),
-            dt,
+            &[contract.pubkey(), to0.pubkey(), to1.pubkey()],
+            FinPlanState::id(),
+            serialize(&instruction).unwrap(),
             Hash::default(),
+            0,
         );
         FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 0);
-        assert_eq!(accounts[to_account].tokens, 1);
+        assert_eq!(accounts[0].tokens, 0);
+        assert_eq!(accounts[1].tokens, 100);
 
-        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
-        assert!(!state.is_pending());
-
-        // try to replay the timestamp contract
+        // Leaving out one recipient rejects the whole release
+        let userdata = serialize(&Instruction::ApplySignature).unwrap();
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to0.pubkey()],
+            FinPlanState::id(),
+            userdata.clone(),
+            Hash::default(),
+            0,
+        );
         assert_eq!(
-            FinPlanState::process_transaction(&tx, &mut accounts),
-            Err(FinPlanError::ContractNotPending(contract.pubkey()))
+            FinPlanState::process_transaction(&tx, &mut accounts[..3]),
+            Err(FinPlanError::DestinationMissing(to1.pubkey()))
         );
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 0);
-        assert_eq!(accounts[to_account].tokens, 1);
+        assert_eq!(accounts[1].tokens, 100);
+        assert_eq!(accounts[2].tokens, 0);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(state.is_pending());
+
+        let tx = Transaction::new(
+            &from,
+            &[contract.pubkey(), to0.pubkey(), to1.pubkey()],
+            FinPlanState::id(),
+            userdata,
+            Hash::default(),
+            0,
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 70);
+        assert_eq!(accounts[3].tokens, 30);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
     }
+
     #[test]
-    fn test_cancel_transfer() {
+    fn test_arbiter_cancel_transfer() {
         let mut accounts = vec![
             Account::new(1, 0, FinPlanState::id()),
             Account::new(0, 512, FinPlanState::id()),
             Account::new(0, 0, FinPlanState::id()),
         ];
-        let from_account = 0;
-        let contract_account = 1;
-        let pay_account = 2;
         let from = Keypair::new();
         let contract = Keypair::new();
         let to = Keypair::new();
-        let dt = Utc::now();
-        let tx = Transaction::fin_plan_new_on_date(
+        let arbiter = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date_with_arbiter(
             &from,
             to.pubkey(),
             contract.pubkey(),
-            dt,
+            Utc::now(),
             from.pubkey(),
-            Some(from.pubkey()),
+            arbiter.pubkey(),
             1,
             Hash::default(),
         );
         FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 1);
-        let state = FinPlanState::deserialize(&accounts[contract_account].userdata).unwrap();
-        assert!(state.is_pending());
-
-        // Attack! try to put the tokens into the wrong account with cancel
-        let tx =
-            Transaction::fin_plan_new_signature(&to, contract.pubkey(), to.pubkey(), Hash::default());
-        // unit test hack, the `from account` is passed instead of the `to` account to avoid
-        // creating more account vectors
-        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
-        // nothing should be changed because apply witness didn't finalize a payment
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 1);
-        // this would be the `to.pubkey()` account
-        assert_eq!(accounts[pay_account].tokens, 0);
 
-        // Now, cancel the transaction. from gets her funds back
+        // The payer can't cancel on their own
         let tx = Transaction::fin_plan_new_signature(
             &from,
             contract.pubkey(),
             from.pubkey(),
             Hash::default(),
         );
-        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 0);
-        assert_eq!(accounts[pay_account].tokens, 1);
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UnusedWitness)
+        );
+        assert_eq!(accounts[1].tokens, 1);
 
-        // try to replay the signature contract
+        // The arbiter can't redirect the refund to itself
+        let tx = Transaction::fin_plan_new_signature(
+            &arbiter,
+            contract.pubkey(),
+            arbiter.pubkey(),
+            Hash::default(),
+        );
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::DestinationMissing(from.pubkey()))
+        );
+
+        // `accounts[2]` stands in for the payer's account
         let tx = Transaction::fin_plan_new_signature(
+            &arbiter,
+            contract.pubkey(),
+            from.pubkey(),
+            Hash::default(),
+        );
+        FinPlanState::process_transaction(&tx, &mut accounts).unwrap();
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[2].tokens, 1);
+        let state = FinPlanState::deserialize(&accounts[1].userdata).unwrap();
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_undersized_contract_rolls_back() {
+        let mut accounts = vec![
+            Account::new(5, 0, FinPlanState::id()),
+            Account::new(0, 16, FinPlanState::id()), // room for the header, not the state
+        ];
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let tx = Transaction::fin_plan_new_on_date(
             &from,
+            from.pubkey(),
             contract.pubkey(),
+            Utc::now(),
             from.pubkey(),
+            Some(from.pubkey()),
+            5,
             Hash::default(),
         );
         assert_eq!(
             FinPlanState::process_transaction(&tx, &mut accounts),
-            Err(FinPlanError::ContractNotPending(contract.pubkey()))
+            Err(FinPlanError::UserdataTooSmall)
         );
-        assert_eq!(accounts[from_account].tokens, 0);
-        assert_eq!(accounts[contract_account].tokens, 0);
-        assert_eq!(accounts[pay_account].tokens, 1);
+        assert_eq!(accounts[0].tokens, 5);
+        assert_eq!(accounts[1].tokens, 0);
+        assert_eq!(accounts[1].userdata, vec![0; 16]);
     }
 
     #[test]
@@ -482,8 +5741,14 @@ mod test {
             Hash::default(),
         );
 
-        assert!(FinPlanState::process_transaction(&tx, &mut accounts).is_err());
+        assert_eq!(
+            FinPlanState::process_transaction(&tx, &mut accounts),
+            Err(FinPlanError::UserdataTooSmall)
+        );
         assert!(FinPlanState::deserialize(&accounts[1].userdata).is_err());
+        // Neither the debit nor the credit stuck
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(accounts[1].tokens, 1);
 
         let tx = Transaction::fin_plan_new_timestamp(
             &from,
@@ -500,6 +5765,27 @@ mod test {
 
     /// Detect binary changes in the serialized contract userdata, which could have a downstream
     /// affect on SDKs and DApps
+    #[test]
+    fn test_golden_bytes_round_trip() {
+        let golden = FinPlanState::golden_bytes();
+        assert_eq!(golden, FinPlanState::golden_bytes());
+        for (state, userdata) in &golden.states {
+            assert_eq!(userdata[7], FIN_PLAN_STATE_VERSION);
+            assert_eq!(&FinPlanState::deserialize(userdata).unwrap(), state);
+        }
+        // Every instruction has a fixture
+        let tags: Vec<u32> = golden
+            .instructions
+            .iter()
+            .map(|(instruction, userdata)| {
+                assert_eq!(&deserialize::<Instruction>(userdata).unwrap(), instruction);
+                deserialize(&userdata[..4]).unwrap()
+            }).collect();
+        for tag in 0..INSTRUCTION_VARIANTS {
+            assert!(tags.contains(&tag), "no fixture for instruction {}", tag);
+        }
+    }
+
     #[test]
     fn test_sdk_serialize() {
         let keypair = &GenKeys::new([0u8; 32]).gen_n_keypairs(1)[0];
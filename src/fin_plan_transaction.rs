@@ -1,11 +1,11 @@
 use bincode::{deserialize, serialize};
 use fin_plan::{FinPlan, Condition};
-use fin_plan_instruction::{Contract, Instruction, Vote};
+use fin_plan_instruction::{Contract, Instruction};
 use fin_plan_program::FinPlanState;
 use chrono::prelude::*;
 use hash::Hash;
 use trx_out::Payment;
-use signature::Keypair;
+use signature::{Keypair, KeypairUtil};
 use xpz_program_interface::pubkey::Pubkey;
 use transaction::Transaction;
 
@@ -13,12 +13,12 @@ pub trait FinPlanTransaction {
     fn fin_plan_new_taxed(
         from_keypair: &Keypair,
         to: Pubkey,
-        tokens: i64,
+        tokens: u64,
         fee: i64,
         last_id: Hash,
     ) -> Self;
 
-    fn fin_plan_new(from_keypair: &Keypair, to: Pubkey, tokens: i64, last_id: Hash) -> Self;
+    fn fin_plan_new(from_keypair: &Keypair, to: Pubkey, tokens: u64, last_id: Hash) -> Self;
 
     fn fin_plan_new_timestamp(
         from_keypair: &Keypair,
@@ -35,7 +35,27 @@ pub trait FinPlanTransaction {
         last_id: Hash,
     ) -> Self;
 
-    fn fin_plan_new_vote(from_keypair: &Keypair, vote: Vote, last_id: Hash, fee: i64) -> Self;
+    fn fin_plan_new_block_height(
+        from_keypair: &Keypair,
+        contract: Pubkey,
+        to: Pubkey,
+        height: u64,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_get_balance(
+        from_keypair: &Keypair,
+        contract: Pubkey,
+        output: Pubkey,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_add_funds(
+        from_keypair: &Keypair,
+        contract: Pubkey,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
 
     fn fin_plan_new_on_date(
         from_keypair: &Keypair,
@@ -44,7 +64,63 @@ pub trait FinPlanTransaction {
         dt: DateTime<Utc>,
         dt_pubkey: Pubkey,
         cancelable: Option<Pubkey>,
-        tokens: i64,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_on_date_with_expiry(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dt: DateTime<Utc>,
+        dt_pubkey: Pubkey,
+        cancelable: Option<Pubkey>,
+        expiry: Option<DateTime<Utc>>,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_on_date_with_arbiter(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dt: DateTime<Utc>,
+        dt_pubkey: Pubkey,
+        arbiter: Pubkey,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_cancelable_after(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dt: DateTime<Utc>,
+        dt_pubkey: Pubkey,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_installments(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dates: Vec<DateTime<Utc>>,
+        dt_pubkey: Pubkey,
+        tranche: u64,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_recurring(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        start: DateTime<Utc>,
+        interval: u64,
+        count: u64,
+        dt_pubkey: Pubkey,
+        tokens: u64,
         last_id: Hash,
     ) -> Self;
 
@@ -54,11 +130,26 @@ pub trait FinPlanTransaction {
         contract: Pubkey,
         witness: Pubkey,
         cancelable: Option<Pubkey>,
-        tokens: i64,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
+
+    fn fin_plan_new_multisig(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        signers: Vec<Pubkey>,
+        threshold: u64,
+        tokens: u64,
         last_id: Hash,
     ) -> Self;
 
-    fn vote(&self) -> Option<(Pubkey, Vote, Hash)>;
+    fn fin_plan_new_split(
+        from_keypair: &Keypair,
+        recipients: Vec<(Pubkey, u8)>,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self;
 
     fn instruction(&self) -> Option<Instruction>;
 
@@ -70,29 +161,21 @@ impl FinPlanTransaction for Transaction {
     fn fin_plan_new_taxed(
         from_keypair: &Keypair,
         to: Pubkey,
-        tokens: i64,
+        tokens: u64,
         fee: i64,
         last_id: Hash,
     ) -> Self {
-        let payment = Payment {
-            tokens: tokens - fee,
-            to,
-        };
-        let fin_plan = FinPlan::Pay(payment);
-        let instruction = Instruction::NewContract(Contract { fin_plan, tokens });
-        let userdata = serialize(&instruction).unwrap();
-        Self::new(
-            from_keypair,
-            &[to],
-            FinPlanState::id(),
-            userdata,
-            last_id,
-            fee,
-        )
+        FinPlanTransactionBuilder::new()
+            .from(from_keypair)
+            .to(to)
+            .tokens(tokens)
+            .fee(fee)
+            .last_id(last_id)
+            .build()
     }
 
     /// Create and sign a new Transaction. Used for unit-testing.
-    fn fin_plan_new(from_keypair: &Keypair, to: Pubkey, tokens: i64, last_id: Hash) -> Self {
+    fn fin_plan_new(from_keypair: &Keypair, to: Pubkey, tokens: u64, last_id: Hash) -> Self {
         Self::fin_plan_new_taxed(from_keypair, to, tokens, 0, last_id)
     }
 
@@ -135,10 +218,63 @@ impl FinPlanTransaction for Transaction {
         )
     }
 
-    fn fin_plan_new_vote(from_keypair: &Keypair, vote: Vote, last_id: Hash, fee: i64) -> Self {
-        let instruction = Instruction::NewVote(vote);
-        let userdata = serialize(&instruction).expect("serialize instruction");
-        Self::new(from_keypair, &[], FinPlanState::id(), userdata, last_id, fee)
+    /// Create and sign a new Witness BlockHeight. Used for unit-testing.
+    fn fin_plan_new_block_height(
+        from_keypair: &Keypair,
+        contract: Pubkey,
+        to: Pubkey,
+        height: u64,
+        last_id: Hash,
+    ) -> Self {
+        let instruction = Instruction::ApplyBlockHeight(height);
+        let userdata = serialize(&instruction).unwrap();
+        Self::new(
+            from_keypair,
+            &[contract, to],
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
+    }
+
+    /// Create and sign a request to write the contract's balance into `output`.
+    fn fin_plan_new_get_balance(
+        from_keypair: &Keypair,
+        contract: Pubkey,
+        output: Pubkey,
+        last_id: Hash,
+    ) -> Self {
+        let instruction = Instruction::GetBalance;
+        let userdata = serialize(&instruction).unwrap();
+        Self::new(
+            from_keypair,
+            &[contract, output],
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
+    }
+
+    /// Create and sign a request to move `tokens` into the pending `contract` the key
+    /// funded, growing its payout by as much.
+    fn fin_plan_new_add_funds(
+        from_keypair: &Keypair,
+        contract: Pubkey,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let instruction = Instruction::AddFunds(tokens);
+        let userdata = serialize(&instruction).unwrap();
+        Self::new(
+            from_keypair,
+            &[contract],
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
     }
 
     /// Create and sign a postdated Transaction. Used for unit-testing.
@@ -149,28 +285,189 @@ impl FinPlanTransaction for Transaction {
         dt: DateTime<Utc>,
         dt_pubkey: Pubkey,
         cancelable: Option<Pubkey>,
-        tokens: i64,
+        tokens: u64,
         last_id: Hash,
     ) -> Self {
-        let fin_plan = if let Some(from) = cancelable {
-            FinPlan::Or(
-                (Condition::Timestamp(dt, dt_pubkey), Payment { tokens, to }),
-                (Condition::Signature(from), Payment { tokens, to: from }),
-            )
-        } else {
-            FinPlan::After(Condition::Timestamp(dt, dt_pubkey), Payment { tokens, to })
+        Self::fin_plan_new_on_date_with_expiry(
+            from_keypair,
+            to,
+            contract,
+            dt,
+            dt_pubkey,
+            cancelable,
+            None,
+            tokens,
+            last_id,
+        )
+    }
+
+    /// Create and sign a postdated Transaction that refunds `from_keypair` once `expiry`
+    /// passes without the payment being made.
+    fn fin_plan_new_on_date_with_expiry(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dt: DateTime<Utc>,
+        dt_pubkey: Pubkey,
+        cancelable: Option<Pubkey>,
+        expiry: Option<DateTime<Utc>>,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let mut builder = FinPlanTransactionBuilder::new();
+        builder
+            .from(from_keypair)
+            .to(to)
+            .contract(contract)
+            .on_date(dt, dt_pubkey)
+            .tokens(tokens)
+            .last_id(last_id);
+        if let Some(from) = cancelable {
+            builder.cancelable_by(from);
+        }
+        if let Some(expiry) = expiry {
+            builder.expiry(expiry);
+        }
+        builder.build()
+    }
+
+    /// Create and sign a postdated Transaction that `arbiter` may cancel, refunding
+    /// `from_keypair`.
+    fn fin_plan_new_on_date_with_arbiter(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dt: DateTime<Utc>,
+        dt_pubkey: Pubkey,
+        arbiter: Pubkey,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let fin_plan = FinPlan::new_arbitrated_future_payment(
+            dt,
+            dt_pubkey,
+            arbiter,
+            from_keypair.pubkey(),
+            tokens,
+            to,
+        );
+        let keys = contract_keys(from_keypair.pubkey(), contract, fin_plan.keys());
+        let instruction = Instruction::NewContract(Contract {
+            fin_plan,
+            tokens,
+            expiry: None,
+            memo: None,
+        });
+        let userdata = serialize(&instruction).expect("serialize instruction");
+        Self::new(
+            from_keypair,
+            &keys,
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
+    }
+
+    /// Create and sign a Transaction that pays `to` once it signs, and that `from_keypair`
+    /// may cancel for a refund once `dt_pubkey` witnesses `dt`.
+    fn fin_plan_new_cancelable_after(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dt: DateTime<Utc>,
+        dt_pubkey: Pubkey,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let fin_plan = FinPlan::new_cancelable_after_payment(
+            dt,
+            dt_pubkey,
+            from_keypair.pubkey(),
+            tokens,
+            to,
+        );
+        let keys = contract_keys(from_keypair.pubkey(), contract, fin_plan.keys());
+        let instruction = Instruction::NewContract(Contract {
+            fin_plan,
+            tokens,
+            expiry: None,
+            memo: None,
+        });
+        let userdata = serialize(&instruction).expect("serialize instruction");
+        Self::new(
+            from_keypair,
+            &keys,
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
+    }
+
+    /// Create and sign a Transaction that releases `tranche` tokens on each of `dates`.
+    fn fin_plan_new_installments(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        dates: Vec<DateTime<Utc>>,
+        dt_pubkey: Pubkey,
+        tranche: u64,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let fin_plan = FinPlan::new_installment_payment(dates, dt_pubkey, tranche, tokens, to);
+        let keys = contract_keys(from_keypair.pubkey(), contract, fin_plan.keys());
+        let instruction = Instruction::NewContract(Contract {
+            fin_plan,
+            tokens,
+            expiry: None,
+            memo: None,
+        });
+        let userdata = serialize(&instruction).expect("serialize instruction");
+        Self::new(
+            from_keypair,
+            &keys,
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
+    }
+
+    /// Create and sign a Transaction that pays `tokens` to `to` every `interval` seconds from
+    /// `start`, `count` times.
+    fn fin_plan_new_recurring(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        start: DateTime<Utc>,
+        interval: u64,
+        count: u64,
+        dt_pubkey: Pubkey,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let instruction = Instruction::NewRecurringContract {
+            start,
+            interval,
+            count,
+            tokens,
+            dt_pubkey,
+            to,
         };
-        let instruction = Instruction::NewContract(Contract { fin_plan, tokens });
+        let keys = contract_keys(from_keypair.pubkey(), contract, vec![to, dt_pubkey]);
         let userdata = serialize(&instruction).expect("serialize instruction");
         Self::new(
             from_keypair,
-            &[contract],
+            &keys,
             FinPlanState::id(),
             userdata,
             last_id,
             0,
         )
     }
+
     /// Create and sign a multisig Transaction.
     fn fin_plan_new_when_signed(
         from_keypair: &Keypair,
@@ -178,7 +475,7 @@ impl FinPlanTransaction for Transaction {
         contract: Pubkey,
         witness: Pubkey,
         cancelable: Option<Pubkey>,
-        tokens: i64,
+        tokens: u64,
         last_id: Hash,
     ) -> Self {
         let fin_plan = if let Some(from) = cancelable {
@@ -189,11 +486,17 @@ impl FinPlanTransaction for Transaction {
         } else {
             FinPlan::After(Condition::Signature(witness), Payment { tokens, to })
         };
-        let instruction = Instruction::NewContract(Contract { fin_plan, tokens });
+        let keys = contract_keys(from_keypair.pubkey(), contract, fin_plan.keys());
+        let instruction = Instruction::NewContract(Contract {
+            fin_plan,
+            tokens,
+            expiry: None,
+            memo: None,
+        });
         let userdata = serialize(&instruction).expect("serialize instruction");
         Self::new(
             from_keypair,
-            &[contract],
+            &keys,
             FinPlanState::id(),
             userdata,
             last_id,
@@ -201,12 +504,55 @@ impl FinPlanTransaction for Transaction {
         )
     }
 
-    fn vote(&self) -> Option<(Pubkey, Vote, Hash)> {
-        if let Some(Instruction::NewVote(vote)) = self.instruction() {
-            Some((*self.from(), vote, self.last_id))
-        } else {
-            None
-        }
+    /// Create and sign an M-of-N multisig Transaction.
+    fn fin_plan_new_multisig(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        contract: Pubkey,
+        signers: Vec<Pubkey>,
+        threshold: u64,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let mut referenced = vec![to];
+        referenced.extend(signers.iter().cloned());
+        let keys = contract_keys(from_keypair.pubkey(), contract, referenced);
+        let instruction = Instruction::NewMultisigContract {
+            signers,
+            threshold,
+            tokens,
+            to,
+        };
+        let userdata = serialize(&instruction).expect("serialize instruction");
+        Self::new(
+            from_keypair,
+            &keys,
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
+    }
+
+    /// Create and sign a Transaction that splits `tokens` between `recipients` by
+    /// percentage share.
+    fn fin_plan_new_split(
+        from_keypair: &Keypair,
+        recipients: Vec<(Pubkey, u8)>,
+        tokens: u64,
+        last_id: Hash,
+    ) -> Self {
+        let keys: Vec<Pubkey> = recipients.iter().map(|(to, _)| *to).collect();
+        let instruction = Instruction::NewSplitContract { recipients, tokens };
+        let userdata = serialize(&instruction).expect("serialize instruction");
+        Self::new(
+            from_keypair,
+            &keys,
+            FinPlanState::id(),
+            userdata,
+            last_id,
+            0,
+        )
     }
 
     fn instruction(&self) -> Option<Instruction> {
@@ -215,21 +561,169 @@ impl FinPlanTransaction for Transaction {
 
     /// Verify only the payment plan.
     fn verify_plan(&self) -> bool {
-        if let Some(Instruction::NewContract(contract)) = self.instruction() {
-            self.fee >= 0
-                && self.fee <= contract.tokens
-                && contract.fin_plan.verify(contract.tokens - self.fee)
-        } else {
-            true
+        match self.instruction() {
+            Some(Instruction::NewContract(contract)) => {
+                self.fee >= 0
+                    && self.fee as u64 <= contract.tokens
+                    && contract.fin_plan.verify(contract.tokens - self.fee as u64)
+            }
+            Some(Instruction::NewMultisigContract { tokens, .. })
+            | Some(Instruction::NewSplitContract { tokens, .. }) => {
+                self.fee >= 0 && self.fee as u64 <= tokens
+            }
+            Some(Instruction::NewRecurringContract { tokens, count, .. }) => {
+                self.fee >= 0
+                    && tokens
+                        .checked_mul(count)
+                        .map_or(false, |total| self.fee as u64 <= total)
+            }
+            Some(Instruction::AddFunds(tokens)) => self.fee >= 0 && tokens > 0,
+            _ => true,
         }
     }
 }
 
+/// The keys of a transaction creating `contract`: the contract, then every key other than
+/// the source `from` in `referenced`, the keys its plan may pay or wait on a witness from.
+fn contract_keys(from: Pubkey, contract: Pubkey, referenced: Vec<Pubkey>) -> Vec<Pubkey> {
+    let mut keys = vec![contract];
+    for key in referenced {
+        if key != from && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// Builds the transactions made by `fin_plan_new`, `fin_plan_new_taxed` and
+/// `fin_plan_new_on_date*` one named field at a time, so keys can't be passed in the
+/// wrong position.
+#[derive(Default)]
+pub struct FinPlanTransactionBuilder<'a> {
+    from_keypair: Option<&'a Keypair>,
+    to: Pubkey,
+    contract: Option<Pubkey>,
+    on_date: Option<(DateTime<Utc>, Pubkey)>,
+    cancelable: Option<Pubkey>,
+    expiry: Option<DateTime<Utc>>,
+    memo: Option<Vec<u8>>,
+    tokens: u64,
+    fee: i64,
+    last_id: Hash,
+}
+
+impl<'a> FinPlanTransactionBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The key that funds the payment and signs the transaction.
+    pub fn from(&mut self, from_keypair: &'a Keypair) -> &mut Self {
+        self.from_keypair = Some(from_keypair);
+        self
+    }
+
+    pub fn to(&mut self, to: Pubkey) -> &mut Self {
+        self.to = to;
+        self
+    }
+
+    /// The account that holds the tokens of a postdated payment until it is made.
+    pub fn contract(&mut self, contract: Pubkey) -> &mut Self {
+        self.contract = Some(contract);
+        self
+    }
+
+    /// Pay once `dt_pubkey` witnesses a time at or after `dt`, instead of right away.
+    pub fn on_date(&mut self, dt: DateTime<Utc>, dt_pubkey: Pubkey) -> &mut Self {
+        self.on_date = Some((dt, dt_pubkey));
+        self
+    }
+
+    /// Let `from` cancel a postdated payment, refunding itself.
+    pub fn cancelable_by(&mut self, from: Pubkey) -> &mut Self {
+        self.cancelable = Some(from);
+        self
+    }
+
+    /// Refund the source of a postdated payment once `expiry` passes.
+    pub fn expiry(&mut self, expiry: DateTime<Utc>) -> &mut Self {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    /// An opaque reference kept with the contract, such as an invoice id.
+    pub fn memo(&mut self, memo: Vec<u8>) -> &mut Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    pub fn tokens(&mut self, tokens: u64) -> &mut Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// A fee taken out of the tokens of an immediate payment.
+    pub fn fee(&mut self, fee: i64) -> &mut Self {
+        self.fee = fee;
+        self
+    }
+
+    pub fn last_id(&mut self, last_id: Hash) -> &mut Self {
+        self.last_id = last_id;
+        self
+    }
+
+    /// Create and sign the transaction. Panics without a `from` keypair, or without a
+    /// `contract` for a postdated payment.
+    pub fn build(&self) -> Transaction {
+        let from_keypair = self
+            .from_keypair
+            .expect("FinPlanTransactionBuilder needs a from keypair");
+        let (tokens, to) = (self.tokens, self.to);
+        let (fin_plan, expiry, keys) = match self.on_date {
+            None => {
+                // Wraps like the signed arithmetic did, so a bad fee still yields a plan
+                // that `verify_plan` rejects
+                let fin_plan = FinPlan::transfer(to, tokens.wrapping_sub(self.fee as u64));
+                (fin_plan, None, vec![to])
+            }
+            Some((dt, dt_pubkey)) => {
+                let fin_plan = if let Some(from) = self.cancelable {
+                    FinPlan::escrow(dt, dt_pubkey, to, from, tokens)
+                } else {
+                    FinPlan::after(dt, dt_pubkey, to, tokens)
+                };
+                let contract = self
+                    .contract
+                    .expect("FinPlanTransactionBuilder needs a contract for a postdated payment");
+                let keys = contract_keys(from_keypair.pubkey(), contract, fin_plan.keys());
+                (fin_plan, self.expiry, keys)
+            }
+        };
+        let instruction = Instruction::NewContract(Contract {
+            fin_plan,
+            tokens,
+            expiry,
+            memo: self.memo.clone(),
+        });
+        let userdata = serialize(&instruction).expect("serialize instruction");
+        Transaction::new(
+            from_keypair,
+            &keys,
+            FinPlanState::id(),
+            userdata,
+            self.last_id,
+            self.fee,
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use bincode::{deserialize, serialize};
-    use signature::KeypairUtil;
+    use trx_out::Witness;
 
     #[test]
     fn test_claim() {
@@ -259,13 +753,119 @@ mod tests {
         assert!(!Transaction::fin_plan_new_taxed(&keypair0, pubkey1, 1, -1, zero).verify_plan());
     }
 
+    #[test]
+    fn test_builder_matches_on_date() {
+        let zero = Hash::default();
+        let keypair = Keypair::new();
+        let from = keypair.pubkey();
+        let to = Keypair::new().pubkey();
+        let contract = Keypair::new().pubkey();
+        let dt = Utc::now();
+        let tx0 = Transaction::fin_plan_new_on_date(
+            &keypair,
+            to,
+            contract,
+            dt,
+            from,
+            Some(from),
+            42,
+            zero,
+        );
+        let tx1 = FinPlanTransactionBuilder::new()
+            .from(&keypair)
+            .to(to)
+            .contract(contract)
+            .on_date(dt, from)
+            .cancelable_by(from)
+            .tokens(42)
+            .last_id(zero)
+            .build();
+        assert_eq!(serialize(&tx1).unwrap(), serialize(&tx0).unwrap());
+
+        let tx0 = Transaction::fin_plan_new(&keypair, to, 42, zero);
+        let tx1 = FinPlanTransactionBuilder::new()
+            .from(&keypair)
+            .to(to)
+            .tokens(42)
+            .last_id(zero)
+            .build();
+        assert_eq!(serialize(&tx1).unwrap(), serialize(&tx0).unwrap());
+    }
+
+    fn contract_plan(tx: &Transaction) -> FinPlan {
+        match tx.instruction() {
+            Some(Instruction::NewContract(contract)) => contract.fin_plan,
+            _ => panic!("not a new contract"),
+        }
+    }
+
+    #[test]
+    fn test_templates_match_transactions() {
+        let zero = Hash::default();
+        let keypair = Keypair::new();
+        let from = keypair.pubkey();
+        let to = Keypair::new().pubkey();
+        let contract = Keypair::new().pubkey();
+        let dt = Utc::now();
+
+        let plan = FinPlan::transfer(to, 42);
+        assert_eq!(
+            contract_plan(&Transaction::fin_plan_new(&keypair, to, 42, zero)),
+            plan
+        );
+        assert_eq!(plan.final_payments(), Some(vec![Payment { tokens: 42, to }]));
+
+        let mut plan = FinPlan::after(dt, from, to, 42);
+        let tx =
+            Transaction::fin_plan_new_on_date(&keypair, to, contract, dt, from, None, 42, zero);
+        assert_eq!(contract_plan(&tx), plan);
+        assert_eq!(plan.final_payments(), None);
+        plan.apply_witness(&Witness::Signature, &from);
+        assert_eq!(plan.final_payments(), None);
+        plan.apply_witness(&Witness::Timestamp(dt), &from);
+        assert_eq!(plan.final_payments(), Some(vec![Payment { tokens: 42, to }]));
+
+        let escrow = FinPlan::escrow(dt, from, to, from, 42);
+        let tx = Transaction::fin_plan_new_on_date(
+            &keypair,
+            to,
+            contract,
+            dt,
+            from,
+            Some(from),
+            42,
+            zero,
+        );
+        assert_eq!(contract_plan(&tx), escrow);
+        assert_eq!(escrow.final_payments(), None);
+
+        let mut plan = escrow.clone();
+        plan.apply_witness(&Witness::Timestamp(dt), &from);
+        assert_eq!(plan.final_payments(), Some(vec![Payment { tokens: 42, to }]));
+
+        let mut plan = escrow.clone();
+        plan.apply_witness(&Witness::Signature, &from);
+        assert_eq!(
+            plan.final_payments(),
+            Some(vec![Payment {
+                tokens: 42,
+                to: from,
+            }])
+        );
+    }
+
     #[test]
     fn test_serialize_claim() {
         let fin_plan = FinPlan::Pay(Payment {
             tokens: 0,
             to: Default::default(),
         });
-        let instruction = Instruction::NewContract(Contract { fin_plan, tokens: 0 });
+        let instruction = Instruction::NewContract(Contract {
+            fin_plan,
+            tokens: 0,
+            expiry: None,
+            memo: None,
+        });
         let userdata = serialize(&instruction).unwrap();
         let claim0 = Transaction {
             keys: vec![],
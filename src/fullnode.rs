@@ -320,6 +320,7 @@ impl Fullnode {
                     &transaction_processor,
                     &blockthread,
                     Default::default(),
+                    Default::default(),
                     node.sockets
                         .transaction
                         .iter()
@@ -328,7 +329,7 @@ impl Fullnode {
                     ledger_path,
                     sigverify_disabled,
                     entry_height,
-                );
+                ).expect("TxCreator::new");
 
                 let broadcast_stage = BroadcastStage::new(
                     node.sockets
@@ -376,6 +377,8 @@ impl Fullnode {
 
         {
             let mut wblockthread = self.blockthread.write().unwrap();
+            // The leader's stages have stopped, so no one asks about earlier heights
+            wblockthread.prune_scheduled_leaders(entry_height);
             let scheduled_leader = wblockthread.get_scheduled_leader(entry_height);
             match scheduled_leader {
                 //TODO: Handle the case where we don't know who the next
@@ -425,12 +428,17 @@ impl Fullnode {
     }
 
     fn validator_to_leader(&mut self, entry_height: u64) {
-        self.blockthread.write().unwrap().set_leader(self.keypair.pubkey());
+        {
+            let mut wblockthread = self.blockthread.write().unwrap();
+            wblockthread.prune_scheduled_leaders(entry_height);
+            wblockthread.set_leader(self.keypair.pubkey());
+        }
         let (tx_creator, blob_receiver, tx_creator_exit) = TxCreator::new(
             self.keypair.clone(),
             &self.transaction_processor,
             &self.blockthread,
             Default::default(),
+            Default::default(),
             self.transaction_sockets
                 .iter()
                 .map(|s| s.try_clone().expect("Failed to clone transaction sockets"))
@@ -438,7 +446,7 @@ impl Fullnode {
             &self.ledger_path,
             self.sigverify_disabled,
             entry_height,
-        );
+        ).expect("TxCreator::new");
 
         let broadcast_stage = BroadcastStage::new(
             self.broadcast_socket
@@ -1,6 +1,5 @@
 use bincode::{self, deserialize, deserialize_from, serialize_into, serialized_size};
-use fin_plan_instruction::Vote;
-use fin_plan_transaction::FinPlanTransaction;
+use vote_program::Vote;
 use entry::Entry;
 use hash::Hash;
 use log::Level::Trace;
@@ -12,13 +11,16 @@ use result::{Error, Result};
 #[cfg(test)]
 use signature::{Keypair, KeypairUtil};
 use xpz_program_interface::pubkey::Pubkey;
+use std::borrow::Borrow;
 use std::fs::{create_dir_all, remove_dir_all, File, OpenOptions};
 use std::io::prelude::*;
 use std::io::{self, BufReader, BufWriter, Seek, SeekFrom};
 use std::mem::size_of;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::Path;
+use std::time::{Duration, Instant};
 use transaction::Transaction;
+use vote_transaction::VoteTransaction;
 use window::WINDOW_SIZE;
 
 
@@ -141,7 +143,8 @@ pub fn verify_ledger(ledger_path: &str) -> io::Result<()> {
     Ok(())
 }
 
-fn recover_ledger(ledger_path: &str) -> io::Result<()> {
+/// Trim a partially written tail off the ledger, returning the number of entries left
+fn recover_ledger(ledger_path: &str) -> io::Result<u64> {
     let ledger_path = Path::new(ledger_path);
     let mut index = OpenOptions::new()
         .write(true)
@@ -205,29 +208,61 @@ fn recover_ledger(ledger_path: &str) -> io::Result<()> {
             }
         }
     }
-    if log_enabled!(Trace) {
-        let num_entries = index.metadata()?.len() / SIZEOF_U64;
-        trace!("recover: done. {} entries", num_entries);
-    }
+    let num_entries = index.metadata()?.len() / SIZEOF_U64;
+    trace!("recover: done. {} entries", num_entries);
 
     // flush everything to disk...
     index.sync_all()?;
-    data.sync_all()
+    data.sync_all()?;
+    Ok(num_entries)
+}
+
+
+
+/// Destination for the entries a leader has produced
+pub trait EntryWriter {
+    fn write_entries(&mut self, entries: &[Entry]) -> io::Result<()>;
 }
 
+/// When a `LedgerWriter` forces the entries it has written onto disk. Entries that were
+/// only flushed to the OS survive the process crashing, but not the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync at the end of every `write_entry` and `write_entries` call
+    EveryBatch,
+    /// Sync at the end of a call once this long has passed since the last sync
+    Interval(Duration),
+    /// Leave it to the OS
+    Never,
+}
 
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        SyncPolicy::Never
+    }
+}
 
 #[derive(Debug)]
 pub struct LedgerWriter {
     index: BufWriter<File>,
     data: BufWriter<File>,
+    sync_policy: SyncPolicy,
+    last_sync: Instant,
 }
 
 impl LedgerWriter {
 
-    pub fn recover(ledger_path: &str) -> io::Result<Self> {
-        recover_ledger(ledger_path)?;
-        LedgerWriter::open(ledger_path, false)
+    /// Open an existing ledger for appending, along with the number of entries it holds.
+    /// Errors keep their kind but name `ledger_path`, so a wrong path is easy to spot.
+    pub fn recover(ledger_path: &str) -> io::Result<(Self, u64)> {
+        let recovered = recover_ledger(ledger_path)
+            .and_then(|num_entries| Ok((LedgerWriter::open(ledger_path, false)?, num_entries)));
+        recovered.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("can't recover ledger at {}: {}", ledger_path, e),
+            )
+        })
     }
 
 
@@ -261,7 +296,34 @@ impl LedgerWriter {
         }
         let data = BufWriter::new(data);
 
-        Ok(LedgerWriter { index, data })
+        Ok(LedgerWriter {
+            index,
+            data,
+            sync_policy: SyncPolicy::default(),
+            last_sync: Instant::now(),
+        })
+    }
+
+    pub fn set_sync_policy(&mut self, sync_policy: SyncPolicy) {
+        self.sync_policy = sync_policy;
+    }
+
+    /// Flush what has been written and sync it to disk if the policy says it is time.
+    /// The data file goes first, so a synced index never points past synced data.
+    fn flush(&mut self) -> io::Result<()> {
+        self.index.flush()?;
+        self.data.flush()?;
+        let is_due = match self.sync_policy {
+            SyncPolicy::EveryBatch => true,
+            SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+            SyncPolicy::Never => false,
+        };
+        if is_due {
+            self.data.get_ref().sync_data()?;
+            self.index.get_ref().sync_data()?;
+            self.last_sync = Instant::now();
+        }
+        Ok(())
     }
 
     fn write_entry_noflush(&mut self, entry: &Entry) -> io::Result<()> {
@@ -293,21 +355,24 @@ impl LedgerWriter {
 
     pub fn write_entry(&mut self, entry: &Entry) -> io::Result<()> {
         self.write_entry_noflush(&entry)?;
-        self.index.flush()?;
-        self.data.flush()?;
-        Ok(())
+        self.flush()
     }
 
-    pub fn write_entries<I>(&mut self, entries: I) -> io::Result<()>
+    pub fn write_entries<I, E>(&mut self, entries: I) -> io::Result<()>
     where
-        I: IntoIterator<Item = Entry>,
+        I: IntoIterator<Item = E>,
+        E: Borrow<Entry>,
     {
         for entry in entries {
-            self.write_entry_noflush(&entry)?;
+            self.write_entry_noflush(entry.borrow())?;
         }
-        self.index.flush()?;
-        self.data.flush()?;
-        Ok(())
+        self.flush()
+    }
+}
+
+impl EntryWriter for LedgerWriter {
+    fn write_entries(&mut self, entries: &[Entry]) -> io::Result<()> {
+        LedgerWriter::write_entries(self, entries)
     }
 }
 
@@ -387,7 +452,7 @@ impl Block for [Entry] {
                 entry
                     .transactions
                     .iter()
-                    .filter_map(FinPlanTransaction::vote)
+                    .filter_map(VoteTransaction::vote)
             }).collect()
     }
 }
@@ -505,7 +570,7 @@ pub fn genesis(name: &str, num: i64) -> (Mint, String) {
 mod tests {
     use super::*;
     use bincode::serialized_size;
-    use fin_plan_instruction::Vote;
+    use vote_program::Vote;
     use fin_plan_transaction::FinPlanTransaction;
     use chrono::prelude::*;
     use entry::{next_entry, Entry};
@@ -559,7 +624,7 @@ mod tests {
         let zero = Hash::default();
         let one = hash(&zero.as_ref());
         let keypair = Keypair::new();
-        let tx0 = Transaction::fin_plan_new_vote(
+        let tx0 = Transaction::vote_new(
             &keypair,
             Vote {
                 version: 0,
@@ -608,7 +673,7 @@ mod tests {
         let id = Hash::default();
         let next_id = hash(&id.as_ref());
         let keypair = Keypair::new();
-        let tx_small = Transaction::fin_plan_new_vote(
+        let tx_small = Transaction::vote_new(
             &keypair,
             Vote {
                 version: 0,
@@ -754,7 +819,8 @@ mod tests {
         // verify should fail at first
         assert!(verify_ledger(&ledger_path).is_err());
         {
-            let mut writer = LedgerWriter::recover(&ledger_path).unwrap();
+            let (mut writer, num_entries) = LedgerWriter::recover(&ledger_path).unwrap();
+            assert_eq!(num_entries, entries.len() as u64 - 1);
             writer.write_entry(&entries[entries.len() - 1]).unwrap();
         }
         // and be fine after recover()
@@ -775,7 +841,8 @@ mod tests {
         garbage_on_data(&ledger_path, entries[..entries.len() - 1].to_vec());
         assert!(verify_ledger(&ledger_path).is_err());
         {
-            let mut writer = LedgerWriter::recover(&ledger_path).unwrap();
+            let (mut writer, num_entries) = LedgerWriter::recover(&ledger_path).unwrap();
+            assert_eq!(num_entries, entries.len() as u64 - 1);
             writer.write_entry(&entries[entries.len() - 1]).unwrap();
         }
         verify_ledger(&ledger_path).unwrap();
@@ -784,6 +851,28 @@ mod tests {
         let _ignored = remove_dir_all(&ledger_path);
     }
 
+    #[test]
+    fn test_sync_every_batch_survives_crash() {
+        let entries = make_tiny_test_entries(10);
+        let ledger_path = tmp_ledger_path("test_sync_every_batch_survives_crash");
+        {
+            let mut writer = LedgerWriter::open(&ledger_path, true).unwrap();
+            writer.set_sync_policy(SyncPolicy::EveryBatch);
+            writer.write_entries(entries[..5].to_vec()).unwrap();
+            writer.write_entries(entries[5..].to_vec()).unwrap();
+
+            // Crash partway through the next entry, without the writer being dropped
+            writer.data.write_all(b"torn").unwrap();
+            writer.data.flush().unwrap();
+            std::mem::forget(writer);
+        }
+
+        let (_, num_entries) = LedgerWriter::recover(&ledger_path).unwrap();
+        assert_eq!(num_entries, entries.len() as u64);
+        read_ledger_check(&ledger_path, entries.clone(), entries.len());
+        let _ignored = remove_dir_all(&ledger_path);
+    }
+
     #[test]
     fn test_verify_ledger() {
         use logger;
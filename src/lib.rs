@@ -35,6 +35,8 @@ pub mod trx_out;
 pub mod pod;
 pub mod pod_recorder;
 pub mod recvmmsg;
+#[cfg(all(unix, feature = "async-recvmmsg"))]
+pub mod recvmmsg_async;
 pub mod replicate_stage;
 pub mod replicator;
 pub mod request;
@@ -44,6 +46,7 @@ pub mod result;
 pub mod retransmit_stage;
 pub mod rpc;
 pub mod rpu;
+pub mod sendmmsg;
 pub mod service;
 pub mod signature;
 pub mod sigverify;
@@ -60,7 +63,9 @@ pub mod timing;
 pub mod tx_creator;
 pub mod transaction;
 pub mod tx_signer;
+pub mod vote_program;
 pub mod vote_stage;
+pub mod vote_transaction;
 pub mod qtc;
 pub mod window;
 pub mod window_service;
@@ -75,10 +80,14 @@ extern crate dirs;
 extern crate generic_array;
 extern crate ipnetwork;
 extern crate itertools;
+#[macro_use]
+extern crate lazy_static;
 extern crate libc;
 extern crate libloading;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "async-recvmmsg")]
+extern crate mio;
 extern crate nix;
 extern crate pnet_datalink;
 extern crate rayon;
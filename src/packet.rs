@@ -7,16 +7,19 @@ use hash::Hash;
 #[cfg(test)]
 use ledger::{next_entries_mut, Block};
 use log::Level;
-use recvmmsg::{recv_mmsg, NUM_RCVMMSGS};
+use recvmmsg::{MmsgReceiver, NUM_RCVMMSGS};
 use result::{Error, Result};
 use serde::Serialize;
 use xpz_program_interface::pubkey::Pubkey;
+use std::cmp;
 use std::fmt;
 use std::io;
 use std::mem::size_of;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::slice;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 pub type SharedPackets = Arc<RwLock<Packets>>;
 pub type SharedBlob = Arc<RwLock<Blob>>;
@@ -36,6 +39,10 @@ pub struct Meta {
     pub addr: [u16; 8],
     pub port: u16,
     pub v6: bool,
+    /// When the packet was received, as time since the unix epoch
+    pub timestamp: Duration,
+    /// Whether the datagram was larger than `data` and only its first `size` bytes were kept
+    pub truncated: bool,
 }
 
 #[derive(Clone)]
@@ -65,6 +72,56 @@ impl Default for Packet {
     }
 }
 
+impl Packet {
+    /// The bytes received into this packet. A `meta.size` past the end of `data` is cut
+    /// short rather than trusted.
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..cmp::min(self.meta.size, PACKET_DATA_SIZE)]
+    }
+}
+
+/// The packets a `recv_mmsg` call filled, without the unused ones after them.
+pub struct FilledPackets<'a> {
+    packets: &'a [Packet],
+}
+
+impl<'a> FilledPackets<'a> {
+    /// The first `count` of `packets`, as returned by `recv_mmsg`.
+    pub fn new(packets: &'a [Packet], count: usize) -> Self {
+        FilledPackets {
+            packets: &packets[..cmp::min(count, packets.len())],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// The payload of each filled packet, in the order they were received.
+    pub fn payloads(&self) -> Payloads<'a> {
+        Payloads {
+            packets: self.packets.iter(),
+        }
+    }
+}
+
+/// Iterator over the payloads of a `FilledPackets`.
+pub struct Payloads<'a> {
+    packets: slice::Iter<'a, Packet>,
+}
+
+impl<'a> Iterator for Payloads<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        self.packets.next().map(Packet::payload)
+    }
+}
+
 impl Meta {
     pub fn addr(&self) -> SocketAddr {
         if !self.v6 {
@@ -154,28 +211,37 @@ pub enum BlobError {
 
 impl Packets {
     fn run_read_from(&mut self, socket: &UdpSocket) -> Result<usize> {
-        self.packets.resize(NUM_PACKETS, Packet::default());
-        let mut i = 0;
- 
         socket.set_nonblocking(false)?;
         trace!("receiving on {}", socket.local_addr().unwrap());
+        let npkts = self.read_batches(socket)?;
+        debug!("got {:?} messages on {}", npkts, socket.local_addr().unwrap());
+        socket.set_nonblocking(true)?;
+        Ok(npkts)
+    }
+    fn read_batches<R: MmsgReceiver + ?Sized>(&mut self, receiver: &R) -> Result<usize> {
+        self.packets.resize(NUM_PACKETS, Packet::default());
+        let mut i = 0;
         loop {
-            match recv_mmsg(socket, &mut self.packets[i..]) {
+            match receiver.recv_mmsg(&mut self.packets[i..]) {
                 Err(_) if i > 0 => {
                     inc_new_counter_info!("packets-recv_count", i);
-                    debug!("got {:?} messages on {}", i, socket.local_addr().unwrap());
-                    socket.set_nonblocking(true)?;
                     return Ok(i);
                 }
                 Err(e) => {
                     trace!("recv_from err {:?}", e);
                     return Err(Error::IO(e));
                 }
+                Ok(0) if i == 0 => {
+                    trace!("recv_from timed out");
+                    return Err(Error::IO(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "no packets received",
+                    )));
+                }
                 Ok(npkts) => {
                     trace!("got {} packets", npkts);
                     i += npkts;
                     if npkts != NUM_RCVMMSGS {
-                        socket.set_nonblocking(true)?;
                         inc_new_counter_info!("packets-recv_count", i);
                         return Ok(i);
                     }
@@ -189,10 +255,17 @@ impl Packets {
         debug!("recv_from: {}", sz);
         Ok(())
     }
+    /// Like `recv_from`, but reads from any `MmsgReceiver`
+    pub fn recv_from_receiver<R: MmsgReceiver + ?Sized>(&mut self, receiver: &R) -> Result<()> {
+        let sz = self.read_batches(receiver)?;
+        self.packets.resize(sz, Packet::default());
+        debug!("recv_from_receiver: {}", sz);
+        Ok(())
+    }
     pub fn send_to(&self, socket: &UdpSocket) -> Result<()> {
         for p in &self.packets {
             let a = p.meta.addr();
-            socket.send_to(&p.data[..p.meta.size], &a)?;
+            socket.send_to(p.payload(), &a)?;
         }
         Ok(())
     }
@@ -413,10 +486,12 @@ pub fn make_consecutive_blobs(
 #[cfg(test)]
 mod tests {
     use packet::{
-        to_packets, Blob, Meta, Packet, Packets, SharedBlob, SharedPackets, NUM_PACKETS,
-        PACKET_DATA_SIZE,
+        to_packets, Blob, FilledPackets, Meta, Packet, Packets, SharedBlob, SharedPackets,
+        NUM_PACKETS, PACKET_DATA_SIZE,
     };
+    use recvmmsg::recv_mmsg;
     use request::Request;
+    use std::time::Duration;
     use std::io;
     use std::io::Write;
     use std::net::UdpSocket;
@@ -441,6 +516,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_packet_batch_payloads() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let sizes = [1, 100, PACKET_DATA_SIZE];
+        for (i, size) in sizes.iter().enumerate() {
+            sender.send_to(&vec![i as u8; *size], &addr).unwrap();
+        }
+
+        let mut packets = vec![Packet::default(); 8];
+        let count = recv_mmsg(&reader, &mut packets[..], Duration::from_secs(1)).unwrap();
+        assert_eq!(count, sizes.len());
+        let batch = FilledPackets::new(&packets, count);
+        assert_eq!(batch.len(), sizes.len());
+        for ((payload, packet), size) in batch.payloads().zip(&packets).zip(&sizes) {
+            assert_eq!(payload.len(), packet.meta.size);
+            assert_eq!(payload.len(), *size);
+        }
+
+        // A corrupt size never reaches past the end of the packet
+        let mut packet = Packet::default();
+        packet.meta.size = PACKET_DATA_SIZE + 1;
+        assert_eq!(packet.payload().len(), PACKET_DATA_SIZE);
+    }
+
     #[test]
     fn test_to_packets() {
         let tx = Request::GetTransactionCount;
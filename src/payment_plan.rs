@@ -11,13 +11,16 @@ pub enum Witness {
 
  
     Signature,
+
+ 
+    BlockHeight(u64),
 }
 
  
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Payment {
  
-    pub tokens: i64,
+    pub tokens: u64,
  
     pub to: Pubkey,
 }
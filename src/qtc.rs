@@ -400,7 +400,7 @@ pub fn process_command(config: &QtcConfig) -> Result<String, Box<error::Error>>
                     contract_state.pubkey(),
                     last_id,
                     1,
-                    196,
+                    512,
                     fin_plan_program_id,
                     0,
                 );
@@ -414,7 +414,7 @@ pub fn process_command(config: &QtcConfig) -> Result<String, Box<error::Error>>
                     dt,
                     dt_pubkey,
                     cancelable,
-                    tokens,
+                    tokens as u64,
                     last_id,
                 );
                 let signature_str = serialize_and_send_tx(&config, &tx)?;
@@ -455,7 +455,7 @@ pub fn process_command(config: &QtcConfig) -> Result<String, Box<error::Error>>
                     contract_state.pubkey(),
                     last_id,
                     1,
-                    196,
+                    512,
                     fin_plan_program_id,
                     0,
                 );
@@ -468,7 +468,7 @@ pub fn process_command(config: &QtcConfig) -> Result<String, Box<error::Error>>
                     contract_state.pubkey(),
                     witness,
                     cancelable,
-                    tokens,
+                    tokens as u64,
                     last_id,
                 );
                 let signature_str = serialize_and_send_tx(&config, &tx)?;
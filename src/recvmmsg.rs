@@ -1,56 +1,298 @@
  
 
+use counter::Counter;
+use log::Level;
 use packet::Packet;
 use std::cmp;
 use std::io;
+use std::mem;
 use std::net::UdpSocket;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(not(target_os = "linux"))]
+use timing::duration_since_epoch;
 
 pub const NUM_RCVMMSGS: usize = 16;
 
+/// How long `recv_mmsg` callers in the streamer wait for the first packet of a batch
+pub const RECV_MMSG_TIMEOUT_MS: u64 = 1000;
+
+static RECV_MMSG_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of receive errors seen by `recv_mmsg` since startup, not counting
+/// the `WouldBlock` that ends a non-blocking drain or a timeout
+pub fn recv_mmsg_error_count() -> usize {
+    RECV_MMSG_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Whether `e` only means that nothing arrived in time
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+fn record_error(e: &io::Error) {
+    if !is_timeout(e) {
+        RECV_MMSG_ERRORS.fetch_add(1, Ordering::Relaxed);
+        inc_new_counter_info!("recvmmsg-error", 1);
+    }
+}
+
+/// Read up to `NUM_RCVMMSGS` packets, waiting at most `timeout` for the first one.
+/// `timeout` replaces the socket's read timeout and must not be zero.
+pub fn recv_mmsg(
+    sock: &UdpSocket,
+    packets: &mut [Packet],
+    timeout: Duration,
+) -> io::Result<usize> {
+    recv_mmsg_with_batch(sock, packets, NUM_RCVMMSGS, timeout)
+}
+
+/// Anything the streamer can read packet batches from. `UdpSocket` is the real one; tests
+/// can swap in a fake that hands back scripted batches.
+pub trait MmsgReceiver {
+    /// Read up to `NUM_RCVMMSGS` packets into `packets`, waiting at most
+    /// `RECV_MMSG_TIMEOUT_MS` for the first one
+    fn recv_mmsg(&self, packets: &mut [Packet]) -> io::Result<usize>;
+}
+
+impl MmsgReceiver for UdpSocket {
+    fn recv_mmsg(&self, packets: &mut [Packet]) -> io::Result<usize> {
+        recv_mmsg(self, packets, Duration::from_millis(RECV_MMSG_TIMEOUT_MS))
+    }
+}
+
+/// Like `recv_mmsg`, but returns straight away with only the packets already queued,
+/// `Ok(0)` if there are none
+pub fn recv_mmsg_nonblocking(sock: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize> {
+    recv_batch(sock, packets, NUM_RCVMMSGS, None).map(|(npkts, _)| npkts)
+}
+
+/// Like `recv_mmsg`, but also returns the total bytes received, for callers that limit
+/// themselves by bandwidth
+pub fn recv_mmsg_counted(
+    sock: &UdpSocket,
+    packets: &mut [Packet],
+    timeout: Duration,
+) -> io::Result<(usize, usize)> {
+    recv_batch(sock, packets, NUM_RCVMMSGS, Some(timeout))
+}
+
+/// Like `recv_mmsg`, but reads up to `max_batch` packets instead of `NUM_RCVMMSGS`
+pub fn recv_mmsg_with_batch(
+    sock: &UdpSocket,
+    packets: &mut [Packet],
+    max_batch: usize,
+    timeout: Duration,
+) -> io::Result<usize> {
+    recv_batch(sock, packets, max_batch, Some(timeout)).map(|(npkts, _)| npkts)
+}
+
+/// Packet buffers shared by successive `recv_mmsg_pooled` calls, so a receive loop
+/// doesn't allocate a fresh batch every time
+#[derive(Clone, Default)]
+pub struct PacketPool {
+    buffers: Arc<Mutex<Vec<Vec<Packet>>>>,
+}
+
+impl PacketPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of buffers waiting to be reused
+    pub fn len(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lease(&self) -> Vec<Packet> {
+        self.buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![Packet::default(); NUM_RCVMMSGS])
+    }
+
+    fn give_back(&self, packets: Vec<Packet>) {
+        self.buffers.lock().unwrap().push(packets);
+    }
+}
+
+/// The packets read by one `recv_mmsg_pooled` call. The buffer goes back to its pool
+/// when the batch is dropped.
+pub struct PacketBatch {
+    packets: Vec<Packet>,
+    len: usize,
+    pool: PacketPool,
+}
+
+impl Deref for PacketBatch {
+    type Target = [Packet];
+
+    fn deref(&self) -> &[Packet] {
+        &self.packets[..self.len]
+    }
+}
+
+impl Drop for PacketBatch {
+    fn drop(&mut self) {
+        self.pool.give_back(mem::replace(&mut self.packets, vec![]));
+    }
+}
+
+/// Like `recv_mmsg`, but reads into a buffer leased from `pool`
+pub fn recv_mmsg_pooled(
+    sock: &UdpSocket,
+    pool: &PacketPool,
+    timeout: Duration,
+) -> io::Result<PacketBatch> {
+    let mut packets = pool.lease();
+    match recv_mmsg(sock, &mut packets[..], timeout) {
+        Ok(len) => Ok(PacketBatch {
+            packets,
+            len,
+            pool: pool.clone(),
+        }),
+        Err(e) => {
+            pool.give_back(packets);
+            Err(e)
+        }
+    }
+}
+
+/// `recv_from` doesn't say whether it cut a datagram short, so `meta.truncated` is
+/// never set here.
 #[cfg(not(target_os = "linux"))]
-pub fn recv_mmsg(socket: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize> {
-    let mut i = 0;
-    socket.set_nonblocking(false)?;
-    let count = cmp::min(NUM_RCVMMSGS, packets.len());
-    for p in packets.iter_mut().take(count) {
-        p.meta.size = 0;
+fn recv_one(socket: &UdpSocket, p: &mut Packet) -> io::Result<()> {
+    p.meta.size = 0;
+    p.meta.truncated = false;
+    let mut retried = false;
+    loop {
         match socket.recv_from(&mut p.data) {
-            Err(_) if i > 0 => {
-                break;
-            }
             Err(e) => {
+                record_error(&e);
+                if e.kind() == io::ErrorKind::Interrupted && !retried {
+                    retried = true;
+                    continue;
+                }
                 return Err(e);
             }
             Ok((nrecv, from)) => {
                 p.meta.size = nrecv;
                 p.meta.set_addr(&from);
-                if i == 0 {
-                    socket.set_nonblocking(true)?;
-                }
+                p.meta.timestamp = duration_since_epoch();
+                return Ok(());
             }
         }
+    }
+}
+
+/// Read up to `max_batch` packets, waiting up to `wait` for the first one, or not at all
+/// if it is `None`. Returns the packets and bytes read. Without recvmmsg there is no
+/// per-call flag, so the socket is left non-blocking either way.
+#[cfg(not(target_os = "linux"))]
+fn recv_batch(
+    socket: &UdpSocket,
+    packets: &mut [Packet],
+    max_batch: usize,
+    wait: Option<Duration>,
+) -> io::Result<(usize, usize)> {
+    let count = cmp::min(max_batch, packets.len());
+    if count == 0 {
+        return Ok((0, 0));
+    }
+    // A buffer reused from a bigger batch mustn't keep reporting its old size
+    for p in &mut packets[..count] {
+        p.meta.size = 0;
+    }
+
+    // Wait up to `wait` for the first packet...
+    match wait {
+        Some(timeout) => {
+            socket.set_nonblocking(false)?;
+            socket.set_read_timeout(Some(timeout))?;
+        }
+        None => socket.set_nonblocking(true)?,
+    }
+    match recv_one(socket, &mut packets[0]) {
+        Ok(()) => (),
+        Err(ref e) if is_timeout(e) => return Ok((0, 0)),
+        Err(e) => return Err(e),
+    }
+
+    // ...then only take what is already queued behind it
+    socket.set_nonblocking(true)?;
+    let mut i = 1;
+    let mut total_size = packets[0].meta.size;
+    while i < count && recv_one(socket, &mut packets[i]).is_ok() {
+        total_size += packets[i].meta.size;
         i += 1;
     }
-    Ok(i)
+    Ok((i, total_size))
 }
 
+/// Read up to `max_batch` packets, waiting up to `wait` for the first one, or not at all
+/// if it is `None`. Returns the packets and bytes read. Not waiting is a flag on this call
+/// alone; the socket stays blocking.
 #[cfg(target_os = "linux")]
-pub fn recv_mmsg(sock: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize> {
+fn recv_batch(
+    sock: &UdpSocket,
+    packets: &mut [Packet],
+    max_batch: usize,
+    wait: Option<Duration>,
+) -> io::Result<(usize, usize)> {
     use libc::{
-        c_void, iovec, mmsghdr, recvmmsg, sockaddr_in, socklen_t, time_t, timespec, MSG_WAITFORONE,
+        c_int, c_long, c_void, iovec, mmsghdr, recvmmsg, setsockopt, sockaddr_in, socklen_t,
+        time_t, timespec, CMSG_DATA, CMSG_FIRSTHDR, CMSG_NXTHDR, MSG_DONTWAIT, MSG_TRUNC,
+        MSG_WAITFORONE, SCM_TIMESTAMPNS, SOL_SOCKET, SO_TIMESTAMPNS,
     };
     use nix::sys::socket::InetAddr;
-    use std::mem;
     use std::os::unix::io::AsRawFd;
+    use std::ptr;
+    use timing::duration_since_epoch;
 
-    let mut hdrs: [mmsghdr; NUM_RCVMMSGS] = unsafe { mem::zeroed() };
-    let mut iovs: [iovec; NUM_RCVMMSGS] = unsafe { mem::zeroed() };
-    let mut addr: [sockaddr_in; NUM_RCVMMSGS] = unsafe { mem::zeroed() };
-    let addrlen = mem::size_of_val(&addr) as socklen_t;
+    let count = cmp::min(max_batch, packets.len());
+    if count == 0 {
+        return Ok((0, 0));
+    }
+    // Only the packets read are filled in below, so a buffer reused from a bigger batch
+    // mustn't keep reporting its old size
+    for p in &mut packets[..count] {
+        p.meta.size = 0;
+    }
+
+    // recvmmsg only checks its own timeout after a datagram arrives, so the socket's
+    // read timeout is what bounds the wait on a quiet socket
+    if let Some(timeout) = wait {
+        sock.set_read_timeout(Some(timeout))?;
+    }
+
+    // The headers point into `iovs` and `addr`, so none of these may be resized below
+    let mut hdrs: Vec<mmsghdr> = (0..count).map(|_| unsafe { mem::zeroed() }).collect();
+    let mut iovs: Vec<iovec> = (0..count).map(|_| unsafe { mem::zeroed() }).collect();
+    let mut addr: Vec<sockaddr_in> = (0..count).map(|_| unsafe { mem::zeroed() }).collect();
+    // u64 words keep the control buffers aligned for `cmsghdr`
+    let mut ctrls: Vec<[u64; 8]> = vec![[0; 8]; count];
+    let addrlen = mem::size_of::<sockaddr_in>() as socklen_t;
 
     let sock_fd = sock.as_raw_fd();
 
-    let count = cmp::min(iovs.len(), packets.len());
+    // Ask the kernel to attach a receive timestamp to every datagram
+    let enable: c_int = 1;
+    unsafe {
+        setsockopt(
+            sock_fd,
+            SOL_SOCKET,
+            SO_TIMESTAMPNS,
+            &enable as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        );
+    }
 
     for i in 0..count {
         iovs[i].iov_base = packets[i].data.as_mut_ptr() as *mut c_void;
@@ -60,33 +302,85 @@ pub fn recv_mmsg(sock: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize>
         hdrs[i].msg_hdr.msg_namelen = addrlen;
         hdrs[i].msg_hdr.msg_iov = &mut iovs[i];
         hdrs[i].msg_hdr.msg_iovlen = 1;
+        hdrs[i].msg_hdr.msg_control = ctrls[i].as_mut_ptr() as *mut c_void;
+        hdrs[i].msg_hdr.msg_controllen = mem::size_of_val(&ctrls[i]) as _;
     }
-    let mut ts = timespec {
-        tv_sec: 1 as time_t,
-        tv_nsec: 0,
+
+    // Without a wait there is no recvmmsg timeout either; a zero one would stop the call
+    // after the first datagram
+    let mut ts: timespec = unsafe { mem::zeroed() };
+    let (flags, ts_ptr) = match wait {
+        Some(timeout) => {
+            ts.tv_sec = timeout.as_secs() as time_t;
+            ts.tv_nsec = timeout.subsec_nanos() as c_long;
+            (MSG_WAITFORONE, &mut ts as *mut timespec)
+        }
+        None => (MSG_DONTWAIT, ptr::null_mut()),
+    };
+    // Have `msg_len` report the full length of a datagram that didn't fit
+    let flags = flags | MSG_TRUNC;
+
+    let mut retried = false;
+    let npkts = loop {
+        match unsafe { recvmmsg(sock_fd, &mut hdrs[0], count as u32, flags, ts_ptr) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                record_error(&e);
+                if e.kind() == io::ErrorKind::Interrupted && !retried {
+                    retried = true;
+                    continue;
+                }
+                if is_timeout(&e) {
+                    return Ok((0, 0));
+                }
+                return Err(e);
+            }
+            n => break n as usize,
+        }
     };
 
-    let npkts =
-        match unsafe { recvmmsg(sock_fd, &mut hdrs[0], count as u32, MSG_WAITFORONE, &mut ts) } {
-            -1 => return Err(io::Error::last_os_error()),
-            n => {
-                for i in 0..n as usize {
-                    let mut p = &mut packets[i];
-                    p.meta.size = hdrs[i].msg_len as usize;
-                    let inet_addr = InetAddr::V4(addr[i]);
-                    p.meta.set_addr(&inet_addr.to_std());
+    let mut total_size = 0;
+    for i in 0..npkts {
+        let mut p = &mut packets[i];
+        let len = hdrs[i].msg_len as usize;
+        p.meta.truncated = len > p.data.len();
+        p.meta.size = cmp::min(len, p.data.len());
+        total_size += p.meta.size;
+        let inet_addr = InetAddr::V4(addr[i]);
+        p.meta.set_addr(&inet_addr.to_std());
+
+        // Fall back to the current time if the kernel didn't hand one back
+        p.meta.timestamp = duration_since_epoch();
+        unsafe {
+            let msg_hdr = &hdrs[i].msg_hdr;
+            let mut cmsg = CMSG_FIRSTHDR(msg_hdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == SOL_SOCKET && (*cmsg).cmsg_type == SCM_TIMESTAMPNS {
+                    let ts = *(CMSG_DATA(cmsg) as *const timespec);
+                    p.meta.timestamp = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+                    break;
                 }
-                n as usize
+                cmsg = CMSG_NXTHDR(msg_hdr, cmsg);
             }
-        };
+        }
+    }
 
-    Ok(npkts)
+    let truncated = packets[..npkts].iter().filter(|p| p.meta.truncated).count();
+    if truncated > 0 {
+        inc_new_counter_info!("recvmmsg-truncated", truncated);
+    }
+    Ok((npkts, total_size))
 }
 
 #[cfg(test)]
 mod tests {
     use packet::PACKET_DATA_SIZE;
     use recvmmsg::*;
+    use std::time::{Duration, Instant};
+
+    fn timeout() -> Duration {
+        Duration::from_millis(RECV_MMSG_TIMEOUT_MS)
+    }
 
     #[test]
     pub fn test_recv_mmsg_one_iter() {
@@ -101,7 +395,7 @@ mod tests {
         }
 
         let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
-        let recv = recv_mmsg(&reader, &mut packets[..]).unwrap();
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
         assert_eq!(sent, recv);
         for i in 0..recv {
             assert_eq!(packets[i].meta.size, PACKET_DATA_SIZE);
@@ -122,14 +416,14 @@ mod tests {
         }
 
         let mut packets = vec![Packet::default(); NUM_RCVMMSGS * 2];
-        let recv = recv_mmsg(&reader, &mut packets[..]).unwrap();
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
         assert_eq!(NUM_RCVMMSGS, recv);
         for i in 0..recv {
             assert_eq!(packets[i].meta.size, PACKET_DATA_SIZE);
             assert_eq!(packets[i].meta.addr(), saddr);
         }
 
-        let recv = recv_mmsg(&reader, &mut packets[..]).unwrap();
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
         assert_eq!(sent - NUM_RCVMMSGS, recv);
         for i in 0..recv {
             assert_eq!(packets[i].meta.size, PACKET_DATA_SIZE);
@@ -137,6 +431,237 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn test_recv_mmsg_reused_buffer() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+
+        for sent in vec![3, 1] {
+            for _ in 0..sent {
+                sender.send_to(&[0; PACKET_DATA_SIZE], &addr).unwrap();
+            }
+            let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
+            assert_eq!(recv, sent);
+            for p in &packets[..recv] {
+                assert_eq!(p.meta.size, PACKET_DATA_SIZE);
+            }
+            // Packets past the ones read don't keep the last call's sizes
+            for p in &packets[recv..] {
+                assert_eq!(p.meta.size, 0);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_with_batch() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let saddr = sender.local_addr().unwrap();
+        let sent = 40;
+        for _ in 0..sent {
+            let data = [0; PACKET_DATA_SIZE];
+            sender.send_to(&data[..], &addr).unwrap();
+        }
+
+        let mut packets = vec![Packet::default(); 64];
+        let recv = recv_mmsg_with_batch(&reader, &mut packets[..], 64, timeout()).unwrap();
+        assert_eq!(sent, recv);
+        for i in 0..recv {
+            assert_eq!(packets[i].meta.size, PACKET_DATA_SIZE);
+            assert_eq!(packets[i].meta.addr(), saddr);
+        }
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_with_batch_limited_by_packets() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let sent = 40;
+        for _ in 0..sent {
+            let data = [0; PACKET_DATA_SIZE];
+            sender.send_to(&data[..], &addr).unwrap();
+        }
+
+        let mut packets = vec![Packet::default(); 32];
+        let recv = recv_mmsg_with_batch(&reader, &mut packets[..], 64, timeout()).unwrap();
+        assert_eq!(32, recv);
+
+        let recv = recv_mmsg_with_batch(&reader, &mut packets[..], 64, timeout()).unwrap();
+        assert_eq!(sent - 32, recv);
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_timestamps() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let sent = NUM_RCVMMSGS;
+        for _ in 0..sent {
+            let data = [0; PACKET_DATA_SIZE];
+            sender.send_to(&data[..], &addr).unwrap();
+        }
+
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
+        assert_eq!(sent, recv);
+        assert!(packets[0].meta.timestamp > Duration::new(0, 0));
+        for i in 1..recv {
+            assert!(packets[i - 1].meta.timestamp <= packets[i].meta.timestamp);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    pub fn test_recv_mmsg_truncated() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        sender.send_to(&[1; PACKET_DATA_SIZE + 1], &addr).unwrap();
+        sender.send_to(&[2; PACKET_DATA_SIZE], &addr).unwrap();
+
+        // A buffer reused from an oversized datagram doesn't stay flagged
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+        packets[1].meta.truncated = true;
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
+        assert_eq!(recv, 2);
+        assert!(packets[0].meta.truncated);
+        assert_eq!(packets[0].meta.size, PACKET_DATA_SIZE);
+        assert!(!packets[1].meta.truncated);
+        assert_eq!(packets[1].meta.size, PACKET_DATA_SIZE);
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_error_count() {
+        // Sending to a port nobody listens on makes the next receive on the
+        // connected socket fail with ECONNREFUSED
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let closed = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        reader.connect(&closed_addr).unwrap();
+        reader.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+        reader.send(&[0; 8]).unwrap();
+
+        let before = recv_mmsg_error_count();
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+        assert!(recv_mmsg(&reader, &mut packets[..], timeout()).is_err());
+        assert!(recv_mmsg_error_count() > before);
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_timeout() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+
+        // Nothing is ever sent, so the call gives up after the short timeout
+        let start = Instant::now();
+        let recv = recv_mmsg(&reader, &mut packets[..], Duration::from_millis(100)).unwrap();
+        assert_eq!(recv, 0);
+        assert!(start.elapsed() < timeout());
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_does_not_wait_for_a_full_batch() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        sender.send_to(&[0; PACKET_DATA_SIZE], &addr).unwrap();
+
+        // Once the first packet is in, the rest of the batch is only what is queued
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+        let start = Instant::now();
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
+        assert_eq!(recv, 1);
+        assert!(start.elapsed() < timeout());
+
+        // And a quiet socket still comes back empty
+        let recv = recv_mmsg(&reader, &mut packets[..], Duration::from_millis(100)).unwrap();
+        assert_eq!(recv, 0);
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_nonblocking() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+
+        // An empty socket comes back empty without waiting out a timeout
+        let start = Instant::now();
+        assert_eq!(recv_mmsg_nonblocking(&reader, &mut packets[..]).unwrap(), 0);
+        assert!(start.elapsed() < timeout());
+
+        let sent = 3;
+        for _ in 0..sent {
+            sender.send_to(&[0; PACKET_DATA_SIZE], &addr).unwrap();
+        }
+        // Give the datagrams time to reach the socket's queue
+        let start = Instant::now();
+        let mut recv = 0;
+        while recv < sent && start.elapsed() < timeout() {
+            recv += recv_mmsg_nonblocking(&reader, &mut packets[recv..]).unwrap();
+        }
+        assert_eq!(recv, sent);
+
+        // The default still waits for a packet
+        sender.send_to(&[0; PACKET_DATA_SIZE], &addr).unwrap();
+        assert_eq!(recv_mmsg(&reader, &mut packets[..], timeout()).unwrap(), 1);
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_counted() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let sent = NUM_RCVMMSGS - 1;
+        for _ in 0..sent {
+            sender.send_to(&[0; PACKET_DATA_SIZE], &addr).unwrap();
+        }
+
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+        let (recv, total_size) = recv_mmsg_counted(&reader, &mut packets[..], timeout()).unwrap();
+        assert_eq!(recv, sent);
+        assert_eq!(total_size, recv * PACKET_DATA_SIZE);
+
+        // Nothing received counts no bytes
+        let mut packets = vec![Packet::default(); NUM_RCVMMSGS];
+        let short = Duration::from_millis(10);
+        assert_eq!(
+            recv_mmsg_counted(&reader, &mut packets[..], short).unwrap(),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    pub fn test_recv_mmsg_pooled() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let saddr = sender.local_addr().unwrap();
+        let pool = PacketPool::new();
+
+        let mut buffers = vec![];
+        for sent in 1..4 {
+            for _ in 0..sent {
+                let data = [0; PACKET_DATA_SIZE];
+                sender.send_to(&data[..], &addr).unwrap();
+            }
+            let batch = recv_mmsg_pooled(&reader, &pool, timeout()).unwrap();
+            assert_eq!(batch.len(), sent);
+            assert_eq!(batch[0].meta.addr(), saddr);
+            assert!(pool.is_empty());
+            buffers.push(batch.as_ptr());
+        }
+        assert_eq!(pool.len(), 1);
+
+        // Each batch went back to the pool, so every call read into the same buffer
+        assert!(buffers.iter().all(|p| *p == buffers[0]));
+    }
+
     #[test]
     pub fn test_recv_mmsg_multi_addrs() {
         let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
@@ -162,7 +687,7 @@ mod tests {
 
         let mut packets = vec![Packet::default(); NUM_RCVMMSGS * 2];
 
-        let recv = recv_mmsg(&reader, &mut packets[..]).unwrap();
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
         assert_eq!(NUM_RCVMMSGS, recv);
         for i in 0..sent1 {
             assert_eq!(packets[i].meta.size, PACKET_DATA_SIZE);
@@ -174,7 +699,7 @@ mod tests {
             assert_eq!(packets[i].meta.addr(), saddr2);
         }
 
-        let recv = recv_mmsg(&reader, &mut packets[..]).unwrap();
+        let recv = recv_mmsg(&reader, &mut packets[..], timeout()).unwrap();
         assert_eq!(sent1 + sent2 - NUM_RCVMMSGS, recv);
         for i in 0..recv {
             assert_eq!(packets[i].meta.size, PACKET_DATA_SIZE);
@@ -0,0 +1,99 @@
+//! A tokio front end to `recv_mmsg`, so a reactor can wait for packets instead of a
+//! thread blocking on the socket.
+
+use mio::Ready;
+use packet::Packet;
+use recvmmsg::{recv_mmsg_nonblocking, NUM_RCVMMSGS};
+use std::io;
+use std::mem;
+use std::net;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use tokio::net::UdpSocket;
+use tokio::prelude::*;
+
+/// Future returned by `recv_mmsg_async`.
+pub struct RecvMmsg {
+    socket: Option<UdpSocket>,
+    packets: Vec<Packet>,
+}
+
+/// Wait for `socket` to become readable, then read up to `NUM_RCVMMSGS` packets from it in
+/// one batch. Resolves to the socket and the packets read, at least one of them.
+pub fn recv_mmsg_async(socket: UdpSocket) -> RecvMmsg {
+    RecvMmsg {
+        socket: Some(socket),
+        packets: vec![Packet::default(); NUM_RCVMMSGS],
+    }
+}
+
+/// Read whatever is queued on `socket` without waiting.
+fn recv_queued(socket: &UdpSocket, packets: &mut [Packet]) -> io::Result<usize> {
+    // Borrow the descriptor for the batched read; `into_raw_fd` hands it back unclosed
+    let sock = unsafe { net::UdpSocket::from_raw_fd(socket.as_raw_fd()) };
+    let result = recv_mmsg_nonblocking(&sock, packets);
+    sock.into_raw_fd();
+    result
+}
+
+impl Future for RecvMmsg {
+    type Item = (UdpSocket, Vec<Packet>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, io::Error> {
+        let npkts = {
+            let socket = self
+                .socket
+                .as_ref()
+                .expect("RecvMmsg polled after it completed");
+            match socket.poll_read_ready(Ready::readable())? {
+                Async::Ready(_) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+            let npkts = recv_queued(socket, &mut self.packets)?;
+            if npkts == 0 {
+                // Woken for nothing; wait for the next readiness event
+                socket.clear_read_ready(Ready::readable())?;
+                return Ok(Async::NotReady);
+            }
+            npkts
+        };
+        let mut packets = mem::replace(&mut self.packets, vec![]);
+        packets.truncate(npkts);
+        Ok(Async::Ready((self.socket.take().unwrap(), packets)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packet::PACKET_DATA_SIZE;
+    use recvmmsg_async::*;
+    use std::net;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn test_recv_mmsg_async_burst() {
+        let mut runtime = Runtime::new().unwrap();
+        let mut reader = UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).expect("bind");
+        let addr = reader.local_addr().unwrap();
+        let sender = net::UdpSocket::bind("127.0.0.1:0").expect("bind");
+
+        let sent = NUM_RCVMMSGS + 10;
+        for _ in 0..sent {
+            sender.send_to(&[0; PACKET_DATA_SIZE], &addr).unwrap();
+        }
+
+        let mut recv = 0;
+        while recv < sent {
+            let (socket, packets) = runtime.block_on(recv_mmsg_async(reader)).unwrap();
+            assert!(!packets.is_empty());
+            assert!(packets.len() <= NUM_RCVMMSGS);
+            for p in &packets {
+                assert_eq!(p.meta.size, PACKET_DATA_SIZE);
+                assert_eq!(p.meta.addr(), sender.local_addr().unwrap());
+            }
+            recv += packets.len();
+            reader = socket;
+        }
+        assert_eq!(recv, sent);
+    }
+}
@@ -28,7 +28,7 @@ impl RequestStage {
         p.packets
             .par_iter()
             .map(|x| {
-                deserialize(&x.data[0..x.meta.size])
+                deserialize(x.payload())
                     .map(|req| (req, x.meta.addr()))
                     .ok()
             }).collect()
@@ -25,6 +25,11 @@ pub enum Error {
     #[cfg(feature = "erasure")]
     ErasureError(erasure::ErasureError),
     SendError,
+    LedgerWriteError(std::io::Error),
+    /// A lock was poisoned by a thread that panicked while holding it
+    PoisonError,
+    /// Entries didn't chain onto the last entry in the ledger, or onto each other
+    BrokenEntryChain,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -68,6 +73,11 @@ impl<T> std::convert::From<std::sync::mpsc::SendError<T>> for Error {
         Error::SendError
     }
 }
+impl<T> std::convert::From<std::sync::PoisonError<T>> for Error {
+    fn from(_e: std::sync::PoisonError<T>) -> Error {
+        Error::PoisonError
+    }
+}
 impl std::convert::From<Box<Any + Send + 'static>> for Error {
     fn from(e: Box<Any + Send + 'static>) -> Error {
         Error::JoinError(e)
@@ -0,0 +1,144 @@
+use packet::Packet;
+use std::io;
+use std::net::UdpSocket;
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_mmsg(sock: &UdpSocket, packets: &[Packet]) -> io::Result<usize> {
+    let mut sent = 0;
+    for p in packets {
+        let a = p.meta.addr();
+        match sock.send_to(p.payload(), &a) {
+            Err(_) if sent > 0 => break,
+            Err(e) => return Err(e),
+            Ok(_) => sent += 1,
+        }
+    }
+    Ok(sent)
+}
+
+/// Send every packet to its `meta.addr()`, returning how many made it out.
+/// A failure after some packets were sent is reported as a short count.
+#[cfg(target_os = "linux")]
+pub fn send_mmsg(sock: &UdpSocket, packets: &[Packet]) -> io::Result<usize> {
+    use libc::{c_void, iovec, mmsghdr, sendmmsg};
+    use nix::sys::socket::{InetAddr, SockAddr};
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    if packets.is_empty() {
+        return Ok(0);
+    }
+
+    // The headers point into `iovs` and `addrs`, so none of these may be resized below
+    let addrs: Vec<SockAddr> = packets
+        .iter()
+        .map(|p| SockAddr::new_inet(InetAddr::from_std(&p.meta.addr())))
+        .collect();
+    let mut iovs: Vec<iovec> = packets
+        .iter()
+        .map(|p| iovec {
+            iov_base: p.data.as_ptr() as *mut c_void,
+            iov_len: p.meta.size,
+        }).collect();
+    let mut hdrs: Vec<mmsghdr> = (0..packets.len())
+        .map(|_| unsafe { mem::zeroed() })
+        .collect();
+
+    for i in 0..packets.len() {
+        let (addr, addrlen) = unsafe { addrs[i].as_ffi_pair() };
+        hdrs[i].msg_hdr.msg_name = addr as *const _ as *mut c_void;
+        hdrs[i].msg_hdr.msg_namelen = addrlen;
+        hdrs[i].msg_hdr.msg_iov = &mut iovs[i];
+        hdrs[i].msg_hdr.msg_iovlen = 1;
+    }
+
+    let sock_fd = sock.as_raw_fd();
+    let mut sent = 0;
+    while sent < packets.len() {
+        let remaining = (packets.len() - sent) as u32;
+        match unsafe { sendmmsg(sock_fd, &mut hdrs[sent], remaining, 0) } {
+            -1 if sent > 0 => break,
+            -1 => return Err(io::Error::last_os_error()),
+            n => sent += n as usize,
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use packet::{Packet, PACKET_DATA_SIZE};
+    use recvmmsg::{recv_mmsg_with_batch, RECV_MMSG_TIMEOUT_MS};
+    use sendmmsg::send_mmsg;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    fn timeout() -> Duration {
+        Duration::from_millis(RECV_MMSG_TIMEOUT_MS)
+    }
+
+    fn make_packets(num: usize, size: usize, dest: &UdpSocket) -> Vec<Packet> {
+        let addr = dest.local_addr().unwrap();
+        (0..num)
+            .map(|i| {
+                let mut p = Packet::default();
+                p.data[0] = i as u8;
+                p.meta.size = size;
+                p.meta.set_addr(&addr);
+                p
+            }).collect()
+    }
+
+    #[test]
+    pub fn test_send_mmsg_one_batch() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let saddr = sender.local_addr().unwrap();
+        let packets = make_packets(32, PACKET_DATA_SIZE, &reader);
+
+        let sent = send_mmsg(&sender, &packets).unwrap();
+        assert_eq!(sent, packets.len());
+
+        let mut recv_packets = vec![Packet::default(); 64];
+        let recv = recv_mmsg_with_batch(&reader, &mut recv_packets[..], 64, timeout()).unwrap();
+        assert_eq!(recv, sent);
+        for i in 0..recv {
+            assert_eq!(recv_packets[i].meta.size, PACKET_DATA_SIZE);
+            assert_eq!(recv_packets[i].meta.addr(), saddr);
+            assert_eq!(recv_packets[i].data[0], i as u8);
+        }
+    }
+
+    #[test]
+    pub fn test_send_mmsg_matches_send_to() {
+        let reader = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let packets = make_packets(8, 100, &reader);
+
+        for p in &packets {
+            sender
+                .send_to(&p.data[..p.meta.size], &p.meta.addr())
+                .unwrap();
+        }
+        let mut individual = vec![Packet::default(); 16];
+        let recv = recv_mmsg_with_batch(&reader, &mut individual[..], 16, timeout()).unwrap();
+        assert_eq!(recv, packets.len());
+
+        assert_eq!(send_mmsg(&sender, &packets).unwrap(), packets.len());
+        let mut batched = vec![Packet::default(); 16];
+        let recv = recv_mmsg_with_batch(&reader, &mut batched[..], 16, timeout()).unwrap();
+        assert_eq!(recv, packets.len());
+
+        for i in 0..recv {
+            assert_eq!(individual[i].meta.size, batched[i].meta.size);
+            assert_eq!(individual[i].meta.addr(), batched[i].meta.addr());
+            assert_eq!(&individual[i].data[..], &batched[i].data[..]);
+        }
+    }
+
+    #[test]
+    pub fn test_send_mmsg_empty() {
+        let sender = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        assert_eq!(send_mmsg(&sender, &[]).unwrap(), 0);
+    }
+}
@@ -4,4 +4,8 @@ pub trait Service {
     type JoinReturnType;
 
     fn join(self) -> Result<Self::JoinReturnType>;
+
+    /// Ask the service to stop without waiting for it; `join` then waits for it to finish.
+    /// Services that only stop when their inputs close leave this as a no-op.
+    fn exit(&self) {}
 }
@@ -1,27 +1,44 @@
  
+use counter::Counter;
 use influx_db_client as influxdb;
+use libc;
+use log::Level;
 use metrics;
-use packet::{Blob, SharedBlobs, SharedPackets};
+use packet::{Blob, Packets, SharedBlobs, SharedPackets};
+use recvmmsg::MmsgReceiver;
+use rand::{thread_rng, Rng};
 use result::{Error, Result};
+use std::cmp;
+use std::io;
 use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::Arc;
-use std::thread::{Builder, JoinHandle};
+use std::thread::{self, Builder, JoinHandle};
 use std::time::{Duration, Instant};
 use timing::duration_as_ms;
 
+/// First wait before a failed send is tried again; each further retry waits up to twice
+/// as long
+pub const RESPONDER_RETRY_BASE_MS: u64 = 1;
+
+/// Longest wait between two tries of the same send
+pub const RESPONDER_RETRY_MAX_MS: u64 = 64;
+
 pub type PacketReceiver = Receiver<SharedPackets>;
 pub type PacketSender = Sender<SharedPackets>;
 pub type BlobSender = Sender<SharedBlobs>;
 pub type BlobReceiver = Receiver<SharedBlobs>;
 
-fn recv_loop(
-    sock: &UdpSocket,
+fn recv_loop<F>(
+    mut recv: F,
     exit: &Arc<AtomicBool>,
     channel: &PacketSender,
     channel_tag: &'static str,
-) -> Result<()> {
+) -> Result<()>
+where
+    F: FnMut(&mut Packets) -> Result<()>,
+{
     loop {
         let msgs = SharedPackets::default();
         loop {
@@ -29,7 +46,7 @@ fn recv_loop(
             if exit.load(Ordering::Relaxed) {
                 return Ok(());
             }
-            if msgs.write().unwrap().recv_from(sock).is_ok() {
+            if recv(&mut *msgs.write().unwrap()).is_ok() {
                 let len = msgs.read().unwrap().packets.len();
                 metrics::submit(
                     influxdb::Point::new(channel_tag)
@@ -56,7 +73,35 @@ pub fn receiver(
     Builder::new()
         .name("hypercube-receiver".to_string())
         .spawn(move || {
-            let _ = recv_loop(&sock, &exit, &packet_sender, sender_tag);
+            let _ = recv_loop(
+                |msgs| msgs.recv_from(&sock),
+                &exit,
+                &packet_sender,
+                sender_tag,
+            );
+            ()
+        }).unwrap()
+}
+
+/// Like `receiver`, but reads from any `MmsgReceiver` rather than a socket
+pub fn mmsg_receiver<R>(
+    recvr: Arc<R>,
+    exit: Arc<AtomicBool>,
+    packet_sender: PacketSender,
+    sender_tag: &'static str,
+) -> JoinHandle<()>
+where
+    R: MmsgReceiver + Send + Sync + 'static,
+{
+    Builder::new()
+        .name("hypercube-receiver".to_string())
+        .spawn(move || {
+            let _ = recv_loop(
+                |msgs| msgs.recv_from_receiver(&*recvr),
+                &exit,
+                &packet_sender,
+                sender_tag,
+            );
             ()
         }).unwrap()
 }
@@ -102,6 +147,73 @@ pub fn responder(name: &'static str, sock: Arc<UdpSocket>, r: BlobReceiver) -> J
         }).unwrap()
 }
 
+/// Whether a send that failed with `e` may go through if tried again shortly.
+fn is_transient_send_error(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => true,
+        _ => e.raw_os_error() == Some(libc::ENOBUFS),
+    }
+}
+
+/// Call `send` until it succeeds, retrying a transient failure up to `max_retries` times.
+/// Each retry waits a random time up to an exponentially growing backoff, so senders that
+/// failed together don't all retry together.
+fn send_with_retry<F>(mut send: F, max_retries: usize) -> io::Result<()>
+where
+    F: FnMut() -> io::Result<()>,
+{
+    let mut backoff_ms = RESPONDER_RETRY_BASE_MS;
+    let mut retries = 0;
+    loop {
+        match send() {
+            Err(ref e) if retries < max_retries && is_transient_send_error(e) => {
+                retries += 1;
+                inc_new_counter_info!("streamer-responder-retry", 1);
+                thread::sleep(Duration::from_millis(thread_rng().gen_range(0, backoff_ms + 1)));
+                backoff_ms = cmp::min(backoff_ms * 2, RESPONDER_RETRY_MAX_MS);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn recv_send_with_retry(sock: &UdpSocket, r: &BlobReceiver, max_retries: usize) -> Result<()> {
+    let timer = Duration::new(1, 0);
+    let msgs = r.recv_timeout(timer)?;
+    for blob in msgs {
+        let blob = blob.read().unwrap();
+        let addr = blob.meta.addr();
+        let send = || sock.send_to(&blob.data[..blob.meta.size], &addr).map(|_| ());
+        // One blob failing for good doesn't hold back the ones after it
+        if let Err(e) = send_with_retry(send, max_retries) {
+            inc_new_counter_info!("streamer-responder-dropped", 1);
+            warn!("dropped {} byte blob to {:?}: {:?}", blob.meta.size, addr, e);
+        }
+    }
+    Ok(())
+}
+
+/// Like `responder`, but a blob whose send fails transiently is retried up to
+/// `max_retries` times before it is dropped.
+pub fn responder_with_retry(
+    name: &'static str,
+    sock: Arc<UdpSocket>,
+    r: BlobReceiver,
+    max_retries: usize,
+) -> JoinHandle<()> {
+    Builder::new()
+        .name(format!("hypercube-responder-{}", name))
+        .spawn(move || loop {
+            if let Err(e) = recv_send_with_retry(&sock, &r, max_retries) {
+                match e {
+                    Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                    Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                    _ => warn!("{} responder error: {:?}", name, e),
+                }
+            }
+        }).unwrap()
+}
+
 //We would need to put a block of authentication in place before we can build the window, which is a TODO.
 fn recv_blobs(sock: &UdpSocket, s: &BlobSender) -> Result<()> {
     trace!("recv_blobs: receiving on {}", sock.local_addr().unwrap());
@@ -130,15 +242,38 @@ pub fn blob_receiver(sock: Arc<UdpSocket>, exit: Arc<AtomicBool>, s: BlobSender)
 #[cfg(test)]
 mod test {
     use packet::{Blob, Packet, Packets, SharedBlob, PACKET_DATA_SIZE};
+    use recvmmsg::MmsgReceiver;
+    use std::collections::VecDeque;
     use std::io;
     use std::io::Write;
     use std::net::UdpSocket;
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::mpsc::channel;
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
+    use std::thread::sleep;
     use std::time::Duration;
     use streamer::PacketReceiver;
-    use streamer::{receiver, responder};
+    use streamer::{mmsg_receiver, receiver, responder, send_with_retry};
+
+    /// Hands out one scripted batch per call, then reports a timeout once they run out
+    struct ScriptedReceiver {
+        batches: Mutex<VecDeque<Vec<Packet>>>,
+    }
+
+    impl MmsgReceiver for ScriptedReceiver {
+        fn recv_mmsg(&self, packets: &mut [Packet]) -> io::Result<usize> {
+            match self.batches.lock().unwrap().pop_front() {
+                Some(batch) => {
+                    packets[..batch.len()].clone_from_slice(&batch);
+                    Ok(batch.len())
+                }
+                None => {
+                    sleep(Duration::from_millis(1));
+                    Ok(0)
+                }
+            }
+        }
+    }
 
     fn get_msgs(r: PacketReceiver, num: &mut usize) {
         for _t in 0..5 {
@@ -158,6 +293,49 @@ mod test {
         write!(io::sink(), "{:?}", Packets::default()).unwrap();
         write!(io::sink(), "{:?}", Blob::default()).unwrap();
     }
+    #[test]
+    fn test_send_with_retry() {
+        // Two blips, then the send goes through within a budget of three retries
+        let mut tries = 0;
+        let result = send_with_retry(
+            || {
+                tries += 1;
+                if tries <= 2 {
+                    Err(io::Error::new(io::ErrorKind::WouldBlock, "blip"))
+                } else {
+                    Ok(())
+                }
+            },
+            3,
+        );
+        assert!(result.is_ok());
+        assert_eq!(tries, 3);
+
+        // A budget of one retry gives up
+        let mut tries = 0;
+        let result = send_with_retry(
+            || {
+                tries += 1;
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "blip"))
+            },
+            1,
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(tries, 2);
+
+        // An error retrying can't fix is returned straight away
+        let mut tries = 0;
+        let result = send_with_retry(
+            || {
+                tries += 1;
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "no"))
+            },
+            3,
+        );
+        assert!(result.is_err());
+        assert_eq!(tries, 1);
+    }
+
     #[test]
     pub fn streamer_send_test() {
         let read = UdpSocket::bind("127.0.0.1:0").expect("bind");
@@ -193,4 +371,39 @@ mod test {
         t_receiver.join().expect("join");
         t_responder.join().expect("join");
     }
+
+    #[test]
+    fn streamer_mmsg_receiver_test() {
+        let batch = |tag: u8, len: usize| {
+            (0..len)
+                .map(|i| {
+                    let mut p = Packet::default();
+                    p.data[0] = tag;
+                    p.data[1] = i as u8;
+                    p.meta.size = 2;
+                    p
+                }).collect::<Vec<_>>()
+        };
+        let recvr = Arc::new(ScriptedReceiver {
+            batches: Mutex::new(vec![batch(1, 3), batch(2, 7)].into_iter().collect()),
+        });
+        let exit = Arc::new(AtomicBool::new(false));
+        let (s_reader, r_reader) = channel();
+        let t_receiver = mmsg_receiver(recvr, exit.clone(), s_reader, "streamer-test");
+
+        let timer = Duration::new(1, 0);
+        for (tag, len) in vec![(1, 3), (2, 7)] {
+            let msgs = r_reader.recv_timeout(timer).expect("batch");
+            let msgs = msgs.read().unwrap();
+            assert_eq!(msgs.packets.len(), len);
+            for (i, p) in msgs.packets.iter().enumerate() {
+                assert_eq!(p.data[0], tag);
+                assert_eq!(p.data[1], i as u8);
+                assert_eq!(p.meta.size, 2);
+            }
+        }
+        exit.store(true, Ordering::Relaxed);
+        t_receiver.join().expect("join");
+        assert!(r_reader.try_recv().is_err());
+    }
 }
@@ -14,9 +14,12 @@ pub fn duration_as_s(d: &Duration) -> f32 {
     d.as_secs() as f32 + (d.subsec_nanos() as f32 / 1_000_000_000.0)
 }
 
-pub fn timestamp() -> u64 {
-    let now = SystemTime::now()
+pub fn duration_since_epoch() -> Duration {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .expect("create timestamp in timing");
-    duration_as_ms(&now)
+        .expect("create timestamp in timing")
+}
+
+pub fn timestamp() -> u64 {
+    duration_as_ms(&duration_since_epoch())
 }
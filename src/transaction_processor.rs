@@ -1,6 +1,6 @@
 use bincode::deserialize;
 use bincode::serialize;
-use fin_plan_program::FinPlanState;
+use fin_plan_program::{ContractFee, FinPlanState};
 use fin_plan_transaction::FinPlanTransaction;
 use counter::Counter;
 use dynamic_program::DynamicProgram;
@@ -27,6 +27,7 @@ use tictactoe_dashboard_program::TicTacToeDashboardProgram;
 use tictactoe_program::TicTacToeProgram;
 use timing::{duration_as_us, timestamp};
 use transaction::Transaction;
+use vote_program::VoteProgram;
 use window::WINDOW_SIZE;
 
 pub const MAX_ENTRY_IDS: usize = 1024 * 16;
@@ -86,6 +87,15 @@ pub struct TransactionProcessor {
     finality_time: AtomicUsize,
 
     loaded_contracts: RwLock<HashMap<Pubkey, DynamicProgram>>,
+
+    /// Number of entry ids registered so far
+    entry_height: AtomicUsize,
+
+    /// Keep the budget program to escrow, rejecting contracts that would pay out at once
+    pub strict_escrow: bool,
+
+    /// What creating a budget contract costs, paid to the node's fee account
+    pub contract_fee: Option<ContractFee>,
 }
 
 impl Default for TransactionProcessor {
@@ -98,6 +108,9 @@ impl Default for TransactionProcessor {
             is_leader: true,
             finality_time: AtomicUsize::new(std::usize::MAX),
             loaded_contracts: RwLock::new(HashMap::new()),
+            entry_height: AtomicUsize::new(0),
+            strict_escrow: false,
+            contract_fee: None,
         }
     }
 }
@@ -123,7 +136,7 @@ impl TransactionProcessor {
     pub fn new(mint: &Mint) -> Self {
         let deposit = Payment {
             to: mint.pubkey(),
-            tokens: mint.tokens,
+            tokens: mint.tokens as u64,
         };
         let transaction_processor = Self::new_from_deposit(&deposit);
         transaction_processor.register_entry_id(&mint.last_id());
@@ -132,7 +145,7 @@ impl TransactionProcessor {
 
     fn apply_payment(payment: &Payment, account: &mut Account) {
         trace!("apply payments {}", payment.tokens);
-        account.tokens += payment.tokens;
+        account.tokens += payment.tokens as i64;
     }
 
 
@@ -225,6 +238,11 @@ impl TransactionProcessor {
         }
         last_ids_sigs.insert(*last_id, (HashMap::new(), timestamp()));
         last_ids.push_back(*last_id);
+        self.entry_height.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn entry_height(&self) -> u64 {
+        self.entry_height.load(Ordering::Relaxed) as u64
     }
 
     pub fn process_transaction(&self, tx: &Transaction) -> Result<()> {
@@ -250,11 +268,8 @@ impl TransactionProcessor {
             } else {
                 error_counters.account_not_found_leader += 1;
             }
-            if FinPlanState::check_id(&tx.program_id) {
-                use fin_plan_instruction::Instruction;
-                if let Some(Instruction::NewVote(_vote)) = tx.instruction() {
-                    error_counters.account_not_found_vote += 1;
-                }
+            if VoteProgram::check_id(&tx.program_id) {
+                error_counters.account_not_found_vote += 1;
             }
             Err(TransactionProcessorError::AccountNotFound)
         } else if accounts.get(&tx.keys[0]).unwrap().tokens < tx.fee {
@@ -335,13 +350,24 @@ impl TransactionProcessor {
             SystemProgram::process_transaction(&tx, accounts, &self.loaded_contracts)
         } else if FinPlanState::check_id(&tx.program_id) {
 
-            if FinPlanState::process_transaction(&tx, accounts).is_err() {
+            let entry_height = self.entry_height();
+            let fee = self.contract_fee.as_ref();
+            let result = if self.strict_escrow {
+                FinPlanState::process_transaction_strict(&tx, accounts, entry_height, fee)
+            } else {
+                FinPlanState::process_transaction_with_fee(&tx, accounts, entry_height, fee)
+            };
+            if result.is_err() {
                 return Err(TransactionProcessorError::ProgramRuntimeError);
             }
         } else if StorageProgram::check_id(&tx.program_id) {
             if StorageProgram::process_transaction(&tx, accounts).is_err() {
                 return Err(TransactionProcessorError::ProgramRuntimeError);
             }
+        } else if VoteProgram::check_id(&tx.program_id) {
+            if VoteProgram::process_transaction(&tx, accounts).is_err() {
+                return Err(TransactionProcessorError::ProgramRuntimeError);
+            }
         } else if TicTacToeProgram::check_id(&tx.program_id) {
             if TicTacToeProgram::process_transaction(&tx, accounts).is_err() {
                 return Err(TransactionProcessorError::ProgramRuntimeError);
@@ -591,7 +617,11 @@ impl TransactionProcessor {
         if SystemProgram::check_id(&account.program_id) {
             SystemProgram::get_balance(account)
         } else if FinPlanState::check_id(&account.program_id) {
-            FinPlanState::get_balance(account)
+            // A corrupt contract has nothing it can be trusted to spend
+            FinPlanState::get_balance(account).unwrap_or_else(|e| {
+                warn!("unreadable budget contract: {}", e);
+                0
+            })
         } else {
             account.tokens
         }
@@ -651,9 +681,12 @@ impl TransactionProcessor {
 mod tests {
     use super::*;
     use bincode::serialize;
+    use chrono::{Duration, Utc};
     use entry::next_entry;
     use entry::Entry;
     use entry_writer::{self, EntryWriter};
+    use fin_plan::FinPlan;
+    use fin_plan_instruction::{Contract, Instruction};
     use hash::hash;
     use ledger;
     use logger;
@@ -1060,4 +1093,115 @@ mod tests {
         );
         assert!(transaction_processor.process_transaction(&tx).is_err());
     }
+
+    #[test]
+    fn test_contract_fee() {
+        let mint = Mint::new(10_000);
+        let mut transaction_processor = TransactionProcessor::new(&mint);
+        let collector = Keypair::new().pubkey();
+        transaction_processor.contract_fee = Some(ContractFee {
+            tokens: 2,
+            collector,
+        });
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let to = Keypair::new().pubkey();
+        for (key, tokens, space) in vec![(from.pubkey(), 10, 0), (contract.pubkey(), 1, 512)] {
+            let tx = Transaction::system_create(
+                &mint.keypair(),
+                key,
+                mint.last_id(),
+                tokens,
+                space,
+                FinPlanState::id(),
+                0,
+            );
+            transaction_processor.process_transaction(&tx).unwrap();
+        }
+        let new_contract = |fee_key: Pubkey| {
+            let instruction = Instruction::NewContract(Contract {
+                fin_plan: FinPlan::new_future_payment(Utc::now(), from.pubkey(), 5, to),
+                tokens: 5,
+                memo: None,
+                expiry: None,
+            });
+            Transaction::new(
+                &from,
+                &[contract.pubkey(), to, fee_key],
+                FinPlanState::id(),
+                serialize(&instruction).unwrap(),
+                mint.last_id(),
+                0,
+            )
+        };
+
+        // Naming a key of the payer's own as the collector doesn't dodge the fee
+        let own_key = Keypair::new().pubkey();
+        assert_eq!(
+            transaction_processor.process_transaction(&new_contract(own_key)),
+            Err(TransactionProcessorError::ProgramRuntimeError)
+        );
+        assert_eq!(transaction_processor.get_balance(&from.pubkey()), 10);
+        assert_eq!(transaction_processor.get_balance(&own_key), 0);
+
+        // The node's fee account is paid on top of the contract's tokens
+        transaction_processor
+            .process_transaction(&new_contract(collector))
+            .unwrap();
+        assert_eq!(transaction_processor.get_balance(&from.pubkey()), 3);
+        assert_eq!(transaction_processor.get_balance(&collector), 2);
+        assert_eq!(transaction_processor.get_balance(&to), 0);
+    }
+
+    #[test]
+    fn test_expired_contract_refund() {
+        let mint = Mint::new(10_000);
+        let transaction_processor = TransactionProcessor::new(&mint);
+        let from = Keypair::new();
+        let contract = Keypair::new();
+        let oracle = Keypair::new();
+        let to = Keypair::new().pubkey();
+        for (key, tokens, space) in vec![(from.pubkey(), 10, 0), (contract.pubkey(), 1, 512)] {
+            let tx = Transaction::system_create(
+                &mint.keypair(),
+                key,
+                mint.last_id(),
+                tokens,
+                space,
+                FinPlanState::id(),
+                0,
+            );
+            transaction_processor.process_transaction(&tx).unwrap();
+        }
+        transaction_processor
+            .transfer(1, &mint.keypair(), oracle.pubkey(), mint.last_id())
+            .unwrap();
+
+        let expiry = Utc::now() + Duration::days(1);
+        let tx = Transaction::fin_plan_new_on_date_with_expiry(
+            &from,
+            to,
+            contract.pubkey(),
+            expiry + Duration::days(1),
+            oracle.pubkey(),
+            None,
+            Some(expiry),
+            5,
+            mint.last_id(),
+        );
+        transaction_processor.process_transaction(&tx).unwrap();
+        assert_eq!(transaction_processor.get_balance(&from.pubkey()), 5);
+
+        // The refund is a timestamp witness in the ledger, so every node replays it alike
+        let tx = Transaction::fin_plan_new_timestamp(
+            &oracle,
+            contract.pubkey(),
+            from.pubkey(),
+            expiry,
+            mint.last_id(),
+        );
+        transaction_processor.process_transaction(&tx).unwrap();
+        assert_eq!(transaction_processor.get_balance(&from.pubkey()), 10);
+        assert_eq!(transaction_processor.get_balance(&to), 0);
+    }
 }
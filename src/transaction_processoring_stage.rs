@@ -109,7 +109,7 @@ impl TransactionProcessoringStage {
         p.packets
             .par_iter()
             .map(|x| {
-                deserialize(&x.data[0..x.meta.size])
+                deserialize(x.payload())
                     .map(|req| (req, x.meta.addr()))
                     .ok()
             }).collect()
@@ -3,15 +3,16 @@ use transaction_processoring_stage::{TransactionProcessoringStage, Config};
 use blockthread::BlockThread;
 use entry::Entry;
 use fetch_stage::FetchStage;
+use result::Result;
 use service::Service;
 use signature::Keypair;
 use sigverify_stage::SigVerifyStage;
-use std::net::UdpSocket;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, RwLock};
 use std::thread;
-use write_stage::{WriteStage, WriteStageReturnType};
+use write_stage::{WriteStage, WriteStageConfig, WriteStageReturnType};
 
 pub enum TxCreatorReturnType {
     LeaderRotation,
@@ -26,16 +27,18 @@ pub struct TxCreator {
 }
 
 impl TxCreator {
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new(
         keypair: Arc<Keypair>,
         transaction_processor: &Arc<TransactionProcessor>,
         blockthread: &Arc<RwLock<BlockThread>>,
         tick_duration: Config,
+        write_stage_config: WriteStageConfig,
         transactions_sockets: Vec<UdpSocket>,
         ledger_path: &str,
         sigverify_disabled: bool,
         entry_height: u64,
-    ) -> (Self, Receiver<Vec<Entry>>, Arc<AtomicBool>) {
+    ) -> Result<(Self, Receiver<Vec<Entry>>, Arc<AtomicBool>)> {
         let exit = Arc::new(AtomicBool::new(false));
 
         let (fetch_stage, packet_receiver) = FetchStage::new(transactions_sockets, exit.clone());
@@ -46,14 +49,27 @@ impl TxCreator {
         let (transaction_processoring_stage, entry_receiver) =
             TransactionProcessoringStage::new(&transaction_processor, verified_receiver, tick_duration);
 
-        let (write_stage, entry_forwarder) = WriteStage::new(
+        let write_stage = WriteStage::new(
             keypair,
             transaction_processor.clone(),
             blockthread.clone(),
             ledger_path,
             entry_receiver,
             entry_height,
+            write_stage_config,
+            socketaddr_any!(),
+            // Not `exit`: the writer stops once the stages feeding it have and its receiver
+            // disconnects, so every entry they processed still reaches the ledger
+            Arc::new(AtomicBool::new(false)),
         );
+        let (write_stage, entry_forwarder) = match write_stage {
+            Ok(write_stage) => write_stage,
+            Err(e) => {
+                // The stages already running wind down once fetching stops
+                fetch_stage.close();
+                return Err(e);
+            }
+        };
 
         let tx_creator = TxCreator {
             fetch_stage,
@@ -62,7 +78,7 @@ impl TxCreator {
             write_stage,
             exit: exit.clone(),
         };
-        (tx_creator, entry_forwarder, exit)
+        Ok((tx_creator, entry_forwarder, exit))
     }
 
     pub fn exit(&self) -> () {
@@ -82,7 +98,7 @@ impl Service for TxCreator {
         self.fetch_stage.join()?;
         self.sigverify_stage.join()?;
         self.transaction_processoring_stage.join()?;
-        match self.write_stage.join()? {
+        match self.write_stage.join()?.0 {
             WriteStageReturnType::LeaderRotation => Ok(Some(TxCreatorReturnType::LeaderRotation)),
             _ => Ok(None),
         }
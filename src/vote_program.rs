@@ -0,0 +1,104 @@
+//! vote program
+use bincode::deserialize;
+use xpz_program_interface::account::Account;
+use xpz_program_interface::pubkey::Pubkey;
+use transaction::Transaction;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Vote {
+    pub version: u64,
+    pub contact_info_version: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum VoteProgram {
+    /// Register a vote
+    /// * Transaction::keys[0] - the voter
+    NewVote(Vote),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VoteError {
+    UserdataDeserializeFailure,
+}
+
+pub const VOTE_PROGRAM_ID: [u8; 32] = [
+    2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+impl VoteProgram {
+    pub fn check_id(program_id: &Pubkey) -> bool {
+        program_id.as_ref() == VOTE_PROGRAM_ID
+    }
+
+    pub fn id() -> Pubkey {
+        Pubkey::new(&VOTE_PROGRAM_ID)
+    }
+
+    pub fn get_balance(account: &Account) -> i64 {
+        account.tokens
+    }
+
+    /// Votes don't move tokens; they are collected from the entries by `Block::votes`
+    pub fn process_transaction(
+        tx: &Transaction,
+        _accounts: &mut [Account],
+    ) -> Result<(), VoteError> {
+        if let Ok(instruction) = deserialize(&tx.userdata) {
+            match instruction {
+                VoteProgram::NewVote(_vote) => {
+                    trace!("GOT VOTE! last_id={}", tx.last_id);
+                    Ok(())
+                }
+            }
+        } else {
+            info!("Invalid vote transaction userdata: {:?}", tx.userdata);
+            Err(VoteError::UserdataDeserializeFailure)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hash::Hash;
+    use signature::{Keypair, KeypairUtil};
+    use transaction::Transaction;
+    use vote_program::{Vote, VoteError, VoteProgram};
+    use vote_transaction::VoteTransaction;
+    use xpz_program_interface::account::Account;
+
+    #[test]
+    fn test_process_vote() {
+        let keypair = Keypair::new();
+        let vote = Vote {
+            version: 0,
+            contact_info_version: 1,
+        };
+        let tx = Transaction::vote_new(&keypair, vote.clone(), Hash::default(), 0);
+        assert!(VoteProgram::check_id(&tx.program_id));
+
+        let mut accounts = vec![Account::new(1, 0, VoteProgram::id())];
+        assert_eq!(VoteProgram::process_transaction(&tx, &mut accounts), Ok(()));
+        assert_eq!(accounts[0].tokens, 1);
+        assert_eq!(tx.vote(), Some((keypair.pubkey(), vote, Hash::default())));
+    }
+
+    #[test]
+    fn test_invalid_vote() {
+        let keypair = Keypair::new();
+        let tx = Transaction::new(
+            &keypair,
+            &[],
+            VoteProgram::id(),
+            vec![1, 2, 3], // <== garbage instruction
+            Hash::default(),
+            0,
+        );
+        let mut accounts = vec![Account::new(1, 0, VoteProgram::id())];
+        assert_eq!(
+            VoteProgram::process_transaction(&tx, &mut accounts),
+            Err(VoteError::UserdataDeserializeFailure)
+        );
+        assert_eq!(tx.vote(), None);
+    }
+}
@@ -1,6 +1,5 @@
 use transaction_processor::TransactionProcessor;
 use bincode::serialize;
-use fin_plan_transaction::FinPlanTransaction;
 use counter::Counter;
 use blockthread::BlockThread;
 use hash::Hash;
@@ -17,6 +16,7 @@ use std::sync::{Arc, RwLock};
 use streamer::BlobSender;
 use timing;
 use transaction::Transaction;
+use vote_transaction::VoteTransaction;
 
 pub const VOTE_TIMEOUT_MS: u64 = 1000;
 
@@ -36,7 +36,7 @@ pub fn create_new_signed_vote_blob(
         debug!("voting on {:?}", &last_id.as_ref()[..8]);
         wblockthread.new_vote(*last_id)
     }?;
-    let tx = Transaction::fin_plan_new_vote(&keypair, vote, *last_id, 0);
+    let tx = Transaction::vote_new(&keypair, vote, *last_id, 0);
     {
         let mut blob = shared_blob.write().unwrap();
         let bytes = serialize(&tx)?;
@@ -158,7 +158,7 @@ pub mod tests {
     use super::*;
     use transaction_processor::TransactionProcessor;
     use bincode::deserialize;
-    use fin_plan_instruction::Vote;
+    use vote_program::Vote;
     use blockthread::{BlockThread, NodeInfo};
     use entry::next_entry;
     use hash::{hash, Hash};
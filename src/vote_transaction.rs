@@ -0,0 +1,33 @@
+use bincode::{deserialize, serialize};
+use hash::Hash;
+use signature::Keypair;
+use transaction::Transaction;
+use vote_program::{Vote, VoteProgram};
+use xpz_program_interface::pubkey::Pubkey;
+
+pub trait VoteTransaction {
+    fn vote_new(from_keypair: &Keypair, vote: Vote, last_id: Hash, fee: i64) -> Self;
+
+    fn vote(&self) -> Option<(Pubkey, Vote, Hash)>;
+}
+
+impl VoteTransaction for Transaction {
+    /// Create and sign a new vote Transaction.
+    fn vote_new(from_keypair: &Keypair, vote: Vote, last_id: Hash, fee: i64) -> Self {
+        let instruction = VoteProgram::NewVote(vote);
+        let userdata = serialize(&instruction).expect("serialize instruction");
+        Self::new(from_keypair, &[], VoteProgram::id(), userdata, last_id, fee)
+    }
+
+    /// Return the voter, vote and voted-on last_id if this is a vote transaction.
+    fn vote(&self) -> Option<(Pubkey, Vote, Hash)> {
+        if !VoteProgram::check_id(&self.program_id) {
+            return None;
+        }
+        if let Ok(VoteProgram::NewVote(vote)) = deserialize(&self.userdata) {
+            Some((*self.from(), vote, self.last_id))
+        } else {
+            None
+        }
+    }
+}
@@ -1,72 +1,496 @@
 use transaction_processor::TransactionProcessor;
 use counter::Counter;
-use blockthread::BlockThread;
+use blockthread::{BlockThread, LeaderSchedule};
 use entry::Entry;
-use ledger::{Block, LedgerWriter};
+use hash::Hash;
+use ledger::{Block, EntryWriter, LedgerWindow, LedgerWriter, SyncPolicy};
 use log::Level;
 use result::{Error, Result};
 use service::Service;
 use signature::Keypair;
+use xpz_program_interface::pubkey::Pubkey;
 use std::cmp;
-use std::net::UdpSocket;
-use std::sync::atomic::AtomicUsize;
-use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
 use std::sync::{Arc, RwLock};
 use std::thread::{self, Builder, JoinHandle};
 use std::time::{Duration, Instant};
-use streamer::responder;
+use streamer::responder_with_retry;
 use timing::{duration_as_ms, duration_as_s};
+use vote_program::Vote;
 use vote_stage::send_leader_vote;
 
+/// How long the writer waits for new entries before giving the leader vote a chance to run
+pub const WRITE_STAGE_RECV_TIMEOUT_MS: u64 = 1000;
+
+/// Fewest entries the writer hands to the ledger at once when more are queued
+pub const WRITE_STAGE_FLUSH_INTERVAL_ENTRIES: usize = 1;
+
+/// Most entries the writer accumulates from its channel before it stops to flush them
+pub const WRITE_STAGE_MAX_ENTRIES_PER_ITERATION: usize = 4096;
+
+/// Longest the writer keeps accumulating entries from a busy channel before it flushes them
+pub const WRITE_STAGE_MAX_COALESCE_MS: u64 = 100;
+
+/// Most entry batches the writer queues for broadcast before it stops and waits
+pub const WRITE_STAGE_ENTRY_CHANNEL_BOUND: usize = 1024;
+
+/// Most entry batches the writer queues for the ledger thread before it waits on the disk
+pub const WRITE_STAGE_LEDGER_QUEUE_BOUND: usize = 1024;
+
+/// Most vote batches the writer queues for the blockthread before it waits on the lock
+pub const WRITE_STAGE_VOTE_QUEUE_BOUND: usize = 1024;
+
+/// Fewest entries the writer adds to the ledger between two leader votes
+pub const WRITE_STAGE_VOTE_INTERVAL_ENTRIES: u64 = 1;
+
+/// How many times a vote whose send fails transiently is retried before it is dropped
+pub const WRITE_STAGE_VOTE_SEND_RETRIES: usize = 3;
+
+/// Whether the writer checks that entries chain onto the ledger before writing them. Off
+/// by default, as it hashes every entry a second time on the leader's critical path
+pub const WRITE_STAGE_VERIFY_ENTRIES: bool = false;
+
+/// How often the writer syncs the ledger to disk
+pub const WRITE_STAGE_SYNC_POLICY: SyncPolicy = SyncPolicy::Never;
+
+/// How the writer thread is named and scheduled
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ThreadConfig {
+    pub name: String,
+    /// The CPU to pin the thread to, or `None` to leave it to the scheduler
+    pub affinity: Option<usize>,
+}
+
+impl Default for ThreadConfig {
+    fn default() -> Self {
+        ThreadConfig {
+            name: "hypercube-writer".to_string(),
+            affinity: None,
+        }
+    }
+}
+
+/// How the writer batches, votes and checks what it writes. Each field defaults to the
+/// `WRITE_STAGE_*` constant of the same name.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WriteStageConfig {
+    pub recv_timeout: Duration,
+    pub flush_interval_entries: usize,
+    pub max_entries_per_iteration: usize,
+    pub max_coalesce: Duration,
+    pub entry_channel_bound: usize,
+    pub vote_interval_entries: u64,
+    pub sync_policy: SyncPolicy,
+    pub vote_send_retries: usize,
+    pub verify_entries: bool,
+    pub thread_config: ThreadConfig,
+}
+
+impl Default for WriteStageConfig {
+    fn default() -> Self {
+        WriteStageConfig {
+            recv_timeout: Duration::from_millis(WRITE_STAGE_RECV_TIMEOUT_MS),
+            flush_interval_entries: WRITE_STAGE_FLUSH_INTERVAL_ENTRIES,
+            max_entries_per_iteration: WRITE_STAGE_MAX_ENTRIES_PER_ITERATION,
+            max_coalesce: Duration::from_millis(WRITE_STAGE_MAX_COALESCE_MS),
+            entry_channel_bound: WRITE_STAGE_ENTRY_CHANNEL_BOUND,
+            vote_interval_entries: WRITE_STAGE_VOTE_INTERVAL_ENTRIES,
+            sync_policy: WRITE_STAGE_SYNC_POLICY,
+            vote_send_retries: WRITE_STAGE_VOTE_SEND_RETRIES,
+            verify_entries: WRITE_STAGE_VERIFY_ENTRIES,
+            thread_config: ThreadConfig::default(),
+        }
+    }
+}
+
+/// Pin the calling thread to `cpu`.
+#[cfg(target_os = "linux")]
+fn set_thread_affinity(cpu: usize) -> io::Result<()> {
+    use libc::{cpu_set_t, sched_setaffinity, CPU_SET, CPU_ZERO};
+
+    if cpu >= 8 * mem::size_of::<cpu_set_t>() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no such cpu"));
+    }
+    unsafe {
+        let mut cpus: cpu_set_t = mem::zeroed();
+        CPU_ZERO(&mut cpus);
+        CPU_SET(cpu, &mut cpus);
+        // A pid of 0 is the calling thread
+        if sched_setaffinity(0, mem::size_of::<cpu_set_t>(), &cpus) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_thread_affinity(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "thread affinity is only supported on linux",
+    ))
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum WriteStageReturnType {
     LeaderRotation,
     ChannelDisconnected,
+    Exited,
+    /// Another thread panicked while holding the blockthread lock
+    Poisoned,
+    /// Entries arrived that don't chain onto the ledger, so nothing after them can be
+    /// written either
+    BrokenEntryChain,
+    /// The ledger couldn't be written, so nothing more can be written or broadcast
+    LedgerWriteFailed,
+}
+
+/// Throughput numbers for a single pass of `write_and_send_entries`
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct WriteStats {
+    pub num_txs: usize,
+    pub num_new_entries: usize,
+    pub entries_send_total: u64,
+    pub blockthread_votes_total: u64,
+    pub num_send_blocked: usize,
+    /// Most transactions in any one entry written
+    pub max_entry_txs: usize,
+    /// Fewest transactions in any one entry written
+    pub min_entry_txs: usize,
+}
+
+/// The rotation decision for the epoch the writer is in, so the blockthread lock is
+/// only taken again once the writer crosses into the next epoch
+struct LeaderLookahead {
+    leader_rotation_interval: u64,
+    /// Heights `[start, end)` the cached decision holds for
+    epoch: Option<(u64, u64)>,
+    is_leader_rotation: bool,
+}
+
+impl LeaderLookahead {
+    fn new(leader_rotation_interval: u64) -> Self {
+        LeaderLookahead {
+            leader_rotation_interval,
+            epoch: None,
+            is_leader_rotation: false,
+        }
+    }
+
+    /// Whether this node has to hand over leadership at `entry_height`
+    fn is_leader_rotation(
+        &mut self,
+        blockthread: &Arc<RwLock<BlockThread>>,
+        entry_height: u64,
+    ) -> Result<bool> {
+        if self.leader_rotation_interval == 0 || entry_height % self.leader_rotation_interval != 0
+        {
+            return Ok(false);
+        }
+        match self.epoch {
+            Some((start, end)) if start <= entry_height && entry_height < end => (),
+            _ => {
+                self.is_leader_rotation = WriteStage::is_leader_rotation(
+                    blockthread,
+                    self.leader_rotation_interval,
+                    entry_height,
+                )?;
+                self.epoch = Some((entry_height, entry_height + self.leader_rotation_interval));
+            }
+        }
+        Ok(self.is_leader_rotation)
+    }
+}
+
+/// Limits the leader to one vote per `vote_interval_entries` entries written, so passes
+/// that wrote little or nothing don't each try to vote
+struct VoteCadence {
+    vote_interval_entries: u64,
+    /// The entry height of the last vote sent
+    last_vote_entry_height: Option<u64>,
+}
+
+impl VoteCadence {
+    fn new(vote_interval_entries: u64) -> Self {
+        VoteCadence {
+            vote_interval_entries,
+            last_vote_entry_height: None,
+        }
+    }
+
+    /// Whether enough entries were written since the last vote to vote again
+    fn is_due(&self, entry_height: u64) -> bool {
+        match self.last_vote_entry_height {
+            Some(last) => entry_height.saturating_sub(last) >= self.vote_interval_entries,
+            None => true,
+        }
+    }
+
+    fn voted(&mut self, entry_height: u64) {
+        self.last_vote_entry_height = Some(entry_height);
+    }
+}
+
+/// Where `write_and_send_entries` hands the entries it has taken. Each batch is written to
+/// the ledger before it goes downstream, so nothing is broadcast that isn't persisted.
+pub trait EntrySink {
+    /// Take ownership of `entries`. Returns whether it had to wait for room.
+    fn write_and_send(&mut self, entries: Vec<Entry>) -> Result<bool>;
+}
+
+/// Writes entries to the ledger on a thread of its own and broadcasts each batch once it
+/// is written, so the writer can go back to its channel without waiting on the disk. Up
+/// to `bound` batches queue up before `write_and_send` blocks. A failed write stops the
+/// thread; the error is reported by `join`, every later `write_and_send` call fails, and
+/// nothing past it is broadcast.
+pub struct LedgerWriteThread {
+    sender: SyncSender<Vec<Entry>>,
+    /// Entries the thread has written so far
+    written: Arc<AtomicUsize>,
+    failed: Arc<AtomicBool>,
+    thread_hdl: JoinHandle<io::Result<()>>,
+}
+
+impl LedgerWriteThread {
+    /// Write each queued batch with `ledger_writer`, then hand it to `entry_sender`.
+    pub fn new<W: EntryWriter + Send + 'static>(
+        mut ledger_writer: W,
+        entry_sender: SyncSender<Vec<Entry>>,
+        bound: usize,
+    ) -> Self {
+        let (sender, receiver) = sync_channel::<Vec<Entry>>(bound);
+        let written = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicBool::new(false));
+        let thread_written = written.clone();
+        let thread_failed = failed.clone();
+        let thread_hdl = Builder::new()
+            .name("hypercube-ledger-writer".to_string())
+            .spawn(move || {
+                let mut entry_sender = Some(entry_sender);
+                for entries in receiver.iter() {
+                    if let Err(e) = ledger_writer.write_entries(&entries) {
+                        thread_failed.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                    thread_written.fetch_add(entries.len(), Ordering::Relaxed);
+                    // The ledger matters more than the broadcast, so keep writing without it
+                    let disconnected = match entry_sender {
+                        Some(ref entry_sender) => {
+                            WriteStage::send_entries(entry_sender, entries).is_err()
+                        }
+                        None => false,
+                    };
+                    if disconnected {
+                        warn!("ledger writer's entry receiver went away");
+                        entry_sender = None;
+                    }
+                }
+                Ok(())
+            }).unwrap();
+        LedgerWriteThread {
+            sender,
+            written,
+            failed,
+            thread_hdl,
+        }
+    }
+
+    /// Wait until every queued entry is in the ledger. Returns how many entries the thread
+    /// wrote, along with the error that stopped it early, if any.
+    pub fn join(self) -> (u64, io::Result<()>) {
+        let LedgerWriteThread {
+            sender,
+            written,
+            thread_hdl,
+            ..
+        } = self;
+        drop(sender);
+        let result = thread_hdl.join().unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "ledger write thread panicked",
+            ))
+        });
+        (written.load(Ordering::Relaxed) as u64, result)
+    }
+}
+
+impl EntrySink for LedgerWriteThread {
+    /// Queue `entries` for the ledger thread, blocking while the queue is full.
+    fn write_and_send(&mut self, entries: Vec<Entry>) -> Result<bool> {
+        let stopped = || {
+            Error::LedgerWriteError(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "ledger write thread stopped",
+            ))
+        };
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(stopped());
+        }
+        match self.sender.try_send(entries) {
+            Ok(()) => Ok(false),
+            Err(TrySendError::Full(entries)) => {
+                inc_new_counter_info!("write_stage-ledger_queue_full", 1);
+                self.sender.send(entries).map_err(|_| stopped())?;
+                Ok(true)
+            }
+            Err(TrySendError::Disconnected(_)) => Err(stopped()),
+        }
+    }
+}
+
+/// Writes each batch to `ledger_writer` on the calling thread, then sends it on.
+pub struct InlineEntrySink<'a, W: 'a> {
+    pub ledger_writer: &'a mut W,
+    pub entry_sender: &'a SyncSender<Vec<Entry>>,
+}
+
+impl<'a, W: EntryWriter> EntrySink for InlineEntrySink<'a, W> {
+    fn write_and_send(&mut self, entries: Vec<Entry>) -> Result<bool> {
+        self.ledger_writer
+            .write_entries(&entries)
+            .map_err(Error::LedgerWriteError)?;
+        WriteStage::send_entries(self.entry_sender, entries)
+    }
+}
+
+/// Applies the votes found in written entries to the blockthread on a thread of its own,
+/// so a burst of votes doesn't keep the writer, or anyone reading the blockthread, waiting
+/// on the write lock. Batches are applied in the order they were queued, which is the
+/// order their entries were written in.
+pub struct VoteInsertThread {
+    sender: SyncSender<Vec<(Pubkey, Vote, Hash)>>,
+    thread_hdl: JoinHandle<()>,
+}
+
+impl VoteInsertThread {
+    pub fn new(blockthread: Arc<RwLock<BlockThread>>, bound: usize) -> Self {
+        Self::with_inserter(bound, move |votes| {
+            blockthread.write()?.insert_votes(votes);
+            Ok(())
+        })
+    }
+
+    /// Like `new`, but hands each batch to `insert` rather than to a blockthread. The
+    /// thread stops at the first batch `insert` fails on.
+    pub fn with_inserter<F>(bound: usize, mut insert: F) -> Self
+    where
+        F: FnMut(&[(Pubkey, Vote, Hash)]) -> Result<()> + Send + 'static,
+    {
+        let (sender, receiver) = sync_channel::<Vec<(Pubkey, Vote, Hash)>>(bound);
+        let thread_hdl = Builder::new()
+            .name("hypercube-vote-inserter".to_string())
+            .spawn(move || {
+                for votes in receiver.iter() {
+                    if let Err(e) = insert(&votes) {
+                        inc_new_counter_info!("write_stage-insert_votes-error", 1);
+                        error!("can't insert votes: {:?}", e);
+                        return;
+                    }
+                }
+            }).unwrap();
+        VoteInsertThread { sender, thread_hdl }
+    }
+
+    /// Queue `votes` for the blockthread, blocking while the queue is full. Fails once the
+    /// thread has stopped, which only happens when the blockthread lock is poisoned.
+    pub fn insert_votes(&self, votes: Vec<(Pubkey, Vote, Hash)>) -> Result<()> {
+        if votes.is_empty() {
+            return Ok(());
+        }
+        match self.sender.try_send(votes) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(votes)) => {
+                inc_new_counter_info!("write_stage-vote_queue_full", 1);
+                self.sender.send(votes).map_err(|_| Error::PoisonError)
+            }
+            Err(TrySendError::Disconnected(_)) => Err(Error::PoisonError),
+        }
+    }
+
+    /// Wait until every queued vote has been applied.
+    pub fn join(self) -> thread::Result<()> {
+        drop(self.sender);
+        self.thread_hdl.join()
+    }
 }
 
 pub struct WriteStage {
     thread_hdls: Vec<JoinHandle<()>>,
-    write_thread: JoinHandle<WriteStageReturnType>,
+    write_thread: JoinHandle<(WriteStageReturnType, u64)>,
+    vote_sender_addr: SocketAddr,
+    exit: Arc<AtomicBool>,
 }
 
 impl WriteStage {
-    
-    fn find_leader_rotation_index(
+    /// Whether this node has to hand over leadership at `entry_height`, read straight from
+    /// the blockthread
+    fn is_leader_rotation(
         blockthread: &Arc<RwLock<BlockThread>>,
         leader_rotation_interval: u64,
         entry_height: u64,
+    ) -> Result<bool> {
+        if leader_rotation_interval == 0 || entry_height % leader_rotation_interval != 0 {
+            return Ok(false);
+        }
+        let rblockthread = blockthread.read()?;
+        let my_id = rblockthread.my_data().id;
+        match rblockthread.get_scheduled_leader(entry_height) {
+            Some(id) if id == my_id => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    fn find_leader_rotation_index(
+        leader_schedule: &LeaderSchedule,
+        leader_rotation_interval: u64,
+        entry_height: u64,
         mut new_entries: Vec<Entry>,
     ) -> (Vec<Entry>, bool) {
-        let new_entries_length = new_entries.len();
+        // Without an interval there is no schedule to rotate on
+        if leader_rotation_interval == 0 {
+            return (new_entries, false);
+        }
+
+        // The ledger can't grow past the last height, so leadership ends there
+        let new_entries_length =
+            cmp::min(new_entries.len() as u64, u64::max_value() - entry_height) as usize;
+        let is_last_height = new_entries_length < new_entries.len();
 
-        
+        // Read every height the entries could reach, including the one just past them
+        let my_id = leader_schedule.my_id();
+        let scheduled_leaders =
+            leader_schedule.get_scheduled_leaders(entry_height, new_entries_length as u64 + 1);
         let mut i = 0;
         let mut is_leader_rotation = false;
 
         loop {
-            if (entry_height + i as u64) % leader_rotation_interval == 0 {
-                let rblockthread = blockthread.read().unwrap();
-                let my_id = rblockthread.my_data().id;
-                let next_leader = rblockthread.get_scheduled_leader(entry_height + i as u64);
-                if next_leader != Some(my_id) {
-                    is_leader_rotation = true;
-                    break;
-                }
+            let height = entry_height + i as u64;
+            if height % leader_rotation_interval == 0 && scheduled_leaders[i] != Some(my_id) {
+                is_leader_rotation = true;
+                break;
             }
 
             if i == new_entries_length {
+                is_leader_rotation = is_last_height;
                 break;
             }
 
-            
+            // At least 1, and a whole epoch when `height` is on a boundary this node
+            // keeps leading, so every pass moves `i` forward
             let entries_until_leader_rotation =
-                leader_rotation_interval - (entry_height % leader_rotation_interval);
+                leader_rotation_interval - (height % leader_rotation_interval);
+            debug_assert!(entries_until_leader_rotation > 0);
 
-            
+            // Take the minimum before narrowing, so a huge interval can't truncate to 0
             i += cmp::min(
-                entries_until_leader_rotation as usize,
-                new_entries_length - i,
-            );
+                entries_until_leader_rotation,
+                (new_entries_length - i) as u64,
+            ) as usize;
         }
 
         new_entries.truncate(i as usize);
@@ -75,26 +499,79 @@ impl WriteStage {
     }
 
     
-    pub fn write_and_send_entries(
+    /// Group `ventries` into batches of at least `flush_interval_entries` entries. The last
+    /// batch may be smaller, so nothing received is held back.
+    fn coalesce_entries(ventries: Vec<Vec<Entry>>, flush_interval_entries: usize) -> Vec<Vec<Entry>> {
+        let mut batches = vec![];
+        let mut batch = vec![];
+        for entries in ventries {
+            if batch.is_empty() {
+                // Reuse the received buffer rather than copying it into a fresh one
+                batch = entries;
+            } else {
+                batch.extend(entries);
+            }
+            if batch.len() >= flush_interval_entries {
+                batches.push(mem::replace(&mut batch, vec![]));
+            }
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+        batches
+    }
+
+    /// Entries are only counted towards `entry_height` and voted on once `entry_sink` has
+    /// taken them. Every entry received is handed to it before this returns, so a leader
+    /// rotation never leaves entries unflushed. A `LedgerWriteThread` only queues them, so
+    /// the writer doesn't wait on the disk.
+    ///
+    /// If `last_entry_id` is given it must be the id of the ledger's last entry. Entries
+    /// that don't chain onto it are then not written, and `Error::BrokenEntryChain` is
+    /// returned; it is moved past every entry that is written.
+    ///
+    /// The votes in the entries are queued on `vote_inserter` once the entries are handed
+    /// to `entry_sink`, so the blockthread never counts a vote from an entry that
+    /// didn't make it that far.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    pub fn write_and_send_entries<S: EntrySink>(
         blockthread: &Arc<RwLock<BlockThread>>,
-        ledger_writer: &mut LedgerWriter,
-        entry_sender: &Sender<Vec<Entry>>,
+        entry_sink: &mut S,
+        vote_inserter: &VoteInsertThread,
         entry_receiver: &Receiver<Vec<Entry>>,
         entry_height: &mut u64,
         leader_rotation_interval: u64,
-    ) -> Result<()> {
+        config: &WriteStageConfig,
+        mut last_entry_id: Option<&mut Hash>,
+    ) -> Result<WriteStats> {
         let mut ventries = Vec::new();
-        let mut received_entries = entry_receiver.recv_timeout(Duration::new(1, 0))?;
+        let mut received_entries = entry_receiver.recv_timeout(config.recv_timeout)?;
         let now = Instant::now();
         let mut num_new_entries = 0;
         let mut num_txs = 0;
 
+        // Decide every rotation in this call against one schedule, even if it is
+        // updated while entries are still arriving. It covers the heights the first batch
+        // and a full iteration after it can reach, and only a later batch that outruns it
+        // reads more
+        let lookahead =
+            received_entries.len() as u64 + config.max_entries_per_iteration as u64 + 1;
+        let mut leader_schedule = blockthread.read()?.leader_schedule(*entry_height, lookahead);
         loop {
-            
+            let height = *entry_height + num_new_entries as u64;
+            let last_height = height.saturating_add(received_entries.len() as u64);
+            if let Some(next_height) = leader_schedule.next_height() {
+                if last_height >= next_height {
+                    let count = (last_height - next_height).saturating_add(1);
+                    let later = blockthread.read()?.leader_schedule(next_height, count);
+                    leader_schedule.extend(later);
+                }
+            }
+
             let (new_entries, is_leader_rotation) = Self::find_leader_rotation_index(
-                blockthread,
+                &leader_schedule,
                 leader_rotation_interval,
-                *entry_height + num_new_entries as u64,
+                height,
                 received_entries,
             );
 
@@ -105,6 +582,19 @@ impl WriteStage {
                 break;
             }
 
+            // Flush what has piled up rather than let a flood grow it without bound. The
+            // first batch received is always taken whole, so it may exceed the cap alone
+            if num_new_entries >= config.max_entries_per_iteration {
+                inc_new_counter_info!("write_stage-max_entries_per_iteration", 1);
+                break;
+            }
+
+            // Nor let a steady stream hold back what was received first
+            if now.elapsed() >= config.max_coalesce {
+                inc_new_counter_info!("write_stage-max_coalesce", 1);
+                break;
+            }
+
             if let Ok(n) = entry_receiver.try_recv() {
                 received_entries = n;
             } else {
@@ -117,50 +607,121 @@ impl WriteStage {
 
         let mut entries_send_total = 0;
         let mut blockthread_votes_total = 0;
+        let mut num_send_blocked = 0;
+        let mut max_entry_txs = 0;
+        let mut min_entry_txs = None;
 
         let start = Instant::now();
-        for entries in ventries {
+        for entries in Self::coalesce_entries(ventries, config.flush_interval_entries) {
             for e in &entries {
-                num_txs += e.transactions.len();
+                let entry_txs = e.transactions.len();
+                inc_new_counter_info!("write_stage-entry_tx_count", entry_txs);
+                num_txs += entry_txs;
+                max_entry_txs = cmp::max(max_entry_txs, entry_txs);
+                min_entry_txs =
+                    Some(min_entry_txs.map_or(entry_txs, |min| cmp::min(min, entry_txs)));
             }
-            let blockthread_votes_start = Instant::now();
-            let votes = &entries.votes();
-            blockthread.write().unwrap().insert_votes(&votes);
-            blockthread_votes_total += duration_as_ms(&blockthread_votes_start.elapsed());
-
-            ledger_writer.write_entries(entries.clone())?;
-            
-            *entry_height += entries.len() as u64;
-
-            inc_new_counter_info!("write_stage-write_entries", entries.len());
-
-            
+            if entries.is_empty() {
+                continue;
+            }
+            if let Some(ref last_id) = last_entry_id {
+                if !entries.verify(last_id) {
+                    inc_new_counter_info!("write_stage-broken_entry_chain", 1);
+                    return Err(Error::BrokenEntryChain);
+                }
+            }
+            let num_entries = entries.len();
+            let tail_id = entries[num_entries - 1].id;
+            let votes = entries.votes();
+            let num_votes = votes.len();
 
-            trace!("New entries? {}", entries.len());
+            trace!("writing and broadcasting {}", num_entries);
             let entries_send_start = Instant::now();
-            if !entries.is_empty() {
-                inc_new_counter_info!("write_stage-recv_vote", votes.len());
-                inc_new_counter_info!("write_stage-entries_sent", entries.len());
-                trace!("broadcasting {}", entries.len());
-                entry_sender.send(entries)?;
+            if entry_sink.write_and_send(entries)? {
+                num_send_blocked += 1;
             }
-
             entries_send_total += duration_as_ms(&entries_send_start.elapsed());
+            if let Some(last_id) = last_entry_id.as_mut() {
+                **last_id = tail_id;
+            }
+
+            *entry_height += num_entries as u64;
+
+            let blockthread_votes_start = Instant::now();
+            vote_inserter.insert_votes(votes)?;
+            blockthread_votes_total += duration_as_ms(&blockthread_votes_start.elapsed());
+
+            inc_new_counter_info!("write_stage-write_entries", num_entries);
+            inc_new_counter_info!("write_stage-recv_vote", num_votes);
+            inc_new_counter_info!("write_stage-entries_sent", num_entries);
         }
         inc_new_counter_info!(
             "write_stage-time_ms",
             duration_as_ms(&now.elapsed()) as usize
         );
-        info!("done write_stage txs: {} time {} ms txs/s: {} entries_send_total: {} blockthread_votes_total: {}",
+        info!("done write_stage txs: {} time {} ms txs/s: {} entries_send_total: {} blockthread_votes_total: {} entry txs max: {} min: {}",
               num_txs, duration_as_ms(&start.elapsed()),
               num_txs as f32 / duration_as_s(&start.elapsed()),
               entries_send_total,
-              blockthread_votes_total);
+              blockthread_votes_total,
+              max_entry_txs,
+              min_entry_txs.unwrap_or(0));
+
+        Ok(WriteStats {
+            num_txs,
+            num_new_entries,
+            entries_send_total,
+            blockthread_votes_total,
+            num_send_blocked,
+            max_entry_txs,
+            min_entry_txs: min_entry_txs.unwrap_or(0),
+        })
+    }
 
-        Ok(())
+    /// Hand `entries` downstream. The entries are already in the ledger, so rather than
+    /// drop them when the channel is full the writer blocks until there is room, which in
+    /// turn stops it from pulling more work off `entry_receiver`. Returns whether it had to
+    /// wait.
+    fn send_entries(entry_sender: &SyncSender<Vec<Entry>>, entries: Vec<Entry>) -> Result<bool> {
+        match entry_sender.try_send(entries) {
+            Ok(()) => Ok(false),
+            Err(TrySendError::Full(entries)) => {
+                inc_new_counter_info!("write_stage-entry_sender_full", 1);
+                entry_sender.send(entries)?;
+                Ok(true)
+            }
+            Err(TrySendError::Disconnected(_)) => Err(Error::SendError),
+        }
     }
 
-    
+    /// The ledger is the source of truth for how far this stage has written. A caller
+    /// supplied height that disagrees with it is reported and replaced by the ledger's.
+    pub fn reconcile_entry_height(entry_height: u64, ledger_entry_height: u64) -> u64 {
+        if entry_height != ledger_entry_height {
+            inc_new_counter_info!("write_stage-entry_height_mismatch", 1);
+            error!(
+                "write_stage entry_height {} does not match the {} entries in the ledger",
+                entry_height, ledger_entry_height
+            );
+        }
+        ledger_entry_height
+    }
+
+    /// The id of the last of the `entry_height` entries in the ledger, if it can be read.
+    fn ledger_tail_id(ledger_path: &str, entry_height: u64) -> Option<Hash> {
+        if entry_height == 0 {
+            return None;
+        }
+        match LedgerWindow::open(ledger_path).and_then(|mut w| w.get_entry(entry_height - 1)) {
+            Ok(entry) => Some(entry.id),
+            Err(e) => {
+                warn!("can't read the last ledger entry: {:?}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new(
         keypair: Arc<Keypair>,
         transaction_processor: Arc<TransactionProcessor>,
@@ -168,59 +729,139 @@ impl WriteStage {
         ledger_path: &str,
         entry_receiver: Receiver<Vec<Entry>>,
         entry_height: u64,
-    ) -> (Self, Receiver<Vec<Entry>>) {
+        config: WriteStageConfig,
+        vote_bind_addr: SocketAddr,
+        exit: Arc<AtomicBool>,
+    ) -> Result<(Self, Receiver<Vec<Entry>>)> {
+        let (mut ledger_writer, ledger_entry_height) =
+            LedgerWriter::recover(ledger_path).map_err(|e| {
+                error!("write_stage can't open the ledger: {}", e);
+                Error::IO(e)
+            })?;
+        ledger_writer.set_sync_policy(config.sync_policy);
+        let entry_height = Self::reconcile_entry_height(entry_height, ledger_entry_height);
+        // Without the ledger's tail there is nothing to check the first entries against
+        let last_entry_id = if config.verify_entries {
+            Self::ledger_tail_id(ledger_path, entry_height)
+        } else {
+            None
+        };
+        if config.verify_entries && last_entry_id.is_none() {
+            warn!("write_stage can't verify entries against the ledger");
+        }
+        Self::spawn(
+            keypair,
+            transaction_processor,
+            blockthread,
+            ledger_writer,
+            entry_receiver,
+            entry_height,
+            last_entry_id,
+            config,
+            vote_bind_addr,
+            exit,
+        )
+    }
+
+    /// Start writing to `ledger_writer`, which already holds `entry_height` entries ending
+    /// in `last_entry_id`.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    fn spawn<W: EntryWriter + Send + 'static>(
+        keypair: Arc<Keypair>,
+        transaction_processor: Arc<TransactionProcessor>,
+        blockthread: Arc<RwLock<BlockThread>>,
+        ledger_writer: W,
+        entry_receiver: Receiver<Vec<Entry>>,
+        entry_height: u64,
+        mut last_entry_id: Option<Hash>,
+        config: WriteStageConfig,
+        vote_bind_addr: SocketAddr,
+        exit: Arc<AtomicBool>,
+    ) -> Result<(Self, Receiver<Vec<Entry>>)> {
         let (vote_blob_sender, vote_blob_receiver) = channel();
-        let send = UdpSocket::bind("0.0.0.0:0").expect("bind");
-        let t_responder = responder(
+        let send = UdpSocket::bind(vote_bind_addr)?;
+        let vote_sender_addr = send.local_addr()?;
+        let t_responder = responder_with_retry(
             "write_stage_vote_sender",
             Arc::new(send),
             vote_blob_receiver,
+            config.vote_send_retries,
         );
-        let (entry_sender, entry_receiver_forward) = channel();
-        let mut ledger_writer = LedgerWriter::recover(ledger_path).unwrap();
+        let (entry_sender, entry_receiver_forward) = sync_channel(config.entry_channel_bound);
 
+        let affinity = config.thread_config.affinity;
+        let thread_exit = exit.clone();
         let write_thread = Builder::new()
-            .name("hypercube-writer".to_string())
+            .name(config.thread_config.name.clone())
             .spawn(move || {
+                if let Some(cpu) = affinity {
+                    // Running unpinned is slower, not wrong, so carry on
+                    if let Err(e) = set_thread_affinity(cpu) {
+                        inc_new_counter_info!("write_stage-set_affinity-error", 1);
+                        warn!("can't pin the writer to cpu {}: {:?}", cpu, e);
+                    }
+                }
                 let mut last_vote = 0;
+                let mut vote_cadence = VoteCadence::new(config.vote_interval_entries);
                 let mut last_valid_validator_timestamp = 0;
                 let id;
                 let leader_rotation_interval;
                 {
-                    let rblockthread = blockthread.read().unwrap();
+                    let rblockthread = match blockthread.read() {
+                        Ok(rblockthread) => rblockthread,
+                        Err(_) => return Self::poisoned(entry_height),
+                    };
                     id = rblockthread.id;
                     leader_rotation_interval = rblockthread.get_leader_rotation_interval();
                 }
+                let ledger_entry_height = entry_height;
+                let mut ledger_writer = LedgerWriteThread::new(
+                    ledger_writer,
+                    entry_sender,
+                    WRITE_STAGE_LEDGER_QUEUE_BOUND,
+                );
+                let vote_inserter =
+                    VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND);
                 let mut entry_height = entry_height;
-                loop {
-                    if entry_height % (leader_rotation_interval as u64) == 0 {
-                        let rblockthread = blockthread.read().unwrap();
-                        let my_id = rblockthread.my_data().id;
-                        let scheduled_leader = rblockthread.get_scheduled_leader(entry_height);
-                        drop(rblockthread);
-                        match scheduled_leader {
-                            Some(id) if id == my_id => (),
-                            
-                            _ => {
-                                
-                                return WriteStageReturnType::LeaderRotation;
-                            }
-                        }
+                let mut leader_lookahead = LeaderLookahead::new(leader_rotation_interval);
+                let (return_type, entry_height) = loop {
+                    if thread_exit.load(Ordering::Relaxed) {
+                        break (WriteStageReturnType::Exited, entry_height);
+                    }
+
+                    match leader_lookahead.is_leader_rotation(&blockthread, entry_height) {
+                        Ok(true) => break (WriteStageReturnType::LeaderRotation, entry_height),
+                        Ok(false) => (),
+                        Err(_) => break Self::poisoned(entry_height),
                     }
 
                     if let Err(e) = Self::write_and_send_entries(
                         &blockthread,
                         &mut ledger_writer,
-                        &entry_sender,
+                        &vote_inserter,
                         &entry_receiver,
                         &mut entry_height,
                         leader_rotation_interval,
+                        &config,
+                        last_entry_id.as_mut(),
                     ) {
                         match e {
                             Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => {
-                                return WriteStageReturnType::ChannelDisconnected
+                                break (WriteStageReturnType::ChannelDisconnected, entry_height)
                             }
                             Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                            Error::PoisonError => break Self::poisoned(entry_height),
+                            Error::BrokenEntryChain => {
+                                error!(
+                                    "write_stage entries don't chain onto height {}",
+                                    entry_height
+                                );
+                                break (WriteStageReturnType::BrokenEntryChain, entry_height);
+                            }
+                            // The ledger thread has stopped; joining it below reports why
+                            Error::LedgerWriteError(_) => {
+                                break (WriteStageReturnType::LedgerWriteFailed, entry_height)
+                            }
                             _ => {
                                 inc_new_counter_info!(
                                     "write_stage-write_and_send_entries-error",
@@ -230,6 +871,15 @@ impl WriteStage {
                             }
                         }
                     };
+                    // Voting takes the blockthread lock and would panic on a poisoned one
+                    if blockthread.is_poisoned() {
+                        break Self::poisoned(entry_height);
+                    }
+                    if !vote_cadence.is_due(entry_height) {
+                        inc_new_counter_info!("write_stage-leader_vote-throttled", 1);
+                        continue;
+                    }
+                    let previous_vote = last_vote;
                     if let Err(e) = send_leader_vote(
                         &id,
                         &keypair,
@@ -242,46 +892,105 @@ impl WriteStage {
                         inc_new_counter_info!("write_stage-leader_vote-error", 1);
                         error!("{:?}", e);
                     }
+                    // `last_vote` only moves when a vote goes out
+                    if last_vote != previous_vote {
+                        vote_cadence.voted(entry_height);
+                    }
+                };
+                // Whoever takes over from here reads the ledger, so it has to hold every
+                // entry that was broadcast
+                let (written, result) = ledger_writer.join();
+                if vote_inserter.join().is_err() {
+                    error!("write_stage vote inserter panicked");
+                }
+                match result {
+                    Ok(()) => (return_type, entry_height),
+                    Err(e) => {
+                        inc_new_counter_info!("write_stage-ledger_write-error", 1);
+                        error!(
+                            "ledger write failed at height {}: {:?}",
+                            ledger_entry_height + written,
+                            e
+                        );
+                        (return_type, ledger_entry_height + written)
+                    }
                 }
             }).unwrap();
 
         let thread_hdls = vec![t_responder];
-        (
+        Ok((
             WriteStage {
                 write_thread,
                 thread_hdls,
+                vote_sender_addr,
+                exit,
             },
             entry_receiver_forward,
-        )
+        ))
+    }
+
+    /// Stop writing once the blockthread lock is poisoned. Everything counted in
+    /// `entry_height` is in the ledger once the ledger thread is joined, so the caller can
+    /// restart from it.
+    fn poisoned(entry_height: u64) -> (WriteStageReturnType, u64) {
+        inc_new_counter_info!("write_stage-blockthread_poisoned", 1);
+        error!("write_stage blockthread lock poisoned at height {}", entry_height);
+        (WriteStageReturnType::Poisoned, entry_height)
+    }
+
+    /// The address votes are sent from.
+    pub fn vote_sender_addr(&self) -> SocketAddr {
+        self.vote_sender_addr
     }
 }
 
 impl Service for WriteStage {
-    type JoinReturnType = WriteStageReturnType;
+    /// Why the writer stopped, and the entry height of the ledger it left behind
+    type JoinReturnType = (WriteStageReturnType, u64);
 
-    fn join(self) -> thread::Result<WriteStageReturnType> {
+    fn join(self) -> thread::Result<(WriteStageReturnType, u64)> {
         for thread_hdl in self.thread_hdls {
             thread_hdl.join()?;
         }
 
         self.write_thread.join()
     }
+
+    fn exit(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use transaction_processor::TransactionProcessor;
     use blockthread::{BlockThread, Node};
+    use builtin_tansaction::SystemTransaction;
     use entry::Entry;
     use hash::Hash;
-    use ledger::{genesis, next_entries_mut, read_ledger};
+    use ledger::{genesis, next_entries_mut, read_ledger, EntryWriter, LedgerWriter};
+    use result::Error;
     use service::Service;
     use signature::{Keypair, KeypairUtil};
     use xpz_program_interface::pubkey::Pubkey;
     use std::fs::remove_dir_all;
-    use std::sync::mpsc::{channel, Receiver, Sender};
-    use std::sync::{Arc, RwLock};
-    use write_stage::{WriteStage, WriteStageReturnType};
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender};
+    use std::sync::{Arc, Mutex, RwLock};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use transaction::Transaction;
+    use vote_program::Vote;
+    use vote_transaction::VoteTransaction;
+    use write_stage::{
+        EntrySink, InlineEntrySink, LeaderLookahead, LedgerWriteThread, ThreadConfig, VoteCadence,
+        VoteInsertThread, WriteStage, WriteStageConfig, WriteStageReturnType,
+        WRITE_STAGE_ENTRY_CHANNEL_BOUND, WRITE_STAGE_LEDGER_QUEUE_BOUND,
+        WRITE_STAGE_MAX_COALESCE_MS, WRITE_STAGE_RECV_TIMEOUT_MS,
+        WRITE_STAGE_VOTE_INTERVAL_ENTRIES, WRITE_STAGE_VOTE_QUEUE_BOUND,
+    };
 
     struct DummyWriteStage {
         my_id: Pubkey,
@@ -292,6 +1001,7 @@ mod tests {
         transaction_processor: Arc<TransactionProcessor>,
         leader_ledger_path: String,
         ledger_tail: Vec<Entry>,
+        exit: Arc<AtomicBool>,
     }
 
     fn process_ledger(ledger_path: &str, transaction_processor: &TransactionProcessor) -> (u64, Vec<Entry>) {
@@ -305,6 +1015,13 @@ mod tests {
     }
 
     fn setup_dummy_write_stage(leader_rotation_interval: u64) -> DummyWriteStage {
+        setup_dummy_write_stage_with_config(leader_rotation_interval, WriteStageConfig::default())
+    }
+
+    fn setup_dummy_write_stage_with_config(
+        leader_rotation_interval: u64,
+        config: WriteStageConfig,
+    ) -> DummyWriteStage {
         // Setup leader info
         let leader_keypair = Arc::new(Keypair::new());
         let my_id = leader_keypair.pubkey();
@@ -323,6 +1040,7 @@ mod tests {
 
         // Make a dummy pipe
         let (entry_sender, entry_receiver) = channel();
+        let exit = Arc::new(AtomicBool::new(false));
 
         // Start up the write stage
         let (write_stage, _write_stage_entry_receiver) = WriteStage::new(
@@ -332,7 +1050,10 @@ mod tests {
             &leader_ledger_path,
             entry_receiver,
             entry_height,
-        );
+            config,
+            socketaddr!("127.0.0.1:0"),
+            exit.clone(),
+        ).expect("WriteStage::new");
 
         DummyWriteStage {
             my_id,
@@ -345,6 +1066,7 @@ mod tests {
             transaction_processor,
             leader_ledger_path,
             ledger_tail,
+            exit,
         }
     }
 
@@ -391,7 +1113,7 @@ mod tests {
 
         assert_eq!(
             write_stage_info.write_stage.join().unwrap(),
-            WriteStageReturnType::LeaderRotation
+            (WriteStageReturnType::LeaderRotation, 2 * leader_rotation_interval)
         );
 
         // Make sure the ledger contains exactly 2 * leader_rotation_interval entries
@@ -402,33 +1124,1015 @@ mod tests {
     }
 
     #[test]
-    fn test_leader_index_calculation() {
-        // Set up a dummy node
-        let leader_keypair = Arc::new(Keypair::new());
-        let my_id = leader_keypair.pubkey();
-        let leader_info = Node::new_localhost_with_pubkey(leader_keypair.pubkey());
+    fn test_write_stage_broken_entry_chain() {
+        let config = WriteStageConfig {
+            verify_entries: true,
+            ..WriteStageConfig::default()
+        };
+        let write_stage_info = setup_dummy_write_stage_with_config(100, config);
+        let mut last_id = write_stage_info.ledger_tail.last().unwrap().id;
+        let mut num_hashes = 0;
+        let genesis_entry_height = write_stage_info.ledger_tail.len() as u64;
 
-        let leader_rotation_interval = 10;
+        // A good batch, one that doesn't chain onto it, then another good one
+        let entries = next_entries_mut(&mut last_id, &mut num_hashes, vec![]);
+        write_stage_info.entry_sender.send(entries).unwrap();
+        let entries = vec![Entry::new(&Hash::default(), 0, vec![])];
+        write_stage_info.entry_sender.send(entries).unwrap();
+        let entries = next_entries_mut(&mut last_id, &mut num_hashes, vec![]);
+        write_stage_info.entry_sender.send(entries).unwrap();
 
-        // An epoch is the period of leader_rotation_interval entries
-        // time during which a leader is in power
-        let num_epochs = 3;
+        // The writer stops at the break instead of dropping everything after it
+        assert_eq!(
+            write_stage_info.write_stage.join().unwrap(),
+            (WriteStageReturnType::BrokenEntryChain, genesis_entry_height + 1)
+        );
+        assert_eq!(
+            read_ledger(&write_stage_info.leader_ledger_path, true)
+                .unwrap()
+                .count() as u64,
+            genesis_entry_height + 1
+        );
+        drop(write_stage_info.entry_sender);
+        remove_dir_all(write_stage_info.leader_ledger_path).unwrap();
+    }
 
+    #[test]
+    fn test_write_stage_ledger_write_failed() {
+        let leader_keypair = Arc::new(Keypair::new());
+        let leader_info = Node::new_localhost_with_pubkey(leader_keypair.pubkey());
         let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
-        blockthread.set_leader_rotation_interval(leader_rotation_interval as u64);
-        for i in 0..num_epochs {
-            blockthread.set_scheduled_leader(i * leader_rotation_interval, my_id)
+        blockthread.set_leader_rotation_interval(100);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+        let transaction_processor = Arc::new(TransactionProcessor::new_default(true));
+        let (_, ledger_path) = genesis("test_write_stage_ledger_write_failed", 10_000);
+        let (entry_height, ledger_tail) = process_ledger(&ledger_path, &transaction_processor);
+        let (entry_sender, entry_receiver) = channel();
+        let (write_stage, write_stage_entry_receiver) = WriteStage::spawn(
+            leader_keypair,
+            transaction_processor,
+            blockthread,
+            FailingEntryWriter,
+            entry_receiver,
+            entry_height,
+            None,
+            WriteStageConfig::default(),
+            socketaddr!("127.0.0.1:0"),
+            Arc::new(AtomicBool::new(false)),
+        ).expect("WriteStage::spawn");
+
+        // Keep feeding the writer until it stops taking entries
+        let mut last_id = ledger_tail.last().unwrap().id;
+        let mut num_hashes = 0;
+        let start = Instant::now();
+        loop {
+            let entries = next_entries_mut(&mut last_id, &mut num_hashes, vec![]);
+            if entry_sender.send(entries).is_err() {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
         }
 
-        let blockthread = Arc::new(RwLock::new(blockthread));
-        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        // Nothing was written, so nothing was broadcast either
+        assert_eq!(
+            write_stage.join().unwrap(),
+            (WriteStageReturnType::LedgerWriteFailed, entry_height)
+        );
+        assert!(write_stage_entry_receiver.try_recv().is_err());
+        remove_dir_all(ledger_path).unwrap();
+    }
 
-        // A vector that is completely within a certain epoch should return that
-        // entire vector
-        let mut len = leader_rotation_interval as usize - 1;
-        let mut input = vec![entry.clone(); len];
-        let mut result = WriteStage::find_leader_rotation_index(
-            &blockthread,
+    #[test]
+    fn test_write_stage_exit() {
+        let leader_rotation_interval = 10;
+        let write_stage_info = setup_dummy_write_stage(leader_rotation_interval);
+
+        // The sender stays alive, so only the exit flag can stop the writer
+        write_stage_info.exit.store(true, Ordering::Relaxed);
+        let genesis_entry_height = write_stage_info.ledger_tail.len() as u64;
+        assert_eq!(
+            write_stage_info.write_stage.join().unwrap(),
+            (WriteStageReturnType::Exited, genesis_entry_height)
+        );
+        drop(write_stage_info.entry_sender);
+        remove_dir_all(write_stage_info.leader_ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_stage_service_exit() {
+        let write_stage_info = setup_dummy_write_stage(10);
+
+        write_stage_info.write_stage.exit();
+        assert!(write_stage_info.exit.load(Ordering::Relaxed));
+        let genesis_entry_height = write_stage_info.ledger_tail.len() as u64;
+        assert_eq!(
+            write_stage_info.write_stage.join().unwrap(),
+            (WriteStageReturnType::Exited, genesis_entry_height)
+        );
+        drop(write_stage_info.entry_sender);
+        remove_dir_all(write_stage_info.leader_ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_stage_poisoned_blockthread() {
+        let write_stage_info = setup_dummy_write_stage(10);
+
+        // Panic while holding the write lock so every later lock attempt fails
+        let blockthread = write_stage_info.blockthread.clone();
+        let poisoner = thread::spawn(move || {
+            let _wblockthread = blockthread.write().unwrap();
+            panic!("poison the blockthread lock");
+        });
+        assert!(poisoner.join().is_err());
+        assert!(write_stage_info.blockthread.is_poisoned());
+
+        // The writer notices on its next pass instead of panicking itself
+        let genesis_entry_height = write_stage_info.ledger_tail.len() as u64;
+        assert_eq!(
+            write_stage_info.write_stage.join().unwrap(),
+            (WriteStageReturnType::Poisoned, genesis_entry_height)
+        );
+        drop(write_stage_info.entry_sender);
+        remove_dir_all(write_stage_info.leader_ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_stage_vote_bind_addr() {
+        let write_stage_info = setup_dummy_write_stage(10);
+
+        // Votes go out through the interface the stage was told to bind
+        let vote_sender_addr = write_stage_info.write_stage.vote_sender_addr();
+        assert_eq!(vote_sender_addr.ip(), IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+        assert_ne!(vote_sender_addr.port(), 0);
+
+        write_stage_info.exit.store(true, Ordering::Relaxed);
+        write_stage_info.write_stage.join().unwrap();
+        drop(write_stage_info.entry_sender);
+        remove_dir_all(write_stage_info.leader_ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_stage_missing_ledger() {
+        let leader_keypair = Arc::new(Keypair::new());
+        let leader_info = Node::new_localhost_with_pubkey(leader_keypair.pubkey());
+        let blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        let blockthread = Arc::new(RwLock::new(blockthread));
+        let transaction_processor = Arc::new(TransactionProcessor::new_default(true));
+        let (_entry_sender, entry_receiver) = channel();
+
+        let ledger_path = "/tmp/test_write_stage_missing_ledger/does-not-exist";
+        let write_stage = WriteStage::new(
+            leader_keypair,
+            transaction_processor,
+            blockthread,
+            ledger_path,
+            entry_receiver,
+            0,
+            WriteStageConfig::default(),
+            socketaddr!("127.0.0.1:0"),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        // No panic, and the error says which ledger it couldn't open
+        match write_stage {
+            Err(Error::IO(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::NotFound);
+                assert!(e.to_string().contains(ledger_path));
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("write stage started without a ledger"),
+        }
+    }
+
+    /// The id of this process's thread called `name`.
+    #[cfg(target_os = "linux")]
+    fn thread_id(name: &str) -> Option<i32> {
+        use std::fs;
+
+        fs::read_dir("/proc/self/task")
+            .ok()?
+            .filter_map(|task| task.ok())
+            .find(|task| {
+                fs::read_to_string(task.path().join("comm"))
+                    .map(|comm| comm.trim() == name)
+                    .unwrap_or(false)
+            }).and_then(|task| task.file_name().to_str()?.parse().ok())
+    }
+
+    /// The CPUs thread `tid` may run on, 0 being the calling thread.
+    #[cfg(target_os = "linux")]
+    fn thread_affinity(tid: i32) -> Vec<usize> {
+        use libc::{cpu_set_t, sched_getaffinity, CPU_ISSET};
+        use std::mem;
+
+        unsafe {
+            let mut cpus: cpu_set_t = mem::zeroed();
+            assert_eq!(sched_getaffinity(tid, mem::size_of::<cpu_set_t>(), &mut cpus), 0);
+            (0..8 * mem::size_of::<cpu_set_t>())
+                .filter(|&cpu| CPU_ISSET(cpu, &cpus))
+                .collect()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_write_stage_thread_affinity() {
+        // Ask for a CPU this process is already allowed on
+        let cpu = thread_affinity(0)[0];
+        let config = WriteStageConfig {
+            thread_config: ThreadConfig {
+                name: "pinned-writer".to_string(),
+                affinity: Some(cpu),
+            },
+            ..WriteStageConfig::default()
+        };
+        let write_stage_info = setup_dummy_write_stage_with_config(10, config);
+
+        // The writer pins itself once it starts running
+        let start = Instant::now();
+        let mut pinned = false;
+        while !pinned && start.elapsed() < Duration::from_secs(5) {
+            pinned = thread_id("pinned-writer").map(thread_affinity) == Some(vec![cpu]);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(pinned);
+
+        write_stage_info.exit.store(true, Ordering::Relaxed);
+        write_stage_info.write_stage.join().unwrap();
+        drop(write_stage_info.entry_sender);
+        remove_dir_all(write_stage_info.leader_ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_and_send_entries_recv_timeout() {
+        let leader_keypair = Keypair::new();
+        let leader_info = Node::new_localhost_with_pubkey(leader_keypair.pubkey());
+        let blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (_, ledger_path) = genesis("test_write_and_send_entries_recv_timeout", 10_000);
+        let (mut ledger_writer, _) = LedgerWriter::recover(&ledger_path).unwrap();
+        let (entry_sender, _entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (_entry_input_sender, entry_receiver) = channel();
+        let mut entry_height = 0;
+
+        // Nothing is ever sent, so the call should give up after the short timeout
+        // rather than the default one
+        let now = Instant::now();
+        let result = WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut ledger_writer,
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            10,
+            &WriteStageConfig {
+                recv_timeout: Duration::from_millis(10),
+                ..WriteStageConfig::default()
+            },
+            None,
+        );
+        let elapsed = now.elapsed();
+
+        remove_dir_all(ledger_path).unwrap();
+        match result {
+            Err(Error::RecvTimeoutError(RecvTimeoutError::Timeout)) => (),
+            _ => panic!("expected a receive timeout"),
+        }
+        assert!(elapsed < Duration::from_millis(WRITE_STAGE_RECV_TIMEOUT_MS));
+        assert_eq!(entry_height, 0);
+    }
+
+    #[test]
+    fn test_write_and_send_entries_stats() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (_, ledger_path) = genesis("test_write_and_send_entries_stats", 10_000);
+        let (mut ledger_writer, _) = LedgerWriter::recover(&ledger_path).unwrap();
+        let (entry_sender, entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+
+        let last_id = Hash::default();
+        let entries: Vec<Entry> = (0..3)
+            .map(|i| {
+                let transactions = (0..i + 1)
+                    .map(|_| Transaction::system_new(&leader_keypair, my_id, 1, last_id))
+                    .collect();
+                Entry::new(&last_id, 0, transactions)
+            }).collect();
+        entry_input_sender.send(entries).unwrap();
+
+        let mut entry_height = 0;
+        let stats = WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut ledger_writer,
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            10,
+            &WriteStageConfig::default(),
+            None,
+        ).unwrap();
+
+        remove_dir_all(ledger_path).unwrap();
+        assert_eq!(stats.num_txs, 6);
+        assert_eq!(stats.num_new_entries, 3);
+        assert_eq!(entry_height, 3);
+        assert_eq!(entry_forward_receiver.try_recv().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_write_and_send_entries_entry_tx_counts() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (_, ledger_path) = genesis("test_write_and_send_entries_entry_tx_counts", 10_000);
+        let (mut ledger_writer, _) = LedgerWriter::recover(&ledger_path).unwrap();
+        let (entry_sender, _entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+
+        // Lopsided entries, split across two sends
+        let last_id = Hash::default();
+        let new_entry = |num_txs| {
+            let transactions = (0..num_txs)
+                .map(|_| Transaction::system_new(&leader_keypair, my_id, 1, last_id))
+                .collect();
+            Entry::new(&last_id, 0, transactions)
+        };
+        entry_input_sender
+            .send(vec![new_entry(2), new_entry(5)])
+            .unwrap();
+        entry_input_sender.send(vec![new_entry(1)]).unwrap();
+
+        let mut entry_height = 0;
+        let stats = WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut ledger_writer,
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            10,
+            &WriteStageConfig::default(),
+            None,
+        ).unwrap();
+
+        remove_dir_all(ledger_path).unwrap();
+        assert_eq!(stats.num_txs, 8);
+        assert_eq!(stats.num_new_entries, 3);
+        assert_eq!(stats.max_entry_txs, 5);
+        assert_eq!(stats.min_entry_txs, 1);
+    }
+
+    #[test]
+    fn test_write_and_send_entries_max_entries_per_iteration() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(100);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (_, ledger_path) =
+            genesis("test_write_and_send_entries_max_entries_per_iteration", 10_000);
+        let (mut ledger_writer, _) = LedgerWriter::recover(&ledger_path).unwrap();
+        let (entry_sender, _entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+
+        // A flood of ten chunks of four entries, all queued before the writer runs
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        for _ in 0..10 {
+            entry_input_sender.send(vec![entry.clone(); 4]).unwrap();
+        }
+
+        // Each pass stops accumulating at the cap and flushes, leaving the rest queued
+        let mut entry_height = 0;
+        for pass in 1..6 {
+            let stats = WriteStage::write_and_send_entries(
+                &blockthread,
+                &mut InlineEntrySink {
+                    ledger_writer: &mut ledger_writer,
+                    entry_sender: &entry_sender,
+                },
+                &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+                &entry_receiver,
+                &mut entry_height,
+                100,
+                &WriteStageConfig {
+                    max_entries_per_iteration: 8,
+                    ..WriteStageConfig::default()
+                },
+                None,
+            ).unwrap();
+            assert_eq!(stats.num_new_entries, 8);
+            assert_eq!(entry_height, pass * 8);
+        }
+        assert!(entry_receiver.try_recv().is_err());
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_and_send_entries_schedule_lookahead() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        blockthread.set_scheduled_leader(0, my_id);
+        blockthread.set_scheduled_leader(10, my_id);
+        blockthread.set_scheduled_leader(20, Keypair::new().pubkey());
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (_, ledger_path) = genesis("test_write_and_send_entries_schedule_lookahead", 10_000);
+        let (mut ledger_writer, _) = LedgerWriter::recover(&ledger_path).unwrap();
+        let (entry_sender, _entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+
+        // The second batch reaches well past the heights read for the first
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        entry_input_sender.send(vec![entry.clone()]).unwrap();
+        entry_input_sender.send(vec![entry.clone(); 30]).unwrap();
+
+        // It still keeps leading through height 10 and rotates at 20
+        let mut entry_height = 0;
+        let stats = WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut ledger_writer,
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            10,
+            &WriteStageConfig {
+                max_entries_per_iteration: 5,
+                max_coalesce: Duration::from_secs(60),
+                ..WriteStageConfig::default()
+            },
+            None,
+        ).unwrap();
+        assert_eq!(stats.num_new_entries, 20);
+        assert_eq!(entry_height, 20);
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_and_send_entries_max_coalesce() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(0);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        // A sender that never lets the channel run dry
+        let (entry_input_sender, entry_receiver) = sync_channel(16);
+        let t_send = thread::spawn(move || {
+            let entry = Entry::new(&Hash::default(), 0, vec![]);
+            while entry_input_sender.send(vec![entry.clone()]).is_ok() {}
+        });
+
+        let (entry_sender, entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let max_coalesce = Duration::from_millis(WRITE_STAGE_MAX_COALESCE_MS);
+        let mut entry_height = 0;
+        let start = Instant::now();
+        let stats = WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut RecordingEntryWriter::default(),
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            0,
+            &WriteStageConfig {
+                flush_interval_entries: usize::max_value(),
+                max_entries_per_iteration: usize::max_value(),
+                max_coalesce,
+                ..WriteStageConfig::default()
+            },
+            None,
+        ).unwrap();
+
+        // However fast entries arrive, it stops gathering at the deadline and flushes
+        assert!(start.elapsed() < max_coalesce * 5);
+        assert!(stats.num_new_entries > 0);
+        assert_eq!(entry_height, stats.num_new_entries as u64);
+        assert_eq!(
+            entry_forward_receiver.try_recv().unwrap().len(),
+            stats.num_new_entries
+        );
+
+        drop(entry_receiver);
+        t_send.join().unwrap();
+    }
+
+    #[test]
+    fn test_write_and_send_entries_flush_interval() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(100);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (_, ledger_path) = genesis("test_write_and_send_entries_flush_interval", 10_000);
+        let genesis_len = read_ledger(&ledger_path, true).unwrap().count();
+        let (mut ledger_writer, _) = LedgerWriter::recover(&ledger_path).unwrap();
+        let (entry_sender, entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+
+        // Three chunks of four entries, flushed in batches of at least five, leave a
+        // partial batch at the end that still has to reach the ledger
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        for _ in 0..3 {
+            entry_input_sender.send(vec![entry.clone(); 4]).unwrap();
+        }
+
+        let mut entry_height = 0;
+        WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut ledger_writer,
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            100,
+            &WriteStageConfig {
+                flush_interval_entries: 5,
+                ..WriteStageConfig::default()
+            },
+            None,
+        ).unwrap();
+        drop(ledger_writer);
+
+        assert_eq!(entry_height, 12);
+        assert_eq!(entry_forward_receiver.try_recv().unwrap().len(), 8);
+        assert_eq!(entry_forward_receiver.try_recv().unwrap().len(), 4);
+        assert!(entry_forward_receiver.try_recv().is_err());
+        assert_eq!(
+            read_ledger(&ledger_path, true).unwrap().count(),
+            genesis_len + 12
+        );
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_and_send_entries_broken_chain() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(100);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (_, ledger_path) = genesis("test_write_and_send_entries_broken_chain", 10_000);
+        let ledger_tail = read_ledger(&ledger_path, true)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let (mut ledger_writer, _) = LedgerWriter::recover(&ledger_path).unwrap();
+        let (entry_sender, _entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+
+        let mut last_id = ledger_tail.last().unwrap().id;
+        let mut num_hashes = 0;
+        let mut entry_height = 0;
+        let mut last_entry_id = last_id;
+        let tail_id;
+        {
+            let mut write = |entries: Vec<Entry>, last_entry_id: &mut Hash| {
+                entry_input_sender.send(entries).unwrap();
+                WriteStage::write_and_send_entries(
+                    &blockthread,
+                    &mut InlineEntrySink {
+                        ledger_writer: &mut ledger_writer,
+                        entry_sender: &entry_sender,
+                    },
+                    &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+                    &entry_receiver,
+                    &mut entry_height,
+                    100,
+                    &WriteStageConfig::default(),
+                    Some(last_entry_id),
+                )
+            };
+
+            // Entries that chain onto the ledger are written, and become its new tail
+            let entries = next_entries_mut(&mut last_id, &mut num_hashes, vec![]);
+            tail_id = entries.last().unwrap().id;
+            assert!(write(entries, &mut last_entry_id).is_ok());
+            assert_eq!(last_entry_id, tail_id);
+
+            // An entry that doesn't is rejected along with the rest of its batch
+            let mut entries = next_entries_mut(&mut last_id, &mut num_hashes, vec![]);
+            entries.insert(0, Entry::new(&Hash::default(), 0, vec![]));
+            match write(entries, &mut last_entry_id) {
+                Err(Error::BrokenEntryChain) => (),
+                _ => panic!("expected a broken entry chain"),
+            }
+            assert_eq!(last_entry_id, tail_id);
+        }
+
+        assert_eq!(entry_height, 1);
+        drop(ledger_writer);
+        assert_eq!(
+            read_ledger(&ledger_path, true).unwrap().count(),
+            ledger_tail.len() + 1
+        );
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_recover_entry_height() {
+        let (_, ledger_path) = genesis("test_recover_entry_height", 10_000);
+        let genesis_len = read_ledger(&ledger_path, true).unwrap().count() as u64;
+        {
+            let (mut ledger_writer, ledger_entry_height) =
+                LedgerWriter::recover(&ledger_path).unwrap();
+            assert_eq!(ledger_entry_height, genesis_len);
+            let entry = Entry::new(&Hash::default(), 0, vec![]);
+            ledger_writer.write_entries(vec![entry; 5]).unwrap();
+        }
+
+        let entry_height = genesis_len + 5;
+        let (_, ledger_entry_height) = LedgerWriter::recover(&ledger_path).unwrap();
+        assert_eq!(ledger_entry_height, entry_height);
+        assert_eq!(
+            WriteStage::reconcile_entry_height(entry_height, ledger_entry_height),
+            entry_height
+        );
+
+        // A stale height from the caller is overridden by the ledger
+        assert_eq!(
+            WriteStage::reconcile_entry_height(entry_height - 5, ledger_entry_height),
+            entry_height
+        );
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_and_send_entries_backpressure() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        // Room for a single batch downstream, and nobody reading it yet
+        let (entry_sender, entry_forward_receiver) = sync_channel(1);
+        let (entry_input_sender, entry_receiver) = channel();
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        for _ in 0..3 {
+            entry_input_sender.send(vec![entry.clone()]).unwrap();
+        }
+
+        let (done_sender, done_receiver) = channel();
+        let t_write = thread::spawn(move || {
+            let mut entry_height = 0;
+            let stats = WriteStage::write_and_send_entries(
+                &blockthread,
+                &mut InlineEntrySink {
+                    ledger_writer: &mut RecordingEntryWriter::default(),
+                    entry_sender: &entry_sender,
+                },
+                &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+                &entry_receiver,
+                &mut entry_height,
+                10,
+                &WriteStageConfig::default(),
+                None,
+            ).unwrap();
+            done_sender.send(()).unwrap();
+            stats
+        });
+
+        // The writer stalls behind the full channel instead of queueing more batches
+        assert!(
+            done_receiver
+                .recv_timeout(Duration::from_millis(100))
+                .is_err()
+        );
+
+        for _ in 0..3 {
+            assert_eq!(entry_forward_receiver.recv().unwrap().len(), 1);
+        }
+        let stats = t_write.join().unwrap();
+        assert_eq!(stats.num_new_entries, 3);
+        assert!(stats.num_send_blocked > 0);
+    }
+
+    struct FailingEntryWriter;
+
+    impl EntryWriter for FailingEntryWriter {
+        fn write_entries(&mut self, _entries: &[Entry]) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Other, "No space left on device"))
+        }
+    }
+
+    /// Remembers where each written batch lived in memory
+    #[derive(Default)]
+    struct RecordingEntryWriter {
+        written: Vec<(*const Entry, usize)>,
+    }
+
+    impl EntryWriter for RecordingEntryWriter {
+        fn write_entries(&mut self, entries: &[Entry]) -> io::Result<()> {
+            self.written.push((entries.as_ptr(), entries.len()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_and_send_entries_no_copy() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (entry_sender, entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        entry_input_sender.send(vec![entry; 3]).unwrap();
+
+        let mut ledger_writer = RecordingEntryWriter::default();
+        let mut entry_height = 0;
+        WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut ledger_writer,
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            10,
+            &WriteStageConfig::default(),
+            None,
+        ).unwrap();
+
+        // The batch that went to the ledger is the very buffer forwarded downstream,
+        // so no per-batch copy of the entries is made
+        let forwarded = entry_forward_receiver.try_recv().unwrap();
+        assert_eq!(ledger_writer.written, vec![(forwarded.as_ptr(), 3)]);
+    }
+
+    /// Takes `delay` over every batch, like a disk that can't keep up
+    struct SlowEntryWriter {
+        delay: Duration,
+        written: Arc<Mutex<Vec<Entry>>>,
+    }
+
+    impl EntryWriter for SlowEntryWriter {
+        fn write_entries(&mut self, entries: &[Entry]) -> io::Result<()> {
+            thread::sleep(self.delay);
+            self.written.lock().unwrap().extend_from_slice(entries);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ledger_write_thread_slow_disk() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (entry_sender, entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+        let mut last_id = Hash::default();
+        let mut entries = vec![];
+        for i in 0..5 {
+            let entry = Entry::new(&last_id, i, vec![]);
+            last_id = entry.id;
+            entry_input_sender.send(vec![entry.clone()]).unwrap();
+            entries.push(entry);
+        }
+
+        let written = Arc::new(Mutex::new(vec![]));
+        let mut ledger_writer = LedgerWriteThread::new(
+            SlowEntryWriter {
+                delay: Duration::from_millis(200),
+                written: written.clone(),
+            },
+            entry_sender,
+            WRITE_STAGE_LEDGER_QUEUE_BOUND,
+        );
+        let mut entry_height = 0;
+        let start = Instant::now();
+        WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut ledger_writer,
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            10,
+            &WriteStageConfig::default(),
+            None,
+        ).unwrap();
+        assert_eq!(entry_height, 5);
+
+        // The writer hands everything over well before the disk has caught up
+        assert!(start.elapsed() < Duration::from_millis(5 * 200));
+
+        // ...and each batch is only broadcast once it is in the ledger
+        let mut broadcast = vec![];
+        for batch in entry_forward_receiver.iter().take(5) {
+            broadcast.extend(batch);
+            assert!(written.lock().unwrap().starts_with(&broadcast));
+        }
+        assert_eq!(broadcast, entries);
+
+        let (num_written, result) = ledger_writer.join();
+        assert!(result.is_ok());
+        assert_eq!(num_written, 5);
+        assert_eq!(*written.lock().unwrap(), broadcast);
+    }
+
+    #[test]
+    fn test_ledger_write_thread_failure() {
+        let (entry_sender, entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let mut ledger_writer = LedgerWriteThread::new(FailingEntryWriter, entry_sender, 1);
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+
+        // The first batch is only queued, so its failure shows up later
+        assert!(ledger_writer.write_and_send(vec![entry.clone()]).is_ok());
+        let start = Instant::now();
+        while ledger_writer.write_and_send(vec![entry.clone()]).is_ok() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (num_written, result) = ledger_writer.join();
+        assert_eq!(num_written, 0);
+        assert!(result.is_err());
+        // Nothing that failed to reach the ledger was broadcast
+        assert!(entry_forward_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_write_and_send_entries_vote_flood() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(1000);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        // Applying each batch of votes takes far longer than writing its entries
+        let delay = Duration::from_millis(100);
+        let inserted = Arc::new(Mutex::new(vec![]));
+        let thread_inserted = inserted.clone();
+        let insert = move |votes: &[(Pubkey, Vote, Hash)]| {
+            thread::sleep(delay);
+            let mut inserted = thread_inserted.lock().unwrap();
+            inserted.extend(votes.iter().map(|v| v.1.version));
+            Ok(())
+        };
+        let vote_inserter = VoteInsertThread::with_inserter(WRITE_STAGE_VOTE_QUEUE_BOUND, insert);
+
+        let (entry_sender, _entry_forward_receiver) =
+            sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+        let voter = Keypair::new();
+        let mut ledger_writer = RecordingEntryWriter::default();
+        let mut entry_height = 0;
+        for version in 0..10 {
+            let votes = (0..100)
+                .map(|i| {
+                    let vote = Vote {
+                        version: version * 100 + i,
+                        contact_info_version: 0,
+                    };
+                    Transaction::vote_new(&voter, vote, Hash::default(), 0)
+                }).collect();
+            entry_input_sender
+                .send(vec![Entry::new(&Hash::default(), 0, votes)])
+                .unwrap();
+
+            // Each pass only queues its votes, so it never waits on the ones before it
+            let start = Instant::now();
+            WriteStage::write_and_send_entries(
+                &blockthread,
+                &mut InlineEntrySink {
+                    ledger_writer: &mut ledger_writer,
+                    entry_sender: &entry_sender,
+                },
+                &vote_inserter,
+                &entry_receiver,
+                &mut entry_height,
+                1000,
+                &WriteStageConfig::default(),
+                None,
+            ).unwrap();
+            assert!(start.elapsed() < delay);
+        }
+        assert_eq!(entry_height, 10);
+
+        // Every vote is applied, in the order its entry was written
+        vote_inserter.join().unwrap();
+        assert_eq!(*inserted.lock().unwrap(), (0..1000).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_write_and_send_entries_write_failure() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        blockthread.set_scheduled_leader(0, my_id);
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let (entry_sender, entry_forward_receiver) = sync_channel(WRITE_STAGE_ENTRY_CHANNEL_BOUND);
+        let (entry_input_sender, entry_receiver) = channel();
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        entry_input_sender.send(vec![entry; 3]).unwrap();
+
+        let mut entry_height = 0;
+        let result = WriteStage::write_and_send_entries(
+            &blockthread,
+            &mut InlineEntrySink {
+                ledger_writer: &mut FailingEntryWriter,
+                entry_sender: &entry_sender,
+            },
+            &VoteInsertThread::new(blockthread.clone(), WRITE_STAGE_VOTE_QUEUE_BOUND),
+            &entry_receiver,
+            &mut entry_height,
+            10,
+            &WriteStageConfig::default(),
+            None,
+        );
+
+        assert_matches!(result, Err(Error::LedgerWriteError(_)));
+        assert_eq!(entry_height, 0);
+        assert!(entry_forward_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_leader_index_calculation() {
+        // Set up a dummy node
+        let leader_keypair = Arc::new(Keypair::new());
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(leader_keypair.pubkey());
+
+        let leader_rotation_interval = 10;
+
+        // An epoch is the period of leader_rotation_interval entries
+        // time during which a leader is in power
+        let num_epochs = 3;
+
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(leader_rotation_interval as u64);
+        for i in 0..num_epochs {
+            blockthread.set_scheduled_leader(i * leader_rotation_interval, my_id)
+        }
+
+        let blockthread = Arc::new(RwLock::new(blockthread));
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+
+        // A vector that is completely within a certain epoch should return that
+        // entire vector
+        let mut len = leader_rotation_interval as usize - 1;
+        let mut input = vec![entry.clone(); len];
+        let mut result = WriteStage::find_leader_rotation_index(
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
             leader_rotation_interval,
             (num_epochs - 1) * leader_rotation_interval,
             input.clone(),
@@ -441,7 +2145,7 @@ mod tests {
         len = leader_rotation_interval as usize - 1;
         input = vec![entry.clone(); len];
         result = WriteStage::find_leader_rotation_index(
-            &blockthread,
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
             leader_rotation_interval,
             (num_epochs * leader_rotation_interval) - 1,
             input.clone(),
@@ -456,7 +2160,7 @@ mod tests {
         len = 1;
         let mut input = vec![entry.clone(); len];
         result = WriteStage::find_leader_rotation_index(
-            &blockthread,
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
             leader_rotation_interval,
             leader_rotation_interval - 1,
             input.clone(),
@@ -469,7 +2173,7 @@ mod tests {
         len = leader_rotation_interval as usize;
         input = vec![entry.clone(); len];
         result = WriteStage::find_leader_rotation_index(
-            &blockthread,
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
             leader_rotation_interval,
             leader_rotation_interval - 1,
             input.clone(),
@@ -482,7 +2186,7 @@ mod tests {
         len = (num_epochs - 1) as usize * leader_rotation_interval as usize;
         input = vec![entry.clone(); len];
         result = WriteStage::find_leader_rotation_index(
-            &blockthread,
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
             leader_rotation_interval,
             leader_rotation_interval - 1,
             input.clone(),
@@ -496,7 +2200,7 @@ mod tests {
         len = (num_epochs - 1) as usize * leader_rotation_interval as usize + 1;
         input = vec![entry.clone(); len];
         result = WriteStage::find_leader_rotation_index(
-            &blockthread,
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
             leader_rotation_interval,
             leader_rotation_interval - 1,
             input.clone(),
@@ -509,12 +2213,185 @@ mod tests {
         len = leader_rotation_interval as usize;
         input = vec![entry.clone(); len];
         result = WriteStage::find_leader_rotation_index(
-            &blockthread,
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
             leader_rotation_interval,
             num_epochs * leader_rotation_interval,
             input.clone(),
         );
 
         assert_eq!(result, (vec![], true));
+
+        // With an interval of one every entry is a rotation point, so the vector
+        // is cut right before the first height that belongs to a different leader
+        let other_id = Keypair::new().pubkey();
+        {
+            let mut wblockthread = blockthread.write().unwrap();
+            wblockthread.set_leader_rotation_interval(1);
+            for height in 0..5 {
+                wblockthread.set_scheduled_leader(height, my_id);
+            }
+            wblockthread.set_scheduled_leader(5, other_id);
+        }
+        len = 10;
+        input = vec![entry.clone(); len];
+        result = WriteStage::find_leader_rotation_index(
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
+            1,
+            0,
+            input.clone(),
+        );
+
+        input.truncate(5);
+        assert_eq!(result, (input, true));
+
+        // An interval of zero never rotates and must not divide by zero
+        len = 10;
+        input = vec![entry.clone(); len];
+        result = WriteStage::find_leader_rotation_index(
+            &blockthread.read().unwrap().leader_schedule(0, u64::max_value()),
+            0,
+            5,
+            input.clone(),
+        );
+
+        assert_eq!(result, (input, false));
+    }
+
+    #[test]
+    fn test_leader_index_epoch_boundary() {
+        let my_id = Keypair::new().pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let leader_rotation_interval = 10;
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(leader_rotation_interval);
+        for height in &[0, 10, 20] {
+            blockthread.set_scheduled_leader(*height, my_id);
+        }
+        let leader_schedule = blockthread.leader_schedule(0, u64::max_value());
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+
+        // Starting exactly on a boundary this node keeps leading steps over the whole
+        // next epoch rather than stalling on it
+        let input = vec![entry.clone(); 15];
+        let result = WriteStage::find_leader_rotation_index(
+            &leader_schedule,
+            leader_rotation_interval,
+            leader_rotation_interval,
+            input.clone(),
+        );
+        assert_eq!(result, (input, false));
+
+        // Near the last height only the entries that fit are taken, and the writer
+        // rotates there instead of wrapping around
+        let input = vec![entry.clone(); 5];
+        let result = WriteStage::find_leader_rotation_index(
+            &leader_schedule,
+            leader_rotation_interval,
+            u64::max_value() - 2,
+            input.clone(),
+        );
+        assert_eq!(result, (input[..2].to_vec(), true));
+    }
+
+    #[test]
+    fn test_leader_schedule_snapshot() {
+        let my_id = Keypair::new().pubkey();
+        let other_id = Keypair::new().pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(10);
+        for height in &[0, 10, 20] {
+            blockthread.set_scheduled_leader(*height, my_id);
+        }
+        let blockthread = Arc::new(RwLock::new(blockthread));
+        let entry = Entry::new(&Hash::default(), 0, vec![]);
+        let input = vec![entry; 10];
+
+        // The schedule flaps after the snapshot is taken; decisions against the
+        // snapshot don't see it, no matter how often they are made
+        let leader_schedule = blockthread.read().unwrap().leader_schedule(0, u64::max_value());
+        blockthread
+            .write()
+            .unwrap()
+            .set_scheduled_leader(10, other_id);
+        for _ in 0..2 {
+            let result =
+                WriteStage::find_leader_rotation_index(&leader_schedule, 10, 5, input.clone());
+            assert_eq!(result, (input.clone(), false));
+        }
+
+        // The next snapshot picks the update up and truncates at the boundary
+        let leader_schedule = blockthread.read().unwrap().leader_schedule(0, u64::max_value());
+        let result = WriteStage::find_leader_rotation_index(&leader_schedule, 10, 5, input.clone());
+        assert_eq!(result, (input[..5].to_vec(), true));
+    }
+
+    #[test]
+    fn test_vote_cadence() {
+        let mut vote_cadence = VoteCadence::new(10);
+        let mut votes = vec![];
+
+        // Several passes per entry height, as when the writer keeps timing out
+        for entry_height in 0..100 {
+            for _ in 0..3 {
+                if vote_cadence.is_due(entry_height) {
+                    vote_cadence.voted(entry_height);
+                    votes.push(entry_height);
+                }
+            }
+        }
+        assert_eq!(votes, (0..10).map(|i| i * 10).collect::<Vec<u64>>());
+
+        // A pass that wrote nothing never votes twice at the same height
+        let mut vote_cadence = VoteCadence::new(WRITE_STAGE_VOTE_INTERVAL_ENTRIES);
+        assert!(vote_cadence.is_due(5));
+        vote_cadence.voted(5);
+        assert!(!vote_cadence.is_due(5));
+        assert!(vote_cadence.is_due(6));
+    }
+
+    #[test]
+    fn test_leader_lookahead_matches_uncached() {
+        let leader_keypair = Keypair::new();
+        let my_id = leader_keypair.pubkey();
+        let leader_info = Node::new_localhost_with_pubkey(my_id);
+        let leader_rotation_interval = 10;
+        let num_epochs = 6;
+
+        // Lead every other epoch
+        let mut blockthread = BlockThread::new(leader_info.info).expect("BlockThread::new");
+        blockthread.set_leader_rotation_interval(leader_rotation_interval);
+        for i in 0..num_epochs {
+            let leader = if i % 2 == 0 {
+                my_id
+            } else {
+                Keypair::new().pubkey()
+            };
+            blockthread.set_scheduled_leader(i * leader_rotation_interval, leader);
+        }
+        let blockthread = Arc::new(RwLock::new(blockthread));
+
+        let mut leader_lookahead = LeaderLookahead::new(leader_rotation_interval);
+        for entry_height in 0..num_epochs * leader_rotation_interval {
+            // The writer checks the same height again whenever it wrote nothing
+            for _ in 0..2 {
+                assert_eq!(
+                    leader_lookahead
+                        .is_leader_rotation(&blockthread, entry_height)
+                        .unwrap(),
+                    WriteStage::is_leader_rotation(
+                        &blockthread,
+                        leader_rotation_interval,
+                        entry_height
+                    ).unwrap(),
+                    "entry_height {}",
+                    entry_height
+                );
+            }
+        }
+
+        // Without an interval nothing rotates
+        let mut leader_lookahead = LeaderLookahead::new(0);
+        assert!(!leader_lookahead.is_leader_rotation(&blockthread, 10).unwrap());
     }
 }